@@ -0,0 +1,101 @@
+//! Runs a command in a terminal and prints every frame to stdout
+//!
+//! `cargo run --example stdout --no-default-features -- 'ls -l'`
+
+use async_trait::async_trait;
+use discord_termview::terminal::{self, Window};
+use std::time::Duration;
+use tokio::process;
+use tokio::sync::oneshot;
+
+struct Printer {
+    // told once the command has exited, so the terminal isn't removed while it's still running
+    done: Option<oneshot::Sender<()>>,
+}
+
+#[async_trait]
+impl terminal::Handler for Printer {
+    async fn update(&mut self, window: &mut Window) {
+        println!("----");
+        for line in window.visible() {
+            println!("{}", line.shown(window.timestamps));
+        }
+    }
+
+    async fn on_command_start(&mut self, _window: &mut Window, job: &terminal::Job) {
+        println!("---- running {}", job.line);
+    }
+
+    async fn on_command_exit(&mut self, window: &mut Window, exit: terminal::Exit) {
+        self.update(window).await;
+        println!("---- command {}", exit);
+        if let Some(done) = self.done.take() {
+            done.send(()).ok();
+        }
+    }
+
+    async fn on_terminal_exit(&mut self, _window: &mut Window) {
+        println!("---- terminal closed");
+    }
+
+    async fn on_clear(&mut self, window: &mut Window) {
+        self.update(window).await;
+    }
+
+    async fn on_heartbeat(&mut self, _window: &mut Window, _beats: usize) {
+        println!("---- still running");
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let line = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| String::from("ls"));
+
+    let config = terminal::Config {
+        height: 20,
+        timeout: None,
+        cooldown: Duration::from_secs(1),
+        max_cooldown: Duration::from_secs(5),
+        scrollback: 20,
+        idle_timeout: None,
+        heartbeat: Some(Duration::from_secs(5)),
+        dedup: false,
+        width: None,
+        tui: false,
+        timestamps: false,
+        hex: false,
+        encoding: encoding_rs::UTF_8,
+        limits: terminal::ResourceLimits::default(),
+    };
+
+    let (done, finished) = oneshot::channel();
+    let (runner, sender) = terminal::Runner::init(Printer { done: Some(done) }, config);
+    let listener = tokio::spawn(runner.listen());
+
+    let mut exec = process::Command::new("sh");
+    exec.arg("-c").arg(&line);
+
+    sender
+        .send(terminal::Command::Run(Box::new(terminal::Job {
+            line,
+            exec,
+            requested_by: None,
+            stdin: None,
+        })))
+        .await
+        .expect("terminal exited early");
+
+    // removing the terminal kills whatever it's running, so the command is waited on first
+    finished.await.ok();
+
+    let (ack, exited) = oneshot::channel();
+    sender
+        .send(terminal::Command::Remove(ack))
+        .await
+        .expect("terminal exited early");
+
+    exited.await.ok();
+    listener.await.ok();
+}
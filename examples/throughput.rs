@@ -0,0 +1,112 @@
+//! Measures how much time a terminal spends reading a flood of output
+//!
+//! `cargo run --release --example throughput --no-default-features -- 20000`
+//!
+//! Floods are throttled, so the wall time mostly shows the throttle. The CPU time is what it cost
+//! to read the lines.
+
+use async_trait::async_trait;
+use discord_termview::terminal::{self, Exit, Window};
+use std::time::{Duration, Instant};
+use tokio::process;
+use tokio::sync::oneshot;
+
+struct Counter {
+    lines: usize,
+    done: Option<oneshot::Sender<usize>>,
+}
+
+#[async_trait]
+impl terminal::Handler for Counter {
+    async fn update(&mut self, _window: &mut Window) {}
+
+    async fn on_line(&mut self, _stream: terminal::Stream, _line: &str) {
+        self.lines += 1;
+    }
+
+    async fn on_command_exit(&mut self, _window: &mut Window, _exit: Exit) {
+        if let Some(done) = self.done.take() {
+            done.send(self.lines).ok();
+        }
+    }
+
+    async fn on_terminal_exit(&mut self, _window: &mut Window) {}
+}
+
+/// The user and system time the process has spent so far
+fn cpu_time() -> Duration {
+    // SAFETY: getrusage only writes to the struct it's given
+    let usage = unsafe {
+        let mut usage = std::mem::zeroed::<libc::rusage>();
+        libc::getrusage(libc::RUSAGE_SELF, &mut usage);
+        usage
+    };
+
+    let time = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    time(usage.ru_utime) + time(usage.ru_stime)
+}
+
+#[tokio::main]
+async fn main() {
+    let count: usize = std::env::args()
+        .nth(1)
+        .map(|count| {
+            count
+                .parse()
+                .expect("the line count is expected to be a number")
+        })
+        .unwrap_or(20_000);
+
+    let config = terminal::Config {
+        height: 20,
+        timeout: None,
+        cooldown: Duration::from_secs(1),
+        max_cooldown: Duration::from_secs(5),
+        scrollback: 20,
+        idle_timeout: None,
+        heartbeat: None,
+        dedup: false,
+        width: None,
+        tui: false,
+        timestamps: false,
+        hex: false,
+        encoding: encoding_rs::UTF_8,
+        limits: terminal::ResourceLimits::default(),
+    };
+
+    let (done, finished) = oneshot::channel();
+    let counter = Counter {
+        lines: 0,
+        done: Some(done),
+    };
+    let (runner, sender) = terminal::Runner::init(counter, config);
+    tokio::spawn(runner.listen());
+
+    let line = format!("seq {}", count);
+    let mut exec = process::Command::new("sh");
+    exec.arg("-c").arg(&line);
+
+    let (started, cpu_before) = (Instant::now(), cpu_time());
+    sender
+        .send(terminal::Command::Run(Box::new(terminal::Job {
+            line,
+            exec,
+            requested_by: None,
+            stdin: None,
+        })))
+        .await
+        .expect("terminal exited early");
+
+    let lines = finished.await.expect("terminal exited early");
+    let (wall, cpu) = (started.elapsed(), cpu_time() - cpu_before);
+
+    println!(
+        "read {} lines in {:.2}s, using {:.3}s of CPU time ({:.1}µs per line)",
+        lines,
+        wall.as_secs_f64(),
+        cpu.as_secs_f64(),
+        cpu.as_secs_f64() * 1e6 / lines.max(1) as f64
+    );
+}
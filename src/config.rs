@@ -0,0 +1,75 @@
+//! Reading settings from environment variables, collecting every variable that's misconfigured
+//! so that they can all be fixed at once.
+
+/// A variable the settings couldn't be read from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Misconfigured {
+    Invalid {
+        var: &'static str,
+        expected: &'static str,
+    },
+    /// Neither `ALLOWED_ROLES` nor `ALLOWED_USERS` lists anyone
+    NoAllowedUsers,
+    /// A default in `CHANNEL_DEFAULTS` that `new` would refuse if it were given to it
+    ChannelDefault { channel: u64, reason: String },
+}
+
+impl std::fmt::Display for Misconfigured {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Misconfigured::Invalid { var, expected } => {
+                write!(f, "{} is expected to be {}", var, expected)
+            }
+            Misconfigured::NoAllowedUsers => f.write_str(
+                "missing semi-colon seperated ALLOWED_ROLES or ALLOWED_USERS variable containing role or user ID's",
+            ),
+            Misconfigured::ChannelDefault { channel, reason } => write!(
+                f,
+                "CHANNEL_DEFAULTS of channel {} can't be used: {}",
+                channel, reason
+            ),
+        }
+    }
+}
+
+/// Every variable the settings couldn't be read from, so that they can be fixed all at once
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigError(pub Vec<Misconfigured>);
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for (i, misconfigured) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str("\n")?;
+            }
+            misconfigured.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Reads variables from the environment, noting the ones that are set to something invalid
+#[derive(Default)]
+pub(crate) struct Env {
+    pub(crate) misconfigured: Vec<Misconfigured>,
+}
+
+impl Env {
+    /// `None` if `var` isn't set, or if it couldn't be parsed in which case it's noted
+    pub(crate) fn read<T>(
+        &mut self,
+        var: &'static str,
+        expected: &'static str,
+        parse: impl FnOnce(&str) -> Option<T>,
+    ) -> Option<T> {
+        let value = std::env::var(var).ok()?;
+        let parsed = parse(&value);
+        if parsed.is_none() {
+            self.misconfigured
+                .push(Misconfigured::Invalid { var, expected });
+        }
+        parsed
+    }
+}
@@ -1,19 +1,98 @@
-use super::{parser, session, terminal};
+use super::config::Env;
+use super::{logfile, metrics, parser, persist, session, terminal};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
 use serenity::{
     async_trait,
-    model::{channel::Message, gateway::Ready, id::ChannelId, id::MessageId, id::RoleId},
+    http::AttachmentType,
+    model::{
+        channel::{Attachment, Message, Reaction, ReactionType},
+        gateway::Ready,
+        id::{ChannelId, GuildId, MessageId, RoleId, UserId},
+        interactions::{
+            ApplicationCommandInteractionDataOption, ApplicationCommandOptionType, Interaction,
+            InteractionResponseType, InteractionType,
+        },
+        user::User,
+    },
     prelude::*,
 };
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::process;
 use tokio::sync::mpsc as channel;
-use tokio::sync::Mutex;
+use tokio::sync::{oneshot, watch, Mutex};
+
+pub use super::config::{ConfigError, Misconfigured};
 
 pub type Packet = ((ChannelId, MessageId), session::Event);
 type TermID = String;
 
+/// Only ever locked long enough to look up or change an entry and never across an `.await`, so
+/// that a terminal that's slow to take its commands doesn't hold up the others
+type Ttys = Arc<std::sync::Mutex<HashMap<TermID, Tty>>>;
+type Messages = Arc<std::sync::Mutex<HashMap<MessageId, TermID>>>;
+
 const FRAME_BUFFERING: usize = 5;
 const DISCORD_LENGTH_LIMIT: usize = 2000;
+// what `render_terminal_layout` adds around the contents, besides the language
+const FENCE_LENGTH: usize = "```\n```".len();
+const MAX_PAGES: usize = 5;
+// messages being rendered at once, each of them waits on its own rate limit
+const MAX_CONCURRENT_RENDERS: usize = 4;
+const DEFAULT_SHELL: &str = "bash";
+const DEFAULT_PREFIX: &str = "$";
+/// Bytes, of the attachments that are given to a command as its input
+const DEFAULT_MAX_INPUT_SIZE: u64 = 1024 * 1024;
+const ATTACHMENT_NAME: &str = "output.txt";
+// lets a shell run more than the allowed command, such as through pipes or substitutions
+const SHELL_SYNTAX: &[char] = &[';', '&', '|', '<', '>', '$', '`', '(', ')', '\\', '\n'];
+// longer names would crowd the messages they're shown in
+const MAX_NAME_LENGTH: usize = 32;
+// characters of each command line listed by `ps`, the arguments of some commands go on for pages
+const MAX_PROCESS_COMMAND: usize = 100;
+// slots a terminal may have at once, each of them runs commands of its own
+const MAX_SLOTS: usize = 8;
+const EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+const PERSIST_INTERVAL: Duration = Duration::from_secs(30);
+
+// serenity doesn't hand us the `retry_after` of a rate limited request, so we wait this long instead
+const RATE_LIMIT_BACKOFF: Duration = Duration::from_secs(5);
+// the JSON error code Discord responds with when a message doesn't exist, such as once deleted
+const UNKNOWN_MESSAGE: isize = 10008;
+
+const SLASH_COMMAND: &str = "term";
+
+// reports on the bot itself rather than a terminal, written without a terminal name
+const STATUS_COMMAND: &str = "status";
+// followed by what the owner of the bot wants done, see `parser::Admin`
+const ADMIN_COMMAND: &str = "admin";
+
+// the subcommands of the slash command, with their argument following the terminal name
+const SLASH_SUBCOMMANDS: &[(&str, &str, Option<(&str, &str, bool)>)] = &[
+    (
+        "new",
+        "Create a terminal",
+        Some(("options", "Options such as `height=20 private`", false)),
+    ),
+    (
+        "run",
+        "Run a command in a terminal",
+        Some(("command", "The shell command to run", true)),
+    ),
+    ("remove", "Remove a terminal", None),
+];
+
+// what users with a viewer role may do by default, nothing that changes a terminal or reveals its
+// environment
+const DEFAULT_VIEWER_COMMANDS: &[&str] = &["list", "queue", "history", "scroll"];
+
+// reacting to a terminal with these cancel its command or remove it
+const CANCEL_REACTION: &str = "🛑";
+const REMOVE_REACTION: &str = "🗑";
 
 /// The main router for information.
 ///
@@ -24,308 +103,4065 @@ pub struct Handler {
     frame_sender: channel::Sender<Packet>,
     frame_reciever: Mutex<Option<channel::Receiver<Packet>>>,
 
-    settings: Settings,
-    ttys: Mutex<HashMap<TermID, channel::Sender<terminal::Command>>>,
+    // swapped out as a whole when the settings are reloaded or changed by the owner
+    settings: std::sync::RwLock<Arc<Settings>>,
+    // what the owner has changed, which is applied over the settings again when they're reloaded
+    overrides: std::sync::Mutex<Overrides>,
+    ttys: Ttys,
+    messages: Messages,
+    started: Instant,
 }
 
-pub struct Settings {
-    pub allowed_roles: Vec<RoleId>,
-    pub prefix: u8,
+/// The settings the owner changed through `admin` commands since the bot started
+#[derive(Default)]
+struct Overrides {
+    prefix: Option<String>,
+    guild_prefixes: HashMap<GuildId, String>,
+    cooldown: Option<Duration>,
 }
 
-impl Settings {
-    pub fn new(allowed_roles: Vec<serenity::model::id::RoleId>, seperator: u8) -> Self {
-        Self {
-            allowed_roles,
-            prefix: seperator,
+impl Overrides {
+    fn apply(&self, settings: &mut Settings) {
+        if let Some(prefix) = &self.prefix {
+            settings.prefix = prefix.clone();
         }
-    }
 
-    pub fn parse() -> Self {
-        let seperator = std::env::var("SEPERATOR")
-            .map(|s| s.as_bytes()[0])
-            .unwrap_or(b'$');
-
-        let allowed_roles = std::env::var("ALLOWED_ROLES")
-            .expect("missing semi-colon ALLOWED_ROLES variable containing channel ID's")
-            .split(';')
-            .map(|word| word.parse().map(RoleId))
-            .collect::<Result<Vec<RoleId>, _>>()
-            .expect("ALLOWED_ROLES is expected to be a semi-colon seperated list of role ID's in numeric format");
-
-        Settings {
-            allowed_roles,
-            prefix: seperator,
+        for (guild, prefix) in &self.guild_prefixes {
+            settings.guild_prefixes.insert(*guild, prefix.clone());
+        }
+
+        if let Some(cooldown) = self.cooldown {
+            settings.cooldown = cooldown;
+            settings.max_cooldown = settings.max_cooldown.max(cooldown);
         }
     }
 }
 
-enum Error {
-    Parser(parser::Error),
-    NoTerminal(TermID),
-    CannotRespond,
+/// Closes every terminal when the bot is stopped, so that no commands are left running without it
+pub struct Shutdown {
+    ttys: Ttys,
+    persist_path: Option<PathBuf>,
 }
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Error::Parser(err) => err.fmt(f),
-            Error::NoTerminal(term) => write!(f, "terminal `{}` not found", term),
-            Error::CannotRespond => f.write_str("cannot respond to message. Missing permissions?"),
+/// Read access to the output of every open terminal, without being able to change them
+pub struct Snapshots {
+    ttys: Ttys,
+}
+
+/// What the `Handler` keeps track of for each open terminal
+#[derive(Clone)]
+struct Tty {
+    sender: channel::Sender<terminal::Command>,
+    // commands running alongside the terminal's own, addressed as `term.slot`
+    slots: HashMap<String, channel::Sender<terminal::Command>>,
+    // where the sessions send their frames
+    target: Arc<watch::Sender<(ChannelId, MessageId)>>,
+    target_reciever: watch::Receiver<(ChannelId, MessageId)>,
+    channel: ChannelId,
+    message: MessageId,
+    // the user who created the terminal, it counts towards their limits
+    owner: UserId,
+    config: terminal::Config,
+    layout: session::Layout,
+    shell: String,
+    dir: Option<PathBuf>,
+    env: HashMap<String, String>,
+    // where the output is logged to, if anywhere
+    log: Option<PathBuf>,
+    stderr: terminal::StderrMode,
+    // the visible output of the terminal's own session
+    snapshot: Arc<std::sync::Mutex<String>>,
+}
+
+impl Tty {
+    /// The terminal's own runner followed by the runners of its slots
+    fn senders(&self) -> impl Iterator<Item = &channel::Sender<terminal::Command>> {
+        std::iter::once(&self.sender).chain(self.slots.values())
+    }
+}
+
+/// What a user may do with the terminals
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    None,
+    /// Only the commands in `Settings::viewer_commands`
+    View,
+    Full,
+}
+
+/// Where a command came from, and so where its replies should go
+struct Origin {
+    channel: ChannelId,
+    guild: Option<GuildId>,
+    author: User,
+    // empty outside of guilds
+    roles: Vec<RoleId>,
+    // slash commands don't have a message to reply to
+    message: Option<MessageId>,
+    // the input of the command that's run
+    attachments: Vec<Attachment>,
+}
+
+impl From<&Message> for Origin {
+    fn from(msg: &Message) -> Self {
+        Origin {
+            channel: msg.channel_id,
+            guild: msg.guild_id,
+            author: msg.author.clone(),
+            roles: msg
+                .member
+                .as_ref()
+                .map(|member| member.roles.clone())
+                .unwrap_or_default(),
+            message: Some(msg.id),
+            attachments: msg.attachments.clone(),
         }
     }
 }
 
-impl Handler {
-    pub fn new(settings: Settings) -> Self {
-        let (frame_sender, frame_reciever) = channel::channel(FRAME_BUFFERING);
+impl Origin {
+    async fn reply(&self, ctx: &Context, content: String) -> serenity::Result<Message> {
+        self.channel
+            .send_message(ctx, |m| {
+                m.content(content);
+                if let Some(message) = self.message {
+                    m.reference_message((self.channel, message));
+                }
+                m
+            })
+            .await
+    }
+}
+
+#[derive(Clone)]
+pub struct Settings {
+    /// The only user who may use the `admin` commands, and who always has full access
+    pub owner: Option<UserId>,
+    pub allowed_roles: Vec<RoleId>,
+    pub allowed_users: Vec<UserId>,
+    /// Roles that may only use the `viewer_commands`
+    pub viewer_roles: Vec<RoleId>,
+    pub viewer_commands: Vec<String>,
+    pub prefix: String,
+    /// Used in place of `prefix` for the messages sent in the guild
+    pub guild_prefixes: HashMap<GuildId, String>,
+    pub shell: String,
+    /// Program and arguments every command is run through, such as `nice` or a sandbox
+    pub command_prefix: Vec<String>,
+    /// The only commands that may be run, each entry being the words a command has to start with
+    pub command_allowlist: Option<Vec<String>>,
+    /// Show the command line each command would be run as instead of running it
+    pub dry_run: bool,
+    /// Bytes the attachments given to a command as its input may add up to
+    pub max_input_size: u64,
+    pub resource_limits: terminal::ResourceLimits,
+    pub cooldown: Duration,
+    pub max_cooldown: Duration,
+    pub idle_timeout: Option<Duration>,
+    pub heartbeat: Option<Duration>,
+    /// Shown once a terminal is ready for another command, unless it was given one of its own
+    pub prompt: String,
+    pub persist_path: Option<PathBuf>,
+    /// ID and token of the webhook a transcript of every finished command is posted to
+    pub mirror_webhook: Option<(u64, String)>,
+    /// The limits of users without any of the roles in `role_limits`
+    pub limits: Limits,
+    pub role_limits: HashMap<RoleId, Limits>,
+    /// The most terminals that may be open at once across all users, `None` meaning there's no limit
+    pub terminal_limit: Option<usize>,
+    /// Used in place of the global defaults for the terminals created in the channel
+    pub channel_defaults: HashMap<ChannelId, ChannelDefaults>,
+}
+
+/// Defaults of the `new` options for terminals created in a channel, `None` keeping the global one
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ChannelDefaults {
+    pub height: Option<usize>,
+    pub shell: Option<String>,
+    /// Seconds
+    pub cooldown: Option<u64>,
+    /// Seconds
+    pub max_cooldown: Option<u64>,
+}
+
+/// How much of the bot a user may use, `None` meaning there's no limit
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Limits {
+    pub max_terminals: Option<usize>,
+    pub max_height: Option<usize>,
+}
+
+impl Limits {
+    /// The larger of both limits, as a user with several roles gets the most generous of them
+    fn most_generous(self, other: Limits) -> Limits {
+        fn larger(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+            Some(a?.max(b?))
+        }
+
+        Limits {
+            max_terminals: larger(self.max_terminals, other.max_terminals),
+            max_height: larger(self.max_height, other.max_height),
+        }
+    }
+}
 
+impl Settings {
+    pub fn new(allowed_roles: Vec<serenity::model::id::RoleId>, prefix: String) -> Self {
         Self {
-            frame_sender,
-            frame_reciever: Mutex::new(Some(frame_reciever)),
-            settings,
-            ttys: Mutex::new(HashMap::new()),
+            owner: None,
+            allowed_roles,
+            allowed_users: Vec::new(),
+            viewer_roles: Vec::new(),
+            viewer_commands: default_viewer_commands(),
+            prefix,
+            guild_prefixes: HashMap::new(),
+            shell: DEFAULT_SHELL.to_string(),
+            command_prefix: Vec::new(),
+            command_allowlist: None,
+            dry_run: false,
+            max_input_size: DEFAULT_MAX_INPUT_SIZE,
+            resource_limits: terminal::ResourceLimits::default(),
+            cooldown: terminal::DEFAULT_COOLDOWN,
+            max_cooldown: terminal::DEFAULT_MAX_COOLDOWN,
+            idle_timeout: None,
+            heartbeat: Some(terminal::DEFAULT_HEARTBEAT),
+            prompt: session::DEFAULT_PROMPT.to_string(),
+            persist_path: None,
+            mirror_webhook: None,
+            limits: Limits::default(),
+            role_limits: HashMap::new(),
+            terminal_limit: None,
+            channel_defaults: HashMap::new(),
         }
     }
 
-    async fn is_authorized(&self, _ctx: &Context, msg: &Message) -> bool {
-        for role in &self.settings.allowed_roles {
-            if msg.member.as_ref().unwrap().roles.contains(role) {
-                return true;
-            }
+    /// Settings to build on, starting from the same defaults as `new`
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder {
+            settings: Settings::new(Vec::new(), DEFAULT_PREFIX.to_string()),
         }
+    }
 
-        false
+    /// Read the settings from the environment, panicking if any variable is misconfigured
+    pub fn parse() -> Self {
+        Self::try_from_env().unwrap_or_else(|err| panic!("{}", err))
     }
 
-    async fn parse_and_apply_command(
-        &self,
-        ctx: &Context,
-        msg: &Message,
-        term: TermID,
-        cmd: &str,
-    ) -> Result<(), Error> {
-        let action = parser::parse(cmd).map_err(Error::Parser)?;
-        dbg!(&action);
+    /// Read the settings from the environment, reporting every misconfigured variable
+    pub fn try_from_env() -> Result<Self, ConfigError> {
+        let mut env = Env::default();
 
-        match action {
-            parser::Command::New { height, private } => {
-                self.create_terminal(ctx, msg, term, height, private).await
-            }
-            parser::Command::Remove => self.remove_terminal(ctx, msg, term).await,
-            parser::Command::Run(cmd) => self.run_command_in_terminal(term, cmd).await,
+        let prefix = std::env::var("SEPERATOR")
+            .ok()
+            .filter(|prefix| !prefix.is_empty())
+            .unwrap_or_else(|| DEFAULT_PREFIX.to_string());
+
+        let guild_prefixes = env
+            .read(
+                "GUILD_PREFIXES",
+                "a semi-colon seperated list of guild-id:prefix",
+                parse_guild_prefixes,
+            )
+            .unwrap_or_default();
+
+        let owner = env.read("OWNER_ID", "a user ID in numeric format", |id| {
+            id.parse().ok().map(UserId)
+        });
+
+        let allowed_roles = env
+            .read(
+                "ALLOWED_ROLES",
+                "a semi-colon seperated list of role ID's in numeric format",
+                |roles| parse_id_list(roles).ok(),
+            )
+            .unwrap_or_default();
+
+        let allowed_users = env
+            .read(
+                "ALLOWED_USERS",
+                "a semi-colon seperated list of user ID's in numeric format",
+                |users| parse_id_list(users).ok(),
+            )
+            .unwrap_or_default();
+
+        let viewer_roles = env
+            .read(
+                "VIEWER_ROLES",
+                "a semi-colon seperated list of role ID's in numeric format",
+                |roles| parse_id_list(roles).ok(),
+            )
+            .unwrap_or_default();
+
+        let viewer_commands = std::env::var("VIEWER_COMMANDS")
+            .map(|commands| {
+                commands
+                    .split(';')
+                    .filter(|command| !command.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_else(|_| default_viewer_commands());
+
+        if allowed_roles.is_empty() && allowed_users.is_empty() {
+            env.misconfigured.push(Misconfigured::NoAllowedUsers);
         }
-    }
 
-    async fn create_terminal(
-        &self,
-        ctx: &Context,
-        msg: &Message,
-        term: TermID,
-        height: usize,
-        private: bool,
-    ) -> Result<(), Error> {
-        let tty = self.ttys.lock().await.get(&term).cloned();
-        match tty {
-            Some(sender) => {
-                // send exit signal; then create new
-                sender.send(terminal::Command::Remove).await.unwrap();
+        let shell = std::env::var("SHELL_BINARY").unwrap_or_else(|_| DEFAULT_SHELL.to_string());
 
-                tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        let command_prefix = std::env::var("COMMAND_PREFIX")
+            .map(|prefix| prefix.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default();
 
-                self.spawn_new_terminal(ctx, msg, term, height, private)
-                    .await
-            }
-            None => {
-                self.spawn_new_terminal(ctx, msg, term, height, private)
-                    .await
-            }
+        let command_allowlist = std::env::var("COMMAND_ALLOWLIST").ok().map(|list| {
+            list.split(';')
+                .filter(|entry| !entry.trim().is_empty())
+                .map(str::to_string)
+                .collect()
+        });
+
+        let dry_run = env
+            .read("DRY_RUN", "1, 0, true or false", |dry_run| match dry_run {
+                "1" | "true" => Some(true),
+                "0" | "false" => Some(false),
+                _ => None,
+            })
+            .unwrap_or(false);
+
+        let max_input_size = env
+            .read("MAX_INPUT_SIZE", "a number of kilobytes", |kilobytes| {
+                kilobytes
+                    .parse()
+                    .ok()
+                    .and_then(|kilobytes: u64| kilobytes.checked_mul(1024))
+            })
+            .unwrap_or(DEFAULT_MAX_INPUT_SIZE);
+
+        let resource_limits = terminal::ResourceLimits {
+            cpu: env.read("LIMIT_CPU", "a number of seconds", |secs| secs.parse().ok()),
+            memory: env.read("LIMIT_MEMORY", "a number of megabytes", |megabytes| {
+                megabytes
+                    .parse()
+                    .ok()
+                    .and_then(|megabytes: u64| megabytes.checked_mul(1024 * 1024))
+            }),
+            processes: env.read("LIMIT_PROCESSES", "a number of processes", |max| {
+                max.parse().ok()
+            }),
+        };
+
+        let cooldown = env
+            .read("FRAME_COOLDOWN", "a number of seconds", |secs| {
+                secs.parse().ok().map(Duration::from_secs)
+            })
+            .unwrap_or(terminal::DEFAULT_COOLDOWN);
+
+        let max_cooldown = env
+            .read("FRAME_COOLDOWN_MAX", "a number of seconds", |secs| {
+                secs.parse().ok().map(Duration::from_secs)
+            })
+            .unwrap_or(terminal::DEFAULT_MAX_COOLDOWN);
+
+        let idle_timeout = env.read("IDLE_TIMEOUT", "a number of minutes", |mins| {
+            mins.parse()
+                .ok()
+                .and_then(|mins: u64| mins.checked_mul(60))
+                .map(Duration::from_secs)
+        });
+
+        // zero turns the heartbeat off
+        let heartbeat = match env.read("HEARTBEAT", "a number of seconds", |secs| {
+            secs.parse().ok().map(Duration::from_secs)
+        }) {
+            Some(interval) => Some(interval).filter(|interval| !interval.is_zero()),
+            None => Some(terminal::DEFAULT_HEARTBEAT),
+        };
+
+        let prompt =
+            std::env::var("PROMPT").unwrap_or_else(|_| session::DEFAULT_PROMPT.to_string());
+
+        let persist_path = std::env::var_os("PERSIST_PATH").map(PathBuf::from);
+
+        let mirror_webhook = env.read(
+            "MIRROR_WEBHOOK",
+            "the URL of a Discord webhook",
+            parse_webhook_url,
+        );
+
+        let limits = Limits {
+            max_terminals: env.read("MAX_TERMINALS", "a number of terminals", |max| {
+                max.parse().ok()
+            }),
+            max_height: env.read("MAX_HEIGHT", "a number of lines", |max| max.parse().ok()),
+        };
+
+        let role_limits = env
+            .read(
+                "ROLE_LIMITS",
+                "a semi-colon seperated list of role-id:max-terminals:max-height",
+                |list| parse_role_limits(list).ok(),
+            )
+            .unwrap_or_default();
+
+        let terminal_limit = env.read("TERMINAL_LIMIT", "a number of terminals", |max| {
+            max.parse().ok()
+        });
+
+        let channel_defaults = match std::env::var_os("CHANNEL_DEFAULTS") {
+            Some(path) => load_channel_defaults(Path::new(&path)).unwrap_or_else(|invalid| {
+                env.misconfigured.extend(invalid);
+                HashMap::new()
+            }),
+            None => HashMap::new(),
+        };
+
+        if !env.misconfigured.is_empty() {
+            return Err(ConfigError(env.misconfigured));
         }
+
+        Ok(Settings {
+            owner,
+            allowed_roles,
+            allowed_users,
+            viewer_roles,
+            viewer_commands,
+            prefix,
+            guild_prefixes,
+            shell,
+            command_prefix,
+            command_allowlist,
+            dry_run,
+            max_input_size,
+            resource_limits,
+            cooldown,
+            max_cooldown,
+            idle_timeout,
+            heartbeat,
+            prompt,
+            persist_path,
+            mirror_webhook,
+            limits,
+            role_limits,
+            terminal_limit,
+            channel_defaults,
+        })
     }
 
-    async fn remove_terminal(
-        &self,
-        _ctx: &Context,
-        _msg: &Message,
-        term: TermID,
-    ) -> Result<(), Error> {
-        let tty = self.ttys.lock().await.get(&term).cloned();
-        tty.ok_or_else(|| Error::NoTerminal(term.clone()))?
-            .send(terminal::Command::Remove)
-            .await
-            .ok();
+    /// What the messages sent in `guild` have to start with to be commands
+    fn prefix_in(&self, guild: Option<GuildId>) -> &str {
+        guild
+            .and_then(|guild| self.guild_prefixes.get(&guild))
+            .unwrap_or(&self.prefix)
+    }
 
-        self.ttys.lock().await.remove(&term);
+    /// The limits of a user with `roles`
+    fn limits_of(&self, roles: &[RoleId]) -> Limits {
+        roles
+            .iter()
+            .filter_map(|role| self.role_limits.get(role).copied())
+            .reduce(Limits::most_generous)
+            .unwrap_or(self.limits)
+    }
+}
 
-        Ok(())
+/// Builds `Settings` field by field, for running the handler without reading the environment
+#[derive(Clone)]
+pub struct SettingsBuilder {
+    settings: Settings,
+}
+
+impl SettingsBuilder {
+    pub fn owner(mut self, owner: UserId) -> Self {
+        self.settings.owner = Some(owner);
+        self
     }
 
-    async fn spawn_new_terminal(
-        &self,
-        ctx: &Context,
-        msg: &Message,
-        term: TermID,
-        height: usize,
-        _private: bool,
-    ) -> Result<(), Error> {
-        let reply = msg
-            .reply(ctx, render_terminal_layout(" >>> "))
-            .await
-            .map_err(|_| Error::CannotRespond)?;
+    pub fn allowed_roles(mut self, roles: Vec<RoleId>) -> Self {
+        self.settings.allowed_roles = roles;
+        self
+    }
 
-        let ttysession =
-            session::TTYSession::new((msg.channel_id, reply.id), self.frame_sender.clone());
+    pub fn allowed_users(mut self, users: Vec<UserId>) -> Self {
+        self.settings.allowed_users = users;
+        self
+    }
 
-        let (runner, command_sender) = terminal::Runner::init(ttysession, height);
+    pub fn viewer_roles(mut self, roles: Vec<RoleId>) -> Self {
+        self.settings.viewer_roles = roles;
+        self
+    }
 
-        if let Some(_existing) = self.ttys.lock().await.insert(term.clone(), command_sender) {
-            eprintln!(
-                "WARNING: tty `{}` refused to die in time, this might create a zombie process",
-                term
-            )
-        }
+    pub fn viewer_commands(mut self, commands: Vec<String>) -> Self {
+        self.settings.viewer_commands = commands;
+        self
+    }
 
-        tokio::spawn(async move { runner.listen().await });
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.settings.prefix = prefix.into();
+        self
+    }
 
-        Ok(())
+    pub fn guild_prefix(mut self, guild: GuildId, prefix: impl Into<String>) -> Self {
+        self.settings.guild_prefixes.insert(guild, prefix.into());
+        self
     }
 
-    async fn run_command_in_terminal(&self, term: TermID, mut cmd: String) -> Result<(), Error> {
-        println!("applying `{}` onto {}", cmd, term);
+    pub fn shell(mut self, shell: impl Into<String>) -> Self {
+        self.settings.shell = shell.into();
+        self
+    }
 
-        let sender = self
-            .ttys
-            .lock()
-            .await
-            .get(&term)
-            .cloned()
-            .ok_or(Error::NoTerminal(term))?;
+    pub fn command_prefix(mut self, prefix: Vec<String>) -> Self {
+        self.settings.command_prefix = prefix;
+        self
+    }
 
-        // TODO: Fix this
-        // temporary hack to include stderr in discord terminals
-        cmd.push_str(" 2>&1");
+    pub fn command_allowlist(mut self, allowlist: Vec<String>) -> Self {
+        self.settings.command_allowlist = Some(allowlist);
+        self
+    }
 
-        let mut shell = process::Command::new("bash");
-        shell.arg("-c").arg(&cmd);
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.settings.dry_run = dry_run;
+        self
+    }
 
-        println!("handing the command to the terminal instance");
-        sender.send(terminal::Command::Run(shell)).await.unwrap();
+    pub fn max_input_size(mut self, bytes: u64) -> Self {
+        self.settings.max_input_size = bytes;
+        self
+    }
 
-        Ok(())
+    pub fn resource_limits(mut self, limits: terminal::ResourceLimits) -> Self {
+        self.settings.resource_limits = limits;
+        self
     }
 
-    async fn respond_with_error(&self, ctx: &Context, error: Error, channel: ChannelId) {
-        eprintln!("user error: {}", error);
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.settings.cooldown = cooldown;
+        self
+    }
 
-        if let Err(e) = channel
-            .send_message(ctx, |m| {
-                m.content(format!("error: {}", error));
-                m
-            })
-            .await
-        {
-            eprintln!("failed to present error in channel: {}", e)
-        }
+    pub fn max_cooldown(mut self, max_cooldown: Duration) -> Self {
+        self.settings.max_cooldown = max_cooldown;
+        self
     }
-}
 
-// our entry-point for the serenity API
-#[async_trait]
-impl EventHandler for Handler {
-    async fn message(&self, ctx: Context, msg: Message) {
-        if msg.content.as_bytes().first() == Some(&self.settings.prefix)
-            && self.is_authorized(&ctx, &msg).await
-        {
-            println!("parsing {}", &msg.content);
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.settings.idle_timeout = Some(timeout);
+        self
+    }
 
-            let tty_identifier = {
-                let pos = msg.content.as_bytes()[1..]
-                    .iter()
-                    .position(|&b| b == b' ')
-                    .unwrap_or(msg.content.len() - 1);
+    /// `None` turns the heartbeat off
+    pub fn heartbeat(mut self, interval: Option<Duration>) -> Self {
+        self.settings.heartbeat = interval;
+        self
+    }
 
-                msg.content[1..=pos].to_string()
-            };
+    pub fn prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.settings.prompt = prompt.into();
+        self
+    }
 
-            let cmd_portion = msg.content[tty_identifier.len() + 2..].trim();
+    pub fn persist_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.settings.persist_path = Some(path.into());
+        self
+    }
 
-            if let Err(e) = self
-                .parse_and_apply_command(&ctx, &msg, tty_identifier, cmd_portion)
-                .await
-            {
-                self.respond_with_error(&ctx, e, msg.channel_id).await;
-            }
-        }
+    pub fn mirror_webhook(mut self, id: u64, token: impl Into<String>) -> Self {
+        self.settings.mirror_webhook = Some((id, token.into()));
+        self
     }
 
-    async fn ready(&self, ctx: Context, ready: Ready) {
-        println!("connected to discord as {}", ready.user.name);
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.settings.limits = limits;
+        self
+    }
 
-        let mut renderer = Renderer {
-            frame_reciever: self
-                .frame_reciever
-                .lock()
-                .await
-                .take()
-                .expect("no reciever channel"),
-        };
+    pub fn role_limits(mut self, limits: HashMap<RoleId, Limits>) -> Self {
+        self.settings.role_limits = limits;
+        self
+    }
 
-        tokio::spawn(async move { renderer.render_pipeline(ctx).await });
+    pub fn terminal_limit(mut self, max: usize) -> Self {
+        self.settings.terminal_limit = Some(max);
+        self
+    }
+
+    pub fn channel_defaults(mut self, defaults: HashMap<ChannelId, ChannelDefaults>) -> Self {
+        self.settings.channel_defaults = defaults;
+        self
+    }
+
+    pub fn build(self) -> Settings {
+        self.settings
     }
 }
 
-fn render_terminal_layout<C: std::fmt::Display>(contents: C) -> String {
-    format!("```\n{}```", contents)
+fn default_viewer_commands() -> Vec<String> {
+    DEFAULT_VIEWER_COMMANDS
+        .iter()
+        .map(|command| command.to_string())
+        .collect()
 }
 
-/// The renderer recieves frames as channel signals and renders them through the serenity API
-struct Renderer {
-    frame_reciever: channel::Receiver<Packet>,
+/// Get the ID and token out of a webhook URL such as `https://discord.com/api/webhooks/<id>/<token>`
+fn parse_webhook_url(url: &str) -> Option<(u64, String)> {
+    let mut segments = url.trim_end_matches('/').rsplit('/');
+    let token = segments.next().filter(|token| !token.is_empty())?;
+    let id = segments.next()?.parse().ok()?;
+
+    match segments.next() {
+        Some("webhooks") => Some((id, token.to_string())),
+        _ => None,
+    }
 }
 
-impl Renderer {
-    /// Start the render loop
-    async fn render_pipeline(&mut self, ctx: Context) {
-        loop {
-            let ((channelid, messageid), event) = self.frame_reciever.recv().await.unwrap();
+/// Read the defaults of each channel from a JSON object keyed by channel ID, such as
+/// `{"<id-of-channel>": {"height": 40, "shell": "zsh"}}`
+fn load_channel_defaults(
+    path: &Path,
+) -> Result<HashMap<ChannelId, ChannelDefaults>, Vec<Misconfigured>> {
+    let unreadable = || {
+        vec![Misconfigured::Invalid {
+            var: "CHANNEL_DEFAULTS",
+            expected: "the path of a JSON object of channel ID's to defaults",
+        }]
+    };
+    let bytes = std::fs::read(path).map_err(|_| unreadable())?;
+    let defaults: HashMap<u64, ChannelDefaults> =
+        serde_json::from_slice(&bytes).map_err(|_| unreadable())?;
 
-            match event {
-                session::Event::Ready => {
-                    println!("terminal {} finished it's command", messageid);
-                }
-                session::Event::Update(frame) => {
-                    if let Err(e) = self.refresh(&ctx, channelid, messageid, frame).await {
-                        eprintln!("frame update error: {}", e);
-                    };
-                }
-            }
-        }
+    // they're applied after `new` has checked its options, so they're checked here instead
+    let mut channels = defaults.keys().copied().collect::<Vec<_>>();
+    channels.sort_unstable();
+    let invalid = channels
+        .into_iter()
+        .filter_map(|channel| {
+            let height = defaults[&channel].height?;
+            let reason = parser::check_height(height).err()?;
+            Some(Misconfigured::ChannelDefault {
+                channel,
+                reason: reason.to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+    if !invalid.is_empty() {
+        return Err(invalid);
     }
 
-    /// Render a frame to a discord message
-    async fn refresh(
-        &self,
-        ctx: &Context,
-        channelid: ChannelId,
-        messageid: MessageId,
-        mut frame: String,
-    ) -> Result<Message, serenity::Error> {
-        while frame.len() > (DISCORD_LENGTH_LIMIT - 10) {
-            // `- 10` because formatting hasn't been applied
+    Ok(defaults
+        .into_iter()
+        .map(|(channel, defaults)| (ChannelId(channel), defaults))
+        .collect())
+}
 
-            println!(
-                "shrinking message since discord message length limit is exceded even with height limitation"
-            );
+/// Parse a semi-colon seperated list of `role:max_terminals:max_height`, where a limit may be left
+/// empty to not limit it
+fn parse_role_limits(list: &str) -> Result<HashMap<RoleId, Limits>, std::num::ParseIntError> {
+    let limit = |word: Option<&str>| match word {
+        None | Some("") => Ok(None),
+        Some(word) => word.parse().map(Some),
+    };
 
-            let line_end = frame.find(|c| c == '\n').unwrap();
-            frame.replace_range(0..=line_end, "");
+    list.split(';')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let mut words = entry.split(':');
+            let role = RoleId(words.next().unwrap_or("").parse()?);
+            let limits = Limits {
+                max_terminals: limit(words.next())?,
+                max_height: limit(words.next())?,
+            };
+            Ok((role, limits))
+        })
+        .collect()
+}
+
+/// Parse a semi-colon seperated list of `guild:prefix`, where the prefix may not be empty
+fn parse_guild_prefixes(list: &str) -> Option<HashMap<GuildId, String>> {
+    list.split(';')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (guild, prefix) = entry.split_once(':')?;
+            if prefix.is_empty() {
+                return None;
+            }
+            Some((GuildId(guild.parse().ok()?), prefix.to_string()))
+        })
+        .collect()
+}
+
+/// Parse a semi-colon seperated list of numeric ID's
+fn parse_id_list<T: From<u64>>(list: &str) -> Result<Vec<T>, std::num::ParseIntError> {
+    list.split(';')
+        .filter(|word| !word.is_empty())
+        .map(|word| word.parse().map(T::from))
+        .collect()
+}
+
+/// The limit a user ran into, with how much they're allowed
+#[derive(Debug)]
+pub enum Quota {
+    Terminals(usize),
+    Height(usize),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Parser(parser::Error),
+    NoTerminal(TermID),
+    InvalidTerminalName,
+    /// A name that's taken by a command of the bot itself
+    ReservedTerminalName(&'static str),
+    ShellNotFound(String),
+    InvalidDirectory(String),
+    CannotOpenLog(String, std::io::Error),
+    /// The attachments add up to more than this many bytes
+    InputTooLarge(u64),
+    CannotDownload(String, serenity::Error),
+    TerminalExists(TermID),
+    TooManySlots(TermID, usize),
+    NoHistory(TermID),
+    TeeIntoItself,
+    /// A command that posts messages, given where nothing can be posted
+    NeedsDiscord(&'static str),
+    ViewOnly(&'static str),
+    CommandNotAllowed(String),
+    /// An option or command that would get around the allowlist
+    BypassesAllowlist(&'static str),
+    NotOwner,
+    InvalidSettings(ConfigError),
+    QuotaExceeded(Quota),
+    /// How many terminals are open, and how many may be
+    TooManyTerminals(usize, usize),
+    Input(terminal::InputError),
+    Io(std::io::Error),
+    CannotRespond,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Parser(err) => err.fmt(f),
+            Error::NoTerminal(term) => write!(f, "terminal `{}` not found", term),
+            Error::InvalidTerminalName => write!(
+                f,
+                "terminal names are at most {} letters, digits, '-', '_' or '.'",
+                MAX_NAME_LENGTH
+            ),
+            Error::ReservedTerminalName(name) => write!(
+                f,
+                "`{}` is a command of the bot, so no terminal can be named that",
+                name
+            ),
+            Error::ShellNotFound(shell) => write!(f, "shell `{}` is not installed", shell),
+            Error::InvalidDirectory(dir) => write!(f, "`{}` is not an existing directory", dir),
+            Error::CannotOpenLog(path, err) => write!(f, "cannot log to `{}`: {}", path, err),
+            Error::InputTooLarge(max) => write!(
+                f,
+                "the input of a command may be at most {} KiB of attachments",
+                max / 1024
+            ),
+            Error::CannotDownload(file, err) => write!(f, "cannot download `{}`: {}", file, err),
+            Error::TerminalExists(term) => write!(f, "terminal `{}` already exists", term),
+            Error::TooManySlots(term, max) => write!(
+                f,
+                "terminal `{}` already has {} slots, remove one first",
+                term, max
+            ),
+            Error::NoHistory(term) => write!(f, "nothing has been run in `{}` yet", term),
+            Error::TeeIntoItself => f.write_str("a terminal can't tee into itself"),
+            Error::NeedsDiscord(command) => write!(f, "`{}` can't be applied here", command),
+            Error::ViewOnly(command) => write!(f, "viewers may not use `{}`", command),
+            Error::CommandNotAllowed(what) => {
+                write!(
+                    f,
+                    "`{}` is not allowed, only the allowlisted commands are",
+                    what
+                )
+            }
+            Error::BypassesAllowlist(what) => write!(
+                f,
+                "`{}` may not be used while only the allowlisted commands are allowed",
+                what
+            ),
+            Error::NotOwner => write!(f, "only the owner of the bot may use `{}`", ADMIN_COMMAND),
+            Error::InvalidSettings(err) => write!(
+                f,
+                "the settings couldn't be read, the current ones are kept:\n{}",
+                err
+            ),
+            Error::QuotaExceeded(Quota::Terminals(max)) => {
+                write!(f, "you may not have more than {} terminals open", max)
+            }
+            Error::QuotaExceeded(Quota::Height(max)) => {
+                write!(f, "you may not create terminals higher than {} lines", max)
+            }
+            Error::TooManyTerminals(open, max) => write!(
+                f,
+                "there are {} terminals open, which is as many as the bot allows ({})",
+                open, max
+            ),
+            Error::Input(err) => err.fmt(f),
+            Error::Io(err) => err.fmt(f),
+            Error::CannotRespond => f.write_str("cannot respond to message. Missing permissions?"),
         }
+    }
+}
 
-        channelid
-            .edit_message(&ctx, messageid, |m| {
-                m.content(render_terminal_layout(frame));
-                m
-            })
-            .await
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Parser(err) => Some(err),
+            Error::Input(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::CannotOpenLog(_, err) => Some(err),
+            Error::CannotDownload(_, err) => Some(err),
+            Error::InvalidSettings(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<parser::Error> for Error {
+    fn from(err: parser::Error) -> Self {
+        Error::Parser(err)
+    }
+}
+
+impl From<terminal::InputError> for Error {
+    fn from(err: terminal::InputError) -> Self {
+        Error::Input(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl Handler {
+    pub fn new(settings: Settings) -> Self {
+        let (frame_sender, frame_reciever) = channel::channel(FRAME_BUFFERING);
+
+        Self {
+            frame_sender,
+            frame_reciever: Mutex::new(Some(frame_reciever)),
+            settings: std::sync::RwLock::new(Arc::new(settings)),
+            overrides: std::sync::Mutex::new(Overrides::default()),
+            ttys: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            messages: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            started: Instant::now(),
+        }
+    }
+
+    /// The current settings, which stay the same for as long as they're held
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// Replace the settings, only affecting what's done from now on
+    ///
+    /// What the owner has changed since the bot started is kept.
+    fn set_settings(&self, mut settings: Settings) {
+        self.overrides.lock().unwrap().apply(&mut settings);
+        *self.settings.write().unwrap() = Arc::new(settings);
+    }
+
+    fn is_owner(&self, user: UserId) -> bool {
+        self.settings().owner == Some(user)
+    }
+
+    pub fn snapshots(&self) -> Snapshots {
+        Snapshots {
+            ttys: self.ttys.clone(),
+        }
+    }
+
+    pub fn shutdown_handle(&self) -> Shutdown {
+        Shutdown {
+            ttys: self.ttys.clone(),
+            persist_path: self.settings().persist_path.clone(),
+        }
+    }
+
+    /// `roles` is missing outside of guilds, in which case only the user list applies
+    fn access_of(&self, user: UserId, roles: Option<&[RoleId]>) -> Access {
+        let settings = self.settings();
+
+        if settings.owner == Some(user) || settings.allowed_users.contains(&user) {
+            return Access::Full;
+        }
+
+        let roles = match roles {
+            Some(roles) => roles,
+            None => return Access::None,
+        };
+
+        for role in &settings.allowed_roles {
+            if roles.contains(role) {
+                return Access::Full;
+            }
+        }
+
+        for role in &settings.viewer_roles {
+            if roles.contains(role) {
+                return Access::View;
+            }
+        }
+
+        Access::None
+    }
+
+    /// Viewers may only use the commands they've been given
+    fn check_access(&self, access: Access, command: &'static str) -> Result<(), Error> {
+        if access != Access::Full && !self.settings().viewer_commands.iter().any(|c| c == command) {
+            return Err(Error::ViewOnly(command));
+        }
+
+        Ok(())
+    }
+
+    async fn parse_and_apply_command(
+        &self,
+        ctx: &Context,
+        origin: &Origin,
+        access: Access,
+        term: TermID,
+        cmd: &str,
+    ) -> Result<(), Error> {
+        let action = self.parse_command(access, &term, cmd)?;
+
+        match action {
+            parser::Command::New(options) => {
+                self.create_terminal(ctx, origin, term, *options).await
+            }
+            parser::Command::History => self.list_history(ctx, origin, term).await,
+            parser::Command::List => self.list_terminals(ctx, origin).await,
+            parser::Command::Queue => self.list_queue(ctx, origin, term).await,
+            parser::Command::Processes => self.list_processes(ctx, origin, term).await,
+            parser::Command::ListEnv => self.list_env(ctx, origin, term).await,
+            parser::Command::Move => self.move_terminal(ctx, origin, term).await,
+            action => self.apply_command(origin, term, action).await,
+        }
+    }
+
+    /// Parse `cmd` for `term`, refusing whatever `access` or the allowlist doesn't allow
+    fn parse_command(
+        &self,
+        access: Access,
+        term: &str,
+        cmd: &str,
+    ) -> Result<parser::Command, Error> {
+        validate_terminal_name(term)?;
+
+        let action = parser::parse(cmd)?;
+        self.check_access(access, action.name())?;
+
+        if let parser::Command::New(options) = &action {
+            if options.run.is_some() {
+                self.check_access(access, "run")?;
+            }
+        }
+
+        if let parser::Command::Every { .. } = &action {
+            self.check_access(access, "run")?;
+        }
+
+        if self.settings().command_allowlist.is_some() {
+            check_bypasses_allowlist(&action)?;
+        }
+
+        Ok(action)
+    }
+
+    /// Apply a command that only concerns the runners, which takes nothing from Discord
+    ///
+    /// The commands that post messages are applied by `parse_and_apply_command` instead, and are
+    /// refused here.
+    async fn apply_command(
+        &self,
+        origin: &Origin,
+        term: TermID,
+        action: parser::Command,
+    ) -> Result<(), Error> {
+        match action {
+            parser::Command::Remove => self.remove_terminal(term).await,
+            parser::Command::Run(cmd) => self.run_command_in_terminal(origin, term, cmd).await,
+            parser::Command::RunLast => self.run_last_command(origin, term).await,
+            parser::Command::Every { secs, command } => {
+                self.schedule_command(origin, term, secs, command).await
+            }
+            parser::Command::Stop => {
+                self.send_to_terminal(term, terminal::Command::Unschedule)
+                    .await
+            }
+            parser::Command::Input(text) => self.send_input_to_terminal(term, text).await,
+            parser::Command::Cancel => self.send_to_terminal(term, terminal::Command::Cancel).await,
+            parser::Command::CancelAll => self.cancel_all(term).await,
+            parser::Command::Clear => self.send_to_terminal(term, terminal::Command::Clear).await,
+            parser::Command::Cd(path) => self.change_directory(term, path).await,
+            parser::Command::Follow(path) => self.follow_file(term, path).await,
+            parser::Command::Env(key, value) => self.set_env(term, key, Some(value)).await,
+            parser::Command::UnsetEnv(key) => self.set_env(term, key, None).await,
+            parser::Command::Rename(name) => {
+                validate_terminal_name(&name)?;
+                self.rename_terminal(term, name).await
+            }
+            parser::Command::Tee(target) => self.tee_terminal(term, target).await,
+            parser::Command::Grep(filter) => {
+                let filter = filter.map(|pattern| pattern.0);
+                self.send_to_terminal(term, terminal::Command::Filter(filter))
+                    .await
+            }
+            parser::Command::Stderr(mode) => self.set_stderr(term, mode).await,
+            parser::Command::Pause => self.send_to_terminal(term, terminal::Command::Pause).await,
+            parser::Command::Resume => self.send_to_terminal(term, terminal::Command::Resume).await,
+            parser::Command::Scroll { up, lines } => self.scroll_terminal(term, up, lines).await,
+            parser::Command::Resize(height) => self.resize_terminal(origin, term, height).await,
+            other => Err(Error::NeedsDiscord(other.name())),
+        }
+    }
+
+    async fn create_terminal(
+        &self,
+        ctx: &Context,
+        origin: &Origin,
+        term: TermID,
+        mut options: parser::NewTerminal,
+    ) -> Result<(), Error> {
+        // options that weren't given fall back to the defaults of the channel
+        if let Some(defaults) = self.settings().channel_defaults.get(&origin.channel) {
+            options.height = options.height.or(defaults.height);
+            options.shell = options.shell.or_else(|| defaults.shell.clone());
+            options.cooldown = options.cooldown.or(defaults.cooldown);
+            options.max_cooldown = options.max_cooldown.or(defaults.max_cooldown);
+        }
+
+        // checked before an existing terminal is replaced, so that it isn't lost for nothing
+        self.check_limits(origin, &term, &options).await?;
+
+        let run = options.run.take();
+        if let (Some(allowlist), Some(cmd)) = (&self.settings().command_allowlist, &run) {
+            check_allowlist(allowlist, cmd)?;
+        }
+
+        let tty = self.ttys.lock().unwrap().get(&term).cloned();
+        let mut previous = None;
+        if let Some(tty) = tty {
+            // send exit signal and wait for the terminal and its slots to be gone; then create new
+            let mut exits = Vec::new();
+            for sender in tty.senders() {
+                // runners that are already gone have nothing to wait for
+                exits.extend(request_removal(sender).await);
+            }
+
+            let deadline = tokio::time::Instant::now() + EXIT_TIMEOUT;
+            let mut exited_in_time = true;
+            for exited in exits {
+                exited_in_time &= tokio::time::timeout_at(deadline, exited).await.is_ok();
+            }
+
+            if exited_in_time {
+                self.forget_terminal(&term).await;
+
+                // the new terminal takes over the message rather than leaving it behind, which is
+                // only up to date once the old runners are done rendering to it
+                if tty.channel == origin.channel && !options.private {
+                    previous = Some((tty.channel, tty.message));
+                }
+            }
+        }
+
+        self.spawn_new_terminal(ctx, origin, term.clone(), options, previous)
+            .await?;
+
+        // the terminal is registered by now, so the command is queued right behind its greeting
+        match run {
+            Some(cmd) => self.run_command_in_terminal(origin, term, cmd).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Refuse to create `term` if it'd take the author of `origin` past their limits
+    ///
+    /// `term` itself isn't counted, as it's replaced if it exists.
+    async fn check_limits(
+        &self,
+        origin: &Origin,
+        term: &str,
+        options: &parser::NewTerminal,
+    ) -> Result<(), Error> {
+        let limits = self.settings().limits_of(&origin.roles);
+
+        let height = options.height.unwrap_or(parser::DEFAULT_HEIGHT);
+        if let Some(max) = limits.max_height.filter(|&max| height > max) {
+            return Err(Error::QuotaExceeded(Quota::Height(max)));
+        }
+
+        if let Some(max) = limits.max_terminals {
+            let owned = self
+                .ttys
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(name, tty)| tty.owner == origin.author.id && name.as_str() != term)
+                .count();
+
+            if owned >= max {
+                return Err(Error::QuotaExceeded(Quota::Terminals(max)));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn remove_terminal(&self, term: TermID) -> Result<(), Error> {
+        let tty = self
+            .ttys
+            .lock()
+            .unwrap()
+            .get(&term)
+            .cloned()
+            .ok_or_else(|| Error::NoTerminal(term.clone()))?;
+
+        for sender in tty.senders() {
+            request_removal(sender).await;
+        }
+
+        self.forget_terminal(&term).await;
+        self.persist().await;
+
+        Ok(())
+    }
+
+    /// Stop tracking the terminal, without telling it to exit
+    async fn forget_terminal(&self, term: &TermID) {
+        if let Some(tty) = self.ttys.lock().unwrap().remove(term) {
+            self.messages.lock().unwrap().remove(&tty.message);
+        }
+    }
+
+    async fn spawn_new_terminal(
+        &self,
+        ctx: &Context,
+        origin: &Origin,
+        term: TermID,
+        options: parser::NewTerminal,
+        previous: Option<(ChannelId, MessageId)>,
+    ) -> Result<(), Error> {
+        let settings = self.settings();
+        let shell = options.shell.unwrap_or_else(|| settings.shell.clone());
+
+        if !binary_exists(&shell) {
+            return Err(Error::ShellNotFound(shell));
+        }
+
+        let dir = options
+            .dir
+            .map(|dir| resolve_directory(Path::new(&dir)))
+            .transpose()?;
+
+        // opened before the terminal is posted, so that a bad path doesn't leave a dead terminal
+        let log_path = options.log.map(|path| match &dir {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        });
+        let log = match &log_path {
+            Some(path) => {
+                let opened = logfile::LogFile::create(path.clone()).await;
+                Some(opened.map_err(|e| Error::CannotOpenLog(path.display().to_string(), e))?)
+            }
+            None => None,
+        };
+
+        let prompt = options.prompt.unwrap_or_else(|| settings.prompt.clone());
+
+        let layout = session::Layout {
+            raw: options.raw,
+            color: options.color,
+            // only `ansi` code blocks show colors, which rules out highlighting
+            lang: if options.color {
+                Some(String::from("ansi"))
+            } else {
+                options.lang
+            },
+            section: None,
+            overflow: options.overflow,
+            prompt: Some(prompt),
+            transcripts: settings.mirror_webhook.is_some(),
+        };
+
+        let greeting = render_terminal_layout(layout.lang.as_deref(), layout.prompt());
+
+        // checked again once the terminal is inserted, this is so that no message is posted for a
+        // terminal that can't be created
+        self.check_terminal_limit(&self.ttys.lock().unwrap(), &term)?;
+
+        // private terminals are sent to the author in a DM so that the output isn't visible to
+        // the rest of the channel
+        let (channel, message) = match previous {
+            Some(previous) => previous,
+            None if options.private => {
+                let reply = origin
+                    .author
+                    .direct_message(ctx, |m| m.content(greeting))
+                    .await
+                    .map_err(|_| Error::CannotRespond)?;
+                (reply.channel_id, reply.id)
+            }
+            None => {
+                let reply = origin
+                    .reply(ctx, greeting)
+                    .await
+                    .map_err(|_| Error::CannotRespond)?;
+                (reply.channel_id, reply.id)
+            }
+        };
+
+        let (target, target_reciever) = watch::channel((channel, message));
+
+        let ttysession = session::TTYSession::new(
+            target_reciever.clone(),
+            self.frame_sender.clone(),
+            layout.clone(),
+        );
+
+        let config = terminal::Config {
+            height: options.height.unwrap_or(parser::DEFAULT_HEIGHT),
+            scrollback: options.scrollback.unwrap_or(terminal::DEFAULT_SCROLLBACK),
+            timeout: options.timeout.map(Duration::from_secs),
+            cooldown: options
+                .cooldown
+                .map(Duration::from_secs)
+                .unwrap_or(settings.cooldown),
+            max_cooldown: options
+                .max_cooldown
+                .map(Duration::from_secs)
+                .unwrap_or(settings.max_cooldown),
+            idle_timeout: settings.idle_timeout,
+            heartbeat: settings.heartbeat,
+            dedup: options.dedup,
+            width: options.width,
+            tui: options.tui,
+            timestamps: options.timestamps,
+            hex: options.hex,
+            encoding: options.encoding.unwrap_or(encoding_rs::UTF_8),
+            limits: settings.resource_limits,
+        };
+
+        let snapshot = ttysession.snapshot();
+        let (mut runner, sender) = terminal::Runner::init(ttysession, config.clone());
+        if let Some(log) = log {
+            runner.add_handler(log);
+        }
+
+        let tty = Tty {
+            sender: sender.clone(),
+            slots: HashMap::new(),
+            target: Arc::new(target),
+            target_reciever,
+            channel,
+            message,
+            owner: origin.author.id,
+            config,
+            layout,
+            shell,
+            dir,
+            env: HashMap::new(),
+            log: log_path,
+            stderr: terminal::StderrMode::default(),
+            snapshot,
+        };
+
+        let existing = match self.register_terminal(&term, tty) {
+            Ok(existing) => existing,
+            Err(err) => {
+                // another terminal took the last slot while the greeting was being posted
+                if previous.is_none() {
+                    channel.delete_message(ctx, message).await.ok();
+                }
+                return Err(err);
+            }
+        };
+
+        if let Some(existing) = existing {
+            warn!(
+                "tty `{}` refused to die in time, this might create a zombie process",
+                term
+            );
+
+            self.messages.lock().unwrap().remove(&existing.message);
+        }
+
+        self.messages.lock().unwrap().insert(message, term.clone());
+
+        tokio::spawn(async move { runner.listen().await });
+
+        if previous.is_some() {
+            // a cleared window shows only the prompt, just like the greeting of a new message.
+            // Its frame goes through the renderer after the last ones of the old runners, so that
+            // those can't overwrite it
+            sender.send(terminal::Command::Clear).await.ok();
+        }
+
+        self.persist().await;
+
+        Ok(())
+    }
+
+    /// Track `tty` as `term`, unless that'd take the bot past its `terminal_limit`
+    ///
+    /// The terminals are counted under the same lock they're inserted under, so that terminals
+    /// created at the same time can't both take the last slot.
+    fn register_terminal(&self, term: &TermID, tty: Tty) -> Result<Option<Tty>, Error> {
+        let mut ttys = self.ttys.lock().unwrap();
+        self.check_terminal_limit(&ttys, term)?;
+        Ok(ttys.insert(term.clone(), tty))
+    }
+
+    /// Refuse to create `term` if there's no room for another terminal
+    ///
+    /// `term` itself isn't counted, as it's replaced if it exists.
+    fn check_terminal_limit(&self, ttys: &HashMap<TermID, Tty>, term: &str) -> Result<(), Error> {
+        if let Some(max) = self.settings().terminal_limit {
+            let open = ttys.keys().filter(|name| name.as_str() != term).count();
+            if open >= max {
+                return Err(Error::TooManyTerminals(open, max));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn run_command_in_terminal(
+        &self,
+        origin: &Origin,
+        term: TermID,
+        cmd: String,
+    ) -> Result<(), Error> {
+        debug!("applying `{}` onto {}", cmd, term);
+
+        if let Some(allowlist) = &self.settings().command_allowlist {
+            check_allowlist(allowlist, &cmd)?;
+        }
+
+        let (tty, sender) = self.slot_sender(&term).await?;
+        let stdin = self.download_input(origin).await?;
+        let exec = command_exec(
+            &self.settings(),
+            &tty.shell,
+            tty.dir.as_ref(),
+            &tty.env,
+            &cmd,
+        );
+
+        let job = terminal::Job {
+            line: cmd,
+            exec,
+            requested_by: Some(origin.author.tag()),
+            stdin,
+        };
+
+        sender
+            .send(terminal::Command::Run(Box::new(job)))
+            .await
+            .map_err(|_| Error::NoTerminal(term))?;
+
+        Ok(())
+    }
+
+    /// The attachments of the message, one after the other, which are the input of the command
+    /// it came with
+    async fn download_input(&self, origin: &Origin) -> Result<Option<Vec<u8>>, Error> {
+        if origin.attachments.is_empty() {
+            return Ok(None);
+        }
+
+        let max = self.settings().max_input_size;
+        let size = origin.attachments.iter().map(|file| file.size).sum::<u64>();
+        if size > max {
+            return Err(Error::InputTooLarge(max));
+        }
+
+        let mut input = Vec::new();
+        for file in &origin.attachments {
+            let bytes = file
+                .download()
+                .await
+                .map_err(|e| Error::CannotDownload(file.filename.clone(), e))?;
+            input.extend_from_slice(&bytes);
+
+            // the sizes are only what Discord claims them to be
+            if input.len() as u64 > max {
+                return Err(Error::InputTooLarge(max));
+            }
+        }
+
+        Ok(Some(input))
+    }
+
+    /// Run `cmd` every `secs` seconds from now on, as the terminal's directory and environment
+    /// are right now
+    async fn schedule_command(
+        &self,
+        origin: &Origin,
+        term: TermID,
+        secs: u64,
+        cmd: String,
+    ) -> Result<(), Error> {
+        debug!("scheduling `{}` onto {} every {}s", cmd, term, secs);
+
+        if let Some(allowlist) = &self.settings().command_allowlist {
+            check_allowlist(allowlist, &cmd)?;
+        }
+
+        let (tty, sender) = self.slot_sender(&term).await?;
+        // the job outlives the `Tty`, which holds on to the senders of the runner it's sent to
+        let (shell, dir, env) = (tty.shell, tty.dir, tty.env);
+        let settings = self.settings();
+        let requested_by = origin.author.tag();
+
+        let job = terminal::MakeJob::new(move || terminal::Job {
+            line: cmd.clone(),
+            exec: command_exec(&settings, &shell, dir.as_ref(), &env, &cmd),
+            requested_by: Some(requested_by.clone()),
+            stdin: None,
+        });
+
+        let interval = Duration::from_secs(secs);
+        sender
+            .send(terminal::Command::Schedule { interval, job })
+            .await
+            .map_err(|_| Error::NoTerminal(term))?;
+
+        Ok(())
+    }
+
+    /// Show the lines appended to the file at `path`, relative to the terminal's directory
+    async fn follow_file(&self, term: TermID, path: String) -> Result<(), Error> {
+        let (tty, sender) = self.slot_sender(&term).await?;
+
+        let path = match &tty.dir {
+            Some(dir) => dir.join(path),
+            None => PathBuf::from(path),
+        };
+
+        sender
+            .send(terminal::Command::Follow(path))
+            .await
+            .map_err(|_| Error::NoTerminal(term))?;
+
+        Ok(())
+    }
+
+    /// Mirror what `term` reads from now on to `target`, which keeps its own output too
+    ///
+    /// Mirrored lines aren't mirrored any further, and are dropped rather than waited on while
+    /// `target` is backed up, so that terminals teeing into each other can't wait on each other.
+    async fn tee_terminal(&self, term: TermID, target: Option<TermID>) -> Result<(), Error> {
+        let tee = match target {
+            Some(target) => {
+                validate_terminal_name(&target)?;
+                if target == term {
+                    return Err(Error::TeeIntoItself);
+                }
+
+                let (_, sender) = self.terminal_sender(&target).await?;
+                Some(sender)
+            }
+            None => None,
+        };
+
+        self.send_to_terminal(term, terminal::Command::Tee(tee))
+            .await
+    }
+
+    async fn run_last_command(&self, origin: &Origin, term: TermID) -> Result<(), Error> {
+        let cmd = self
+            .history(&term)
+            .await?
+            .pop()
+            .ok_or_else(|| Error::NoHistory(term.clone()))?;
+
+        self.run_command_in_terminal(origin, term, cmd).await
+    }
+
+    /// The latest commands started in the terminal, oldest first
+    async fn history(&self, term: &str) -> Result<Vec<String>, Error> {
+        let (_, sender) = self.terminal_sender(term).await?;
+
+        let (reply, response) = oneshot::channel();
+
+        sender
+            .send(terminal::Command::History(reply))
+            .await
+            .map_err(|_| Error::NoTerminal(term.to_string()))?;
+
+        response
+            .await
+            .map_err(|_| Error::NoTerminal(term.to_string()))
+    }
+
+    async fn list_history(
+        &self,
+        ctx: &Context,
+        origin: &Origin,
+        term: TermID,
+    ) -> Result<(), Error> {
+        let history = self.history(&term).await?;
+
+        let mut listing = String::new();
+        for (i, line) in history.iter().enumerate() {
+            listing.push_str(&format!("{}. {}\n", i + 1, line));
+        }
+
+        if listing.is_empty() {
+            listing.push_str("nothing has been run yet\n");
+        }
+
+        origin
+            .reply(ctx, render_terminal_layout(None, listing))
+            .await
+            .map_err(|_| Error::CannotRespond)?;
+
+        Ok(())
+    }
+
+    async fn list_terminals(&self, ctx: &Context, origin: &Origin) -> Result<(), Error> {
+        let mut ttys = self
+            .ttys
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(term, tty)| (term.clone(), tty.sender.clone()))
+            .collect::<Vec<_>>();
+
+        ttys.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut listing = String::new();
+        for (term, sender) in ttys {
+            let (reply, response) = oneshot::channel();
+
+            let state = match sender.send(terminal::Command::Status(reply)).await {
+                Ok(()) => response.await.ok(),
+                Err(_) => None,
+            };
+
+            let state = match state {
+                Some(status) if status.running && status.queued > 0 => {
+                    format!("running, {} queued", status.queued)
+                }
+                Some(status) if status.running => String::from("running"),
+                Some(_) => String::from("idle"),
+                None => String::from("closing"),
+            };
+
+            listing.push_str(&format!("{}: {}\n", term, state));
+        }
+
+        if listing.is_empty() {
+            listing.push_str("no open terminals\n");
+        }
+
+        origin
+            .reply(ctx, render_terminal_layout(None, listing))
+            .await
+            .map_err(|_| Error::CannotRespond)?;
+
+        Ok(())
+    }
+
+    /// Parse and apply an `admin` command, which only the owner may use
+    async fn parse_and_apply_admin_command(
+        &self,
+        ctx: &Context,
+        origin: &Origin,
+        cmd: &str,
+    ) -> Result<(), Error> {
+        if !self.is_owner(origin.author.id) {
+            return Err(Error::NotOwner);
+        }
+
+        let report = match parser::parse_admin(cmd)? {
+            parser::Admin::Reload => self.reload_settings()?,
+            parser::Admin::KillAll => self.remove_every_terminal().await,
+            parser::Admin::SetCooldown(secs) => self.set_cooldown(Duration::from_secs(secs)),
+            parser::Admin::SetPrefix(prefix) => self.set_prefix(origin.guild, prefix),
+        };
+
+        origin
+            .reply(ctx, report)
+            .await
+            .map_err(|_| Error::CannotRespond)?;
+
+        Ok(())
+    }
+
+    /// Read the settings from the environment again
+    ///
+    /// The persist path and mirror webhook are only read once, as they're in use from the start.
+    fn reload_settings(&self) -> Result<String, Error> {
+        let settings = Settings::try_from_env().map_err(|err| {
+            error!("failed to reload the settings: {}", err);
+            Error::InvalidSettings(err)
+        })?;
+
+        self.set_settings(settings);
+        info!("reloaded the settings");
+
+        Ok(String::from("reloaded the settings"))
+    }
+
+    async fn remove_every_terminal(&self) -> String {
+        let ttys = self.ttys.lock().unwrap().drain().collect::<Vec<_>>();
+
+        for (_, tty) in &ttys {
+            for sender in tty.senders() {
+                // cancelling first so that the queued commands aren't run before the terminal exits
+                if sender.send(terminal::Command::CancelAll).await.is_ok() {
+                    request_removal(sender).await;
+                }
+            }
+
+            self.messages.lock().unwrap().remove(&tty.message);
+        }
+
+        self.persist().await;
+        info!("removed all {} terminals", ttys.len());
+
+        format!("removed {} terminals", ttys.len())
+    }
+
+    /// Change the cooldown of the terminals created from now on
+    ///
+    /// Terminals never render faster than `MIN_COOLDOWN`, which shorter cooldowns are raised to.
+    fn set_cooldown(&self, cooldown: Duration) -> String {
+        let cooldown = cooldown.max(terminal::MIN_COOLDOWN);
+        self.overrides.lock().unwrap().cooldown = Some(cooldown);
+        self.set_settings(Settings::clone(&self.settings()));
+
+        format!(
+            "new terminals wait at least {}s between frames",
+            cooldown.as_secs()
+        )
+    }
+
+    /// Change the prefix of the commands sent in `guild`, or the default one outside of guilds
+    fn set_prefix(&self, guild: Option<GuildId>, prefix: String) -> String {
+        let report = match guild {
+            Some(_) => format!("commands in this server now start with `{}`", prefix),
+            None => format!(
+                "commands now start with `{}`, unless a server has its own prefix",
+                prefix
+            ),
+        };
+
+        {
+            let mut overrides = self.overrides.lock().unwrap();
+            match guild {
+                Some(guild) => overrides.guild_prefixes.insert(guild, prefix),
+                None => overrides.prefix.replace(prefix),
+            };
+        }
+        self.set_settings(Settings::clone(&self.settings()));
+
+        report
+    }
+
+    async fn report_status(&self, ctx: &Context, origin: &Origin) -> Result<(), Error> {
+        let ttys = self
+            .ttys
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let mut running = 0;
+        for tty in &ttys {
+            for sender in tty.senders() {
+                let (reply, response) = oneshot::channel();
+                if sender.send(terminal::Command::Status(reply)).await.is_err() {
+                    continue;
+                }
+
+                if matches!(response.await, Ok(status) if status.running) {
+                    running += 1;
+                    break;
+                }
+            }
+        }
+
+        let report = format!(
+            "up for {}, {} open terminals of which {} are running commands",
+            format_uptime(self.started.elapsed()),
+            ttys.len(),
+            running
+        );
+
+        origin
+            .reply(ctx, report)
+            .await
+            .map_err(|_| Error::CannotRespond)?;
+
+        Ok(())
+    }
+
+    async fn list_queue(&self, ctx: &Context, origin: &Origin, term: TermID) -> Result<(), Error> {
+        let (_, sender) = self.terminal_sender(&term).await?;
+
+        let (reply, response) = oneshot::channel();
+
+        sender
+            .send(terminal::Command::Queue(reply))
+            .await
+            .map_err(|_| Error::NoTerminal(term.clone()))?;
+
+        let queue = response.await.map_err(|_| Error::NoTerminal(term))?;
+
+        let mut listing = String::new();
+        for (i, line) in queue.iter().enumerate() {
+            listing.push_str(&format!("{}. {}\n", i + 1, line));
+        }
+
+        if listing.is_empty() {
+            listing.push_str("nothing queued\n");
+        }
+
+        origin
+            .reply(ctx, render_terminal_layout(None, listing))
+            .await
+            .map_err(|_| Error::CannotRespond)?;
+
+        Ok(())
+    }
+
+    async fn list_processes(
+        &self,
+        ctx: &Context,
+        origin: &Origin,
+        term: TermID,
+    ) -> Result<(), Error> {
+        let (_, sender) = self.terminal_sender(&term).await?;
+
+        let (reply, response) = oneshot::channel();
+
+        sender
+            .send(terminal::Command::Processes(reply))
+            .await
+            .map_err(|_| Error::NoTerminal(term.clone()))?;
+
+        let processes = response.await.map_err(|_| Error::NoTerminal(term))?;
+
+        let mut listing = String::new();
+        for process in processes {
+            let command = process.command.chars().take(MAX_PROCESS_COMMAND);
+            let line = format!(
+                "{}{} {}\n",
+                "  ".repeat(process.depth),
+                process.pid,
+                command.collect::<String>()
+            );
+
+            // whatever doesn't fit is left out rather than splitting the reply
+            if listing.len() + line.len() > page_limit(None) {
+                break;
+            }
+            listing.push_str(&line);
+        }
+
+        if listing.is_empty() {
+            listing.push_str("nothing running\n");
+        }
+
+        origin
+            .reply(ctx, render_terminal_layout(None, listing))
+            .await
+            .map_err(|_| Error::CannotRespond)?;
+
+        Ok(())
+    }
+
+    async fn rename_terminal(&self, term: TermID, name: TermID) -> Result<(), Error> {
+        {
+            let mut ttys = self.ttys.lock().unwrap();
+
+            if ttys.contains_key(&name) {
+                return Err(Error::TerminalExists(name));
+            }
+
+            let tty = ttys
+                .remove(&term)
+                .ok_or_else(|| Error::NoTerminal(term.clone()))?;
+
+            self.messages
+                .lock()
+                .unwrap()
+                .insert(tty.message, name.clone());
+            ttys.insert(name, tty);
+        }
+
+        self.persist().await;
+
+        Ok(())
+    }
+
+    /// Post the terminal in the channel of `origin` and remove it from where it was
+    async fn move_terminal(
+        &self,
+        ctx: &Context,
+        origin: &Origin,
+        term: TermID,
+    ) -> Result<(), Error> {
+        let tty = self
+            .ttys
+            .lock()
+            .unwrap()
+            .get(&term)
+            .cloned()
+            .ok_or_else(|| Error::NoTerminal(term.clone()))?;
+
+        let reply = origin
+            .reply(ctx, render_terminal_layout(None, tty.layout.prompt()))
+            .await
+            .map_err(|_| Error::CannotRespond)?;
+
+        tty.target.send((reply.channel_id, reply.id)).ok();
+
+        if let Some(tty) = self.ttys.lock().unwrap().get_mut(&term) {
+            tty.channel = reply.channel_id;
+            tty.message = reply.id;
+        }
+
+        {
+            let mut messages = self.messages.lock().unwrap();
+            messages.remove(&tty.message);
+            messages.insert(reply.id, term.clone());
+        }
+
+        // fill the new message with the current contents of the terminal
+        for sender in tty.senders() {
+            sender.send(terminal::Command::Redraw).await.ok();
+        }
+
+        if let Err(e) = tty.channel.delete_message(ctx, tty.message).await {
+            warn!("failed to delete the previous message of `{}`: {}", term, e);
+        }
+
+        self.persist().await;
+
+        Ok(())
+    }
+
+    async fn change_directory(&self, term: TermID, path: String) -> Result<(), Error> {
+        let mut ttys = self.ttys.lock().unwrap();
+        let tty = ttys
+            .get_mut(&term)
+            .ok_or_else(|| Error::NoTerminal(term.clone()))?;
+
+        let dir = match &tty.dir {
+            Some(current) => current.join(&path),
+            None => PathBuf::from(&path),
+        };
+
+        tty.dir = Some(resolve_directory(&dir)?);
+
+        Ok(())
+    }
+
+    /// Cancel the running command and drop the queue of the terminal and every one of its slots
+    ///
+    /// Each runner drops its queue before killing its command, so nothing queued gets to start.
+    async fn cancel_all(&self, term: TermID) -> Result<(), Error> {
+        let (tty, _) = self.terminal_sender(&term).await?;
+
+        for sender in tty.senders() {
+            // a slot that has exited by itself has nothing left to cancel
+            sender.send(terminal::Command::CancelAll).await.ok();
+        }
+
+        Ok(())
+    }
+
+    /// Scroll by `lines`, or by the height of the terminal if not given
+    async fn scroll_terminal(
+        &self,
+        term: TermID,
+        up: bool,
+        lines: Option<usize>,
+    ) -> Result<(), Error> {
+        let (tty, sender) = self.terminal_sender(&term).await?;
+
+        let lines = lines.unwrap_or(tty.config.height);
+        let cmd = if up {
+            terminal::Command::ScrollUp(lines)
+        } else {
+            terminal::Command::ScrollDown(lines)
+        };
+
+        sender.send(cmd).await.map_err(|_| Error::NoTerminal(term))
+    }
+
+    /// Change the height of the terminal, or of one of its slots, within the limits of the user
+    async fn resize_terminal(
+        &self,
+        origin: &Origin,
+        term: TermID,
+        height: usize,
+    ) -> Result<(), Error> {
+        let limits = self.settings().limits_of(&origin.roles);
+        if let Some(max) = limits.max_height.filter(|&max| height > max) {
+            return Err(Error::QuotaExceeded(Quota::Height(max)));
+        }
+
+        let (_, sender) = self.terminal_sender(&term).await?;
+
+        sender
+            .send(terminal::Command::Resize(height))
+            .await
+            .map_err(|_| Error::NoTerminal(term.clone()))?;
+
+        // slots started later on are given the same height
+        if let Some(tty) = self.ttys.lock().unwrap().get_mut(&term) {
+            tty.config.height = height;
+        }
+
+        self.persist().await;
+
+        Ok(())
+    }
+
+    /// Change how the terminal shows stderr, which is kept for when it's restored
+    async fn set_stderr(&self, term: TermID, mode: terminal::StderrMode) -> Result<(), Error> {
+        self.send_to_terminal(term.clone(), terminal::Command::Stderr(mode))
+            .await?;
+
+        // slots aren't restored, only the terminals themselves
+        if let Some(tty) = self.ttys.lock().unwrap().get_mut(&term) {
+            tty.stderr = mode;
+        }
+
+        self.persist().await;
+
+        Ok(())
+    }
+
+    /// Set or, when `value` is missing, unset an environment variable of the terminal
+    async fn set_env(&self, term: TermID, key: String, value: Option<String>) -> Result<(), Error> {
+        {
+            let mut ttys = self.ttys.lock().unwrap();
+            let tty = ttys
+                .get_mut(&term)
+                .ok_or_else(|| Error::NoTerminal(term.clone()))?;
+
+            match value {
+                Some(value) => tty.env.insert(key, value),
+                None => tty.env.remove(&key),
+            };
+        }
+
+        self.persist().await;
+
+        Ok(())
+    }
+
+    async fn list_env(&self, ctx: &Context, origin: &Origin, term: TermID) -> Result<(), Error> {
+        let mut env = self
+            .ttys
+            .lock()
+            .unwrap()
+            .get(&term)
+            .ok_or_else(|| Error::NoTerminal(term.clone()))?
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key, value))
+            .collect::<Vec<_>>();
+
+        env.sort();
+
+        let mut listing = env.concat();
+        if listing.is_empty() {
+            listing.push_str("no environment variables set\n");
+        }
+
+        origin
+            .reply(ctx, render_terminal_layout(None, listing))
+            .await
+            .map_err(|_| Error::CannotRespond)?;
+
+        Ok(())
+    }
+
+    /// Find the runner `term` refers to, which is either a terminal or one of its slots
+    ///
+    /// Slots are written as `term.slot`, only `slot_sender` starts the ones that don't exist yet.
+    async fn terminal_sender(
+        &self,
+        term: &str,
+    ) -> Result<(Tty, channel::Sender<terminal::Command>), Error> {
+        let ttys = self.ttys.lock().unwrap();
+
+        if let Some(tty) = ttys.get(term) {
+            return Ok((tty.clone(), tty.sender.clone()));
+        }
+
+        term.rsplit_once('.')
+            .and_then(|(base, slot)| {
+                let tty = ttys.get(base)?;
+                Some((tty.clone(), tty.slots.get(slot)?.clone()))
+            })
+            .ok_or_else(|| Error::NoTerminal(term.to_string()))
+    }
+
+    /// Find the runner `term` refers to like `terminal_sender`, starting the slot if it doesn't
+    /// exist yet
+    async fn slot_sender(
+        &self,
+        term: &str,
+    ) -> Result<(Tty, channel::Sender<terminal::Command>), Error> {
+        let mut ttys = self.ttys.lock().unwrap();
+
+        if let Some(tty) = ttys.get(term) {
+            return Ok((tty.clone(), tty.sender.clone()));
+        }
+
+        let (base, slot) = term
+            .rsplit_once('.')
+            .filter(|(base, slot)| !slot.is_empty() && ttys.contains_key(*base))
+            .ok_or_else(|| Error::NoTerminal(term.to_string()))?;
+
+        let tty = ttys.get_mut(base).unwrap();
+
+        if let Some(sender) = tty.slots.get(slot) {
+            return Ok((tty.clone(), sender.clone()));
+        }
+
+        if tty.slots.len() >= MAX_SLOTS {
+            return Err(Error::TooManySlots(base.to_string(), MAX_SLOTS));
+        }
+
+        let layout = session::Layout {
+            section: Some(slot.to_string()),
+            ..tty.layout.clone()
+        };
+
+        let ttysession = session::TTYSession::new(
+            tty.target_reciever.clone(),
+            self.frame_sender.clone(),
+            layout,
+        );
+
+        let (runner, sender) = terminal::Runner::init(ttysession, tty.config.clone());
+        tokio::spawn(async move { runner.listen().await });
+
+        tty.slots.insert(slot.to_string(), sender.clone());
+        Ok((tty.clone(), sender))
+    }
+
+    /// Hand a command to the terminal without waiting for it to be handled
+    async fn send_to_terminal(&self, term: TermID, cmd: terminal::Command) -> Result<(), Error> {
+        let (_, sender) = self.terminal_sender(&term).await?;
+
+        sender.send(cmd).await.map_err(|_| Error::NoTerminal(term))
+    }
+
+    async fn send_input_to_terminal(&self, term: TermID, text: String) -> Result<(), Error> {
+        let (_, sender) = self.terminal_sender(&term).await?;
+
+        let (reply, response) = oneshot::channel();
+
+        sender
+            .send(terminal::Command::Input(text, reply))
+            .await
+            .map_err(|_| Error::NoTerminal(term.clone()))?;
+
+        response
+            .await
+            .map_err(|_| Error::NoTerminal(term))?
+            .map_err(Error::from)
+    }
+
+    /// Save the terminals to disk, if persistence is enabled
+    async fn persist(&self) {
+        if let Some(path) = &self.settings().persist_path {
+            save_terminals(&self.ttys, path).await;
+        }
+    }
+
+    /// Reattach to the terminals saved from a previous run
+    async fn restore_terminals(&self, path: &Path) -> Result<(), Error> {
+        let saved = persist::load(path)?;
+        let settings = self.settings();
+
+        for saved in saved {
+            let channel = ChannelId(saved.channel);
+            let message = MessageId(saved.message);
+
+            let (target, target_reciever) = watch::channel((channel, message));
+
+            let options = saved.options;
+
+            let layout = session::Layout {
+                raw: options.raw,
+                color: options.color,
+                lang: options.lang,
+                section: None,
+                overflow: options.overflow,
+                prompt: Some(options.prompt.unwrap_or_else(|| settings.prompt.clone())),
+                transcripts: settings.mirror_webhook.is_some(),
+            };
+
+            let ttysession = session::TTYSession::new(
+                target_reciever.clone(),
+                self.frame_sender.clone(),
+                layout.clone(),
+            );
+
+            let encoding = options
+                .encoding
+                .and_then(|name| encoding_rs::Encoding::for_label(name.as_bytes()))
+                .unwrap_or(encoding_rs::UTF_8);
+
+            let config = terminal::Config {
+                height: saved.height,
+                scrollback: saved.scrollback,
+                timeout: options.timeout,
+                cooldown: options.cooldown.unwrap_or(settings.cooldown),
+                max_cooldown: options.max_cooldown.unwrap_or(settings.max_cooldown),
+                idle_timeout: settings.idle_timeout,
+                heartbeat: settings.heartbeat,
+                dedup: options.dedup,
+                width: options.width,
+                tui: options.tui,
+                timestamps: options.timestamps,
+                hex: options.hex,
+                encoding,
+                limits: settings.resource_limits,
+            };
+
+            let snapshot = ttysession.snapshot();
+            let (mut runner, sender) = terminal::Runner::init(ttysession, config.clone());
+            runner.restore(saved.buffer);
+
+            // a log that can't be opened anymore shouldn't cost the terminal itself
+            let mut log = saved.log;
+            if let Some(path) = &log {
+                match logfile::LogFile::create(path.clone()).await {
+                    Ok(opened) => runner.add_handler(opened),
+                    Err(e) => {
+                        warn!(
+                            "no longer logging `{}` to {}: {}",
+                            saved.id,
+                            path.display(),
+                            e
+                        );
+                        log = None;
+                    }
+                }
+            }
+
+            if options.stderr != terminal::StderrMode::default() {
+                sender
+                    .send(terminal::Command::Stderr(options.stderr))
+                    .await
+                    .ok();
+            }
+
+            let tty = Tty {
+                sender,
+                slots: HashMap::new(),
+                target: Arc::new(target),
+                target_reciever,
+                channel,
+                message,
+                owner: UserId(saved.owner),
+                config,
+                layout,
+                shell: saved.shell,
+                dir: saved.dir,
+                env: saved.env,
+                log,
+                stderr: options.stderr,
+                snapshot,
+            };
+
+            info!("restored terminal `{}`", saved.id);
+
+            self.ttys.lock().unwrap().insert(saved.id.clone(), tty);
+            self.messages.lock().unwrap().insert(message, saved.id);
+
+            tokio::spawn(async move { runner.listen().await });
+        }
+
+        Ok(())
+    }
+
+    async fn respond_with_error(&self, ctx: &Context, error: Error, channel: ChannelId) {
+        debug!("user error: {}", error);
+
+        if let Err(e) = channel
+            .send_message(ctx, |m| {
+                m.content(format!("error: {}", error));
+                m
+            })
+            .await
+        {
+            warn!("failed to present error in channel: {}", e)
+        }
+    }
+}
+
+// our entry-point for the serenity API
+#[async_trait]
+impl EventHandler for Handler {
+    async fn message(&self, ctx: Context, msg: Message) {
+        let content = match msg
+            .content
+            .strip_prefix(self.settings().prefix_in(msg.guild_id))
+        {
+            Some(content) => content,
+            None => return,
+        };
+
+        let roles = msg.member.as_ref().map(|member| member.roles.as_slice());
+        let access = self.access_of(msg.author.id, roles);
+        if access == Access::None {
+            return;
+        }
+
+        debug!("parsing {}", &msg.content);
+
+        let (tty_identifier, cmd_portion) = split_terminal_command(content);
+        let origin = Origin::from(&msg);
+
+        let result = if tty_identifier == STATUS_COMMAND && cmd_portion.is_empty() {
+            match self.check_access(access, STATUS_COMMAND) {
+                Ok(()) => self.report_status(&ctx, &origin).await,
+                Err(e) => Err(e),
+            }
+        } else if tty_identifier == ADMIN_COMMAND {
+            self.parse_and_apply_admin_command(&ctx, &origin, cmd_portion)
+                .await
+        } else {
+            self.parse_and_apply_command(
+                &ctx,
+                &origin,
+                access,
+                tty_identifier.to_string(),
+                cmd_portion,
+            )
+            .await
+        };
+
+        if let Err(e) = result {
+            self.respond_with_error(&ctx, e, msg.channel_id).await;
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if interaction.kind != InteractionType::ApplicationCommand {
+            return;
+        }
+
+        let data = match &interaction.data {
+            Some(data) if data.name == SLASH_COMMAND => data,
+            _ => return,
+        };
+
+        let member = &interaction.member;
+        let access = self.access_of(member.user.id, Some(member.roles.as_slice()));
+        if access == Access::None {
+            return;
+        }
+
+        let (term, cmd) = match data.options.first().and_then(slash_command_to_text) {
+            Some(command) => command,
+            None => return,
+        };
+
+        // discord wants a response within a few seconds, so we acknowledge before doing anything
+        if let Err(e) = interaction
+            .create_interaction_response(&ctx.http, |r| {
+                r.kind(InteractionResponseType::ChannelMessageWithSource)
+                    .interaction_response_data(|d| d.content(format!("`{}` {}", term, cmd)))
+            })
+            .await
+        {
+            warn!("failed to respond to interaction: {}", e);
+        }
+
+        let origin = Origin {
+            channel: interaction.channel_id,
+            guild: Some(member.guild_id),
+            author: member.user.clone(),
+            roles: member.roles.clone(),
+            message: None,
+            attachments: Vec::new(),
+        };
+
+        if let Err(e) = self
+            .parse_and_apply_command(&ctx, &origin, access, term, &cmd)
+            .await
+        {
+            self.respond_with_error(&ctx, e, interaction.channel_id)
+                .await;
+        }
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        let user = match reaction.user_id {
+            Some(user) => user,
+            None => return,
+        };
+
+        let emoji = match &reaction.emoji {
+            ReactionType::Unicode(emoji) => emoji.trim_end_matches('\u{fe0f}'),
+            _ => return,
+        };
+
+        if emoji != CANCEL_REACTION && emoji != REMOVE_REACTION {
+            return;
+        }
+
+        let term = match self.messages.lock().unwrap().get(&reaction.message_id) {
+            Some(term) => term.clone(),
+            None => return,
+        };
+
+        let roles = match reaction.guild_id {
+            Some(guild) => guild
+                .member(&ctx, user)
+                .await
+                .ok()
+                .map(|member| member.roles),
+            None => None,
+        };
+
+        // cancelling and removing change the terminal, which viewers may not do
+        if self.access_of(user, roles.as_deref()) != Access::Full {
+            return;
+        }
+
+        let result = if emoji == CANCEL_REACTION {
+            self.send_to_terminal(term, terminal::Command::Cancel).await
+        } else {
+            self.remove_terminal(term).await
+        };
+
+        if let Err(e) = result {
+            self.respond_with_error(&ctx, e, reaction.channel_id).await;
+        }
+    }
+
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        info!("connected to discord as {}", ready.user.name);
+
+        // `ready` is sent again after reconnecting, but everything below should only happen once
+        let frame_reciever = match self.frame_reciever.lock().await.take() {
+            Some(frame_reciever) => frame_reciever,
+            None => return,
+        };
+
+        let mut renderer = Renderer::new(
+            frame_reciever,
+            self.ttys.clone(),
+            self.messages.clone(),
+            self.settings().mirror_webhook.clone(),
+        );
+
+        if let Err(e) = register_slash_command(&ctx, ready.application.id.0).await {
+            error!("failed to register the /{} command: {}", SLASH_COMMAND, e);
+        }
+
+        tokio::spawn(async move { renderer.render_pipeline(ctx).await });
+
+        if let Some(path) = &self.settings().persist_path {
+            if let Err(e) = self.restore_terminals(path).await {
+                error!("failed to load terminals from {}: {}", path.display(), e);
+            }
+
+            let ttys = self.ttys.clone();
+            let path = path.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(PERSIST_INTERVAL).await;
+                    save_terminals(&ttys, &path).await;
+                }
+            });
+        }
+    }
+}
+
+impl Snapshots {
+    /// The visible output of the terminal as of its latest frame
+    pub async fn get(&self, term: &str) -> Option<String> {
+        let ttys = self.ttys.lock().unwrap();
+        let snapshot = ttys.get(term)?.snapshot.lock().unwrap().clone();
+        Some(snapshot)
+    }
+}
+
+#[cfg(feature = "http")]
+#[async_trait]
+impl super::http::Terminals for Snapshots {
+    async fn snapshot(&self, term: &str) -> Option<String> {
+        self.get(term).await
+    }
+}
+
+impl Shutdown {
+    /// Kill the running commands of every terminal and wait a short while for them to exit
+    pub async fn run(self) {
+        // save before closing anything, otherwise the restored terminals would all be closed
+        if let Some(path) = &self.persist_path {
+            save_terminals(&self.ttys, path).await;
+        }
+
+        let ttys = self.ttys.lock().unwrap().drain().collect::<Vec<_>>();
+
+        let mut exits = Vec::with_capacity(ttys.len());
+        for (term, tty) in ttys {
+            for sender in tty.senders() {
+                // cancelling first so that the queued commands aren't run before the terminal exits
+                if sender.send(terminal::Command::CancelAll).await.is_err() {
+                    continue;
+                }
+
+                if let Some(exited) = request_removal(sender).await {
+                    exits.push((term.clone(), exited));
+                }
+            }
+        }
+
+        let deadline = tokio::time::Instant::now() + EXIT_TIMEOUT;
+        for (term, exited) in exits {
+            if tokio::time::timeout_at(deadline, exited).await.is_err() {
+                warn!("tty `{}` didn't exit before shutting down", term);
+            }
+        }
+    }
+}
+
+/// Tell a runner to exit once its queue is done, the returned reciever resolves once it's gone
+///
+/// Returns `None` if the runner had already exited.
+async fn request_removal(
+    sender: &channel::Sender<terminal::Command>,
+) -> Option<oneshot::Receiver<()>> {
+    let (ack, exited) = oneshot::channel();
+    sender.send(terminal::Command::Remove(ack)).await.ok()?;
+    Some(exited)
+}
+
+/// Write the current state of every terminal to `path`
+async fn save_terminals(ttys: &std::sync::Mutex<HashMap<TermID, Tty>>, path: &Path) {
+    let ttys = ttys
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(term, tty)| (term.clone(), tty.clone()))
+        .collect::<Vec<_>>();
+
+    let mut saved = Vec::with_capacity(ttys.len());
+
+    for (term, tty) in ttys {
+        let (reply, response) = oneshot::channel();
+
+        if tty
+            .sender
+            .send(terminal::Command::Snapshot(reply))
+            .await
+            .is_err()
+        {
+            continue;
+        }
+
+        let buffer = match response.await {
+            Ok(buffer) => buffer,
+            Err(_) => continue,
+        };
+
+        saved.push(persist::SavedTerminal {
+            id: term,
+            channel: tty.channel.0,
+            message: tty.message.0,
+            owner: tty.owner.0,
+            height: tty.config.height,
+            scrollback: tty.config.scrollback,
+            shell: tty.shell,
+            dir: tty.dir,
+            env: tty.env,
+            buffer,
+            log: tty.log,
+            options: persist::SavedOptions {
+                lang: tty.layout.lang,
+                raw: tty.layout.raw,
+                color: tty.layout.color,
+                overflow: tty.layout.overflow,
+                prompt: tty.layout.prompt,
+                width: tty.config.width,
+                tui: tty.config.tui,
+                dedup: tty.config.dedup,
+                timestamps: tty.config.timestamps,
+                hex: tty.config.hex,
+                encoding: Some(tty.config.encoding.name().to_string()),
+                cooldown: Some(tty.config.cooldown),
+                max_cooldown: Some(tty.config.max_cooldown),
+                timeout: tty.config.timeout,
+                stderr: tty.stderr,
+            },
+        });
+    }
+
+    if let Err(e) = persist::save(path, &saved) {
+        error!("failed to save terminals to {}: {}", path.display(), e);
+    }
+}
+
+/// Register `/term new`, `/term run` and `/term remove`
+async fn register_slash_command(ctx: &Context, application_id: u64) -> serenity::Result<()> {
+    Interaction::create_global_application_command(&ctx.http, application_id, |c| {
+        c.name(SLASH_COMMAND).description("Control a terminal");
+
+        for (sub, description, argument) in SLASH_SUBCOMMANDS {
+            c.create_interaction_option(|o| {
+                o.name(sub)
+                    .description(description)
+                    .kind(ApplicationCommandOptionType::SubCommand)
+                    .create_sub_option(|o| {
+                        o.name("name")
+                            .description("Name of the terminal")
+                            .kind(ApplicationCommandOptionType::String)
+                            .required(true)
+                    });
+
+                if let Some((name, description, required)) = argument {
+                    o.create_sub_option(|o| {
+                        o.name(name)
+                            .description(description)
+                            .kind(ApplicationCommandOptionType::String)
+                            .required(*required)
+                    });
+                }
+
+                o
+            });
+        }
+
+        c
+    })
+    .await
+    .map(|_| ())
+}
+
+/// Turn a `/term` subcommand into the terminal name and the command as it would've been typed
+fn slash_command_to_text(
+    sub: &ApplicationCommandInteractionDataOption,
+) -> Option<(TermID, String)> {
+    let argument = |name: &str| {
+        sub.options
+            .iter()
+            .find(|option| option.name == name)
+            .and_then(|option| option.value.as_ref())
+            .and_then(|value| value.as_str())
+            .map(str::to_string)
+    };
+
+    let term = argument("name")?;
+    let cmd = match sub.name.as_str() {
+        "new" => format!("new {}", argument("options").unwrap_or_default()),
+        "run" => format!("run {}", argument("command")?),
+        "remove" => String::from("remove"),
+        _ => return None,
+    };
+
+    Some((term, cmd.trim().to_string()))
+}
+
+/// Split the message content following the prefix into the terminal identifier and its command
+///
+/// The identifier ends at any whitespace, so that a code block may start on the next line.
+fn split_terminal_command(content: &str) -> (&str, &str) {
+    let content = content.trim_start();
+    let (term, cmd) = content
+        .split_once(char::is_whitespace)
+        .unwrap_or((content, ""));
+    (term, cmd.trim())
+}
+
+/// Only let through commands starting with the words of an entry of the allowlist, without any
+/// shell syntax that could run something else along with them
+fn check_allowlist(allowlist: &[String], cmd: &str) -> Result<(), Error> {
+    if let Some(syntax) = cmd.chars().find(|c| SHELL_SYNTAX.contains(c)) {
+        return Err(Error::CommandNotAllowed(syntax.to_string()));
+    }
+
+    let words = cmd.split_whitespace().collect::<Vec<_>>();
+    let allowed = allowlist.iter().any(|entry| {
+        let entry = entry.split_whitespace().collect::<Vec<_>>();
+        !entry.is_empty() && words.starts_with(&entry)
+    });
+
+    if !allowed {
+        let program = words.first().copied().unwrap_or_default();
+        return Err(Error::CommandNotAllowed(program.to_string()));
+    }
+
+    Ok(())
+}
+
+/// Refuse what would let commands outside of the allowlist run, or reach past it to files
+///
+/// The shell and the environment decide what an allowlisted command ends up running, while logs
+/// and followed files can be anywhere.
+fn check_bypasses_allowlist(action: &parser::Command) -> Result<(), Error> {
+    match action {
+        parser::Command::New(options) if options.shell.is_some() => {
+            Err(Error::BypassesAllowlist("shell="))
+        }
+        parser::Command::New(options) if options.log.is_some() => {
+            Err(Error::BypassesAllowlist("log="))
+        }
+        parser::Command::Follow(_) => Err(Error::BypassesAllowlist("follow")),
+        parser::Command::Env(..) => Err(Error::BypassesAllowlist("env")),
+        _ => Ok(()),
+    }
+}
+
+/// The process `cmd` is run as in a terminal with `shell`, `dir` and `env`
+fn command_exec(
+    settings: &Settings,
+    shell: &str,
+    dir: Option<&PathBuf>,
+    env: &HashMap<String, String>,
+    cmd: &str,
+) -> process::Command {
+    if settings.dry_run {
+        let line = describe_command(&settings.command_prefix, shell, cmd);
+        let mut echo = process::Command::new("echo");
+        echo.arg(format!("[dry-run] {}", line));
+        return echo;
+    }
+
+    let mut exec = match settings.command_prefix.split_first() {
+        Some((program, args)) => {
+            let mut prefixed = process::Command::new(program);
+            prefixed.args(args).arg(shell);
+            prefixed
+        }
+        None => process::Command::new(shell),
+    };
+    exec.arg("-c").arg(cmd).envs(env);
+
+    if let Some(dir) = dir {
+        exec.current_dir(dir);
+    }
+
+    exec
+}
+
+/// The command line `cmd` is run as, quoted so that it could be pasted into a shell
+fn describe_command(prefix: &[String], shell: &str, cmd: &str) -> String {
+    let quote = |word: &str| {
+        let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+
+        if !word.is_empty() && word.chars().all(plain) {
+            word.to_string()
+        } else {
+            format!("'{}'", word.replace('\'', r"'\''"))
+        }
+    };
+
+    prefix
+        .iter()
+        .map(String::as_str)
+        .chain([shell, "-c", cmd])
+        .map(quote)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Make sure the name, including the slot in `term.slot`, can be shown without breaking messages
+fn validate_terminal_name(name: &str) -> Result<(), Error> {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH || !name.chars().all(allowed) {
+        return Err(Error::InvalidTerminalName);
+    }
+
+    // they'd be taken for those commands, leaving the terminal out of reach
+    for &reserved in &[ADMIN_COMMAND, STATUS_COMMAND] {
+        if name == reserved {
+            return Err(Error::ReservedTerminalName(reserved));
+        }
+    }
+
+    Ok(())
+}
+
+/// Write out a duration as days, hours and minutes, leaving out the larger units that are zero
+fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        _ => format!("{}d {}h {}m", days, hours, minutes),
+    }
+}
+
+/// Resolve `path` to the absolute path of an existing directory
+fn resolve_directory(path: &Path) -> Result<PathBuf, Error> {
+    match std::fs::canonicalize(path) {
+        Ok(dir) if dir.is_dir() => Ok(dir),
+        _ => Err(Error::InvalidDirectory(path.display().to_string())),
+    }
+}
+
+/// Check whether `binary` is an executable path or can be found in `$PATH`
+fn binary_exists(binary: &str) -> bool {
+    if binary.contains('/') {
+        return Path::new(binary).is_file();
+    }
+
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(binary).is_file()))
+        .unwrap_or(false)
+}
+
+/// How long the contents of a message may be for it to fit once `render_terminal_layout` is applied
+fn page_limit(lang: Option<&str>) -> usize {
+    let overhead = FENCE_LENGTH + lang.map_or(0, str::len);
+
+    // a page has to fit something, even if a very long language means the message won't
+    DISCORD_LENGTH_LIMIT.saturating_sub(overhead).max(1)
+}
+
+fn render_terminal_layout<C: std::fmt::Display>(lang: Option<&str>, contents: C) -> String {
+    format!("```{}\n{}```", lang.unwrap_or(""), contents)
+}
+
+/// The renderer recieves frames as channel signals and renders them through the serenity API
+///
+/// Each message is rendered by a task of its own, so that a slow or rate limited message doesn't
+/// hold back the others. A message only has one task at a time, which keeps its frames in order.
+struct Renderer {
+    frame_reciever: channel::Receiver<Packet>,
+
+    // terminals that close by themselves are pruned from these
+    ttys: Ttys,
+    messages: Messages,
+
+    // the messages each frame is spread over besides the terminal's own, while it's not rendering
+    extras: HashMap<MessageId, Extras>,
+
+    // the latest frame of each section of a message, used when a terminal has slots
+    sections: HashMap<MessageId, BTreeMap<Option<String>, session::Frame>>,
+
+    // the newest frame of each message that hasn't been rendered yet
+    pending: HashMap<MessageId, (ChannelId, session::Frame)>,
+    // no frames are rendered until then, as we're being rate limited
+    blocked_until: Option<tokio::time::Instant>,
+
+    // messages with a task currently rendering them
+    rendering: HashSet<MessageId>,
+    // messages of terminals that are gone, which are forgotten once their last frame is shown
+    closing: HashSet<MessageId>,
+    // terminal messages that were deleted and posted again, for the frames that were sent before
+    // the session learned of the new message
+    replaced: HashMap<MessageId, MessageId>,
+    rendered: channel::Sender<Rendered>,
+    rendered_reciever: channel::Receiver<Rendered>,
+
+    // where transcripts of finished commands are posted, as the ID and token of a webhook
+    mirror: Option<(u64, String)>,
+}
+
+/// The messages following a terminal's own message
+#[derive(Default)]
+struct Extras {
+    // used when a frame doesn't fit in one message
+    pages: Vec<MessageId>,
+    // holds the full output of a terminal that overflows to a file
+    attachment: Option<MessageId>,
+    // the frame the message was last successfully edited to show
+    shown: Option<session::Frame>,
+}
+
+/// What a render task hands back once it's done with a message
+struct Rendered {
+    channelid: ChannelId,
+    messageid: MessageId,
+    frame: session::Frame,
+    extras: Extras,
+    // holds the message that took the place of the terminal's own if it had been deleted
+    result: Result<Option<MessageId>, serenity::Error>,
+}
+
+impl Renderer {
+    fn new(
+        frame_reciever: channel::Receiver<Packet>,
+        ttys: Ttys,
+        messages: Messages,
+        mirror: Option<(u64, String)>,
+    ) -> Self {
+        // never more than one result per task
+        let (rendered, rendered_reciever) = channel::channel(MAX_CONCURRENT_RENDERS);
+
+        Renderer {
+            frame_reciever,
+            ttys,
+            messages,
+            extras: HashMap::new(),
+            sections: HashMap::new(),
+            pending: HashMap::new(),
+            blocked_until: None,
+            rendering: HashSet::new(),
+            closing: HashSet::new(),
+            replaced: HashMap::new(),
+            rendered,
+            rendered_reciever,
+            mirror,
+        }
+    }
+
+    /// Start the render loop
+    async fn render_pipeline(&mut self, ctx: Context) {
+        loop {
+            let blocked_until = self.blocked_until;
+
+            tokio::select! {
+                packet = self.frame_reciever.recv() => {
+                    let ((channelid, messageid), event) = match packet {
+                        Some(packet) => packet,
+                        None => return,
+                    };
+
+                    match event {
+                        session::Event::Ready => {
+                            debug!("terminal {} finished it's command", messageid);
+                        }
+                        session::Event::Update(frame) => {
+                            let messageid =
+                                self.replaced.get(&messageid).copied().unwrap_or(messageid);
+                            // a terminal created again on the message of one that closed
+                            self.closing.remove(&messageid);
+                            // replaces any older frame still waiting, only the newest is worth showing
+                            let frame = self.compose(messageid, frame);
+                            self.pending.insert(messageid, (channelid, frame));
+                        }
+                        session::Event::Closed { section } => {
+                            self.prune(&ctx, channelid, messageid, section).await
+                        }
+                        session::Event::Finished(transcript) => {
+                            self.mirror(&ctx, messageid, transcript).await
+                        }
+                    }
+                }
+                Some(rendered) = self.rendered_reciever.recv() => self.finish(&ctx, rendered).await,
+                _ = sleep_until_unblocked(blocked_until), if blocked_until.is_some() => {
+                    self.blocked_until = None;
+                }
+            }
+
+            if self.blocked_until.is_none() {
+                self.flush(&ctx);
+            }
+        }
+    }
+
+    /// Post the transcript to the mirror webhook on a task of its own, so that failing to do so
+    /// doesn't affect the terminal
+    async fn mirror(&self, ctx: &Context, messageid: MessageId, transcript: session::Transcript) {
+        let (id, token) = match &self.mirror {
+            Some(webhook) => webhook.clone(),
+            None => return,
+        };
+
+        let term = match self.messages.lock().unwrap().get(&messageid) {
+            Some(term) => term.clone(),
+            None => return,
+        };
+
+        let http = ctx.http.clone();
+
+        tokio::spawn(async move {
+            for content in render_transcript(&term, &transcript) {
+                let mut map = serde_json::Map::new();
+                map.insert(String::from("content"), serde_json::Value::from(content));
+
+                if let Err(e) = http.execute_webhook(id, &token, false, &map).await {
+                    warn!("failed to mirror a transcript of `{}`: {}", term, e);
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Stop tracking a terminal, or one of its slots, that has closed
+    ///
+    /// Terminals that were removed through the handler have already been forgotten by it, but the
+    /// messages following theirs are still to be deleted.
+    async fn prune(
+        &mut self,
+        ctx: &Context,
+        channelid: ChannelId,
+        messageid: MessageId,
+        section: Option<String>,
+    ) {
+        let messageid = self.replaced.get(&messageid).copied().unwrap_or(messageid);
+        let term = self.messages.lock().unwrap().get(&messageid).cloned();
+
+        if let Some(slot) = section {
+            if let Some(sections) = self.sections.get_mut(&messageid) {
+                sections.remove(&Some(slot.clone()));
+            }
+
+            if let Some(term) = term {
+                if let Some(tty) = self.ttys.lock().unwrap().get_mut(&term) {
+                    tty.slots.remove(&slot);
+                }
+            }
+            return;
+        }
+
+        self.sections.remove(&messageid);
+        self.replaced
+            .retain(|_, replacement| *replacement != messageid);
+
+        if let Some(term) = term {
+            // a terminal that was created again on the same message is still running, it's the
+            // one that it replaced that closed
+            let mut ttys = self.ttys.lock().unwrap();
+            let closed = ttys
+                .get(&term)
+                .filter(|tty| tty.message == messageid && tty.sender.is_closed());
+            if closed.is_some() {
+                ttys.remove(&term);
+                self.messages.lock().unwrap().remove(&messageid);
+                info!("terminal `{}` closed by itself", term);
+            }
+        }
+
+        if !self.messages.lock().unwrap().contains_key(&messageid) {
+            self.closing.insert(messageid);
+
+            // otherwise the last frame is shown first, and `finish` forgets the message
+            if !self.rendering.contains(&messageid) && !self.pending.contains_key(&messageid) {
+                self.forget(ctx, channelid, messageid);
+            }
+        }
+    }
+
+    /// Drop everything kept for the message of a terminal that's gone, and delete the messages
+    /// that followed it
+    fn forget(&mut self, ctx: &Context, channelid: ChannelId, messageid: MessageId) {
+        self.closing.remove(&messageid);
+        self.pending.remove(&messageid);
+
+        let extras = match self.extras.remove(&messageid) {
+            Some(extras) => extras,
+            None => return,
+        };
+
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            for extra in extras.pages.into_iter().chain(extras.attachment) {
+                if let Err(e) = channelid.delete_message(&ctx, extra).await {
+                    warn!("failed to delete a message of a closed terminal: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Start rendering as many pending frames as allowed at once
+    ///
+    /// The frames of messages that are still being rendered wait for their task to be done.
+    fn flush(&mut self, ctx: &Context) {
+        let free = MAX_CONCURRENT_RENDERS.saturating_sub(self.rendering.len());
+        let ready: Vec<MessageId> = self
+            .pending
+            .keys()
+            .filter(|messageid| !self.rendering.contains(messageid))
+            .take(free)
+            .copied()
+            .collect();
+
+        for messageid in ready {
+            let (channelid, frame) = self.pending.remove(&messageid).unwrap();
+
+            // the message already shows exactly this, editing it would be a no-op
+            let shown = self
+                .extras
+                .get(&messageid)
+                .and_then(|extras| extras.shown.as_ref());
+            if shown == Some(&frame) {
+                if self.closing.contains(&messageid) {
+                    self.forget(ctx, channelid, messageid);
+                }
+                continue;
+            }
+
+            let mut extras = self.extras.remove(&messageid).unwrap_or_default();
+            let rendered = self.rendered.clone();
+            let ctx = ctx.clone();
+
+            self.rendering.insert(messageid);
+
+            tokio::spawn(async move {
+                let result = refresh(&ctx, channelid, messageid, &frame, &mut extras).await;
+
+                let done = Rendered {
+                    channelid,
+                    messageid,
+                    frame,
+                    extras,
+                    result,
+                };
+                rendered.send(done).await.ok();
+            });
+        }
+    }
+
+    /// Take back a message from the task that rendered it, keeping the frame for later if we got
+    /// rate limited
+    ///
+    /// Once the last frame of a terminal that's gone has been shown, its message is forgotten.
+    async fn finish(&mut self, ctx: &Context, rendered: Rendered) {
+        let mut messageid = rendered.messageid;
+        self.rendering.remove(&messageid);
+        let mut extras = rendered.extras;
+
+        match rendered.result {
+            Ok(None) => {
+                metrics::frame_rendered();
+                extras.shown = Some(rendered.frame);
+            }
+            Ok(Some(replacement)) => {
+                metrics::frame_rendered();
+                self.replace(rendered.channelid, messageid, replacement)
+                    .await;
+                if self.closing.remove(&messageid) {
+                    self.closing.insert(replacement);
+                }
+                messageid = replacement;
+                extras.shown = Some(rendered.frame);
+            }
+            Err(e) if is_rate_limited(&e) => {
+                metrics::render_failed();
+                warn!(
+                    "rate limited, holding back frames for {:?}",
+                    RATE_LIMIT_BACKOFF
+                );
+
+                self.blocked_until = Some(tokio::time::Instant::now() + RATE_LIMIT_BACKOFF);
+
+                // unless a newer frame has arrived in the meantime
+                self.pending
+                    .entry(messageid)
+                    .or_insert((rendered.channelid, rendered.frame));
+            }
+            Err(e) => {
+                metrics::render_failed();
+                warn!("frame update error: {}", e);
+            }
+        }
+
+        self.extras.insert(messageid, extras);
+
+        if self.closing.contains(&messageid) && !self.pending.contains_key(&messageid) {
+            self.forget(ctx, rendered.channelid, messageid);
+        }
+    }
+
+    /// Move a terminal over to the message that was posted in place of its deleted one, telling its
+    /// sessions where to send their frames from now on
+    async fn replace(
+        &mut self,
+        channelid: ChannelId,
+        messageid: MessageId,
+        replacement: MessageId,
+    ) {
+        let term = match self.messages.lock().unwrap().remove(&messageid) {
+            Some(term) => term,
+            None => return,
+        };
+        self.messages
+            .lock()
+            .unwrap()
+            .insert(replacement, term.clone());
+
+        if let Some(tty) = self.ttys.lock().unwrap().get_mut(&term) {
+            tty.message = replacement;
+            tty.target.send((channelid, replacement)).ok();
+        }
+
+        info!("recreated the deleted message of terminal `{}`", term);
+
+        if let Some(sections) = self.sections.remove(&messageid) {
+            self.sections.insert(replacement, sections);
+        }
+        if let Some(pending) = self.pending.remove(&messageid) {
+            self.pending.insert(replacement, pending);
+        }
+        self.replaced.insert(messageid, replacement);
+    }
+
+    /// Combine the frame with the latest frames of the other sections in the same message
+    fn compose(&mut self, messageid: MessageId, frame: session::Frame) -> session::Frame {
+        let lang = frame.lang.clone();
+
+        let sections = self.sections.entry(messageid).or_default();
+        sections.insert(frame.section.clone(), frame);
+
+        let mut text = String::new();
+        let mut full: Option<String> = None;
+
+        for (section, frame) in sections.iter() {
+            let header = match section {
+                Some(slot) => format!("\n--- slot {} ---\n", slot),
+                None => String::new(),
+            };
+
+            text.push_str(&header);
+            text.push_str(&frame.text);
+
+            if let Some(lines) = &frame.full {
+                let full = full.get_or_insert_with(String::new);
+                full.push_str(&header);
+                full.push_str(lines);
+            }
+        }
+
+        session::Frame {
+            text,
+            lang,
+            section: None,
+            full,
+        }
+    }
+}
+
+/// Render a frame to a discord message, continuing onto more messages if it doesn't fit
+///
+/// A message that has been deleted is posted again, in which case the new message is returned.
+async fn refresh(
+    ctx: &Context,
+    channelid: ChannelId,
+    messageid: MessageId,
+    frame: &session::Frame,
+    extras: &mut Extras,
+) -> Result<Option<MessageId>, serenity::Error> {
+    let lang = frame.lang.as_deref();
+    let mut pages = paginate(&frame.text, page_limit(lang));
+
+    // the whole output goes in the attachment, so only the latest lines are shown
+    let attachment = match &frame.full {
+        Some(full) if pages.len() > 1 => {
+            pages.drain(..pages.len() - 1);
+            Some(full.as_str())
+        }
+        _ => None,
+    };
+
+    if pages.len() > MAX_PAGES {
+        debug!(
+            "dropping the oldest lines since the frame doesn't fit even when spread over {} messages",
+            MAX_PAGES
+        );
+        pages.drain(..pages.len() - MAX_PAGES);
+    }
+
+    let mut pages = pages.into_iter();
+    let first = render_terminal_layout(lang, pages.next().unwrap_or_default());
+    let mut replacement = None;
+
+    let edited = channelid
+        .edit_message(&ctx, messageid, |m| {
+            m.content(&first);
+            m
+        })
+        .await;
+
+    match edited {
+        Ok(_) => {}
+        Err(e) if is_unknown_message(&e) => {
+            let message = channelid
+                .send_message(&ctx, |m| {
+                    m.content(&first);
+                    m
+                })
+                .await?;
+            replacement = Some(message.id);
+
+            // the pages would come before the new message, so they're posted again after it
+            for pageid in extras.pages.drain(..) {
+                if let Err(e) = channelid.delete_message(&ctx, pageid).await {
+                    warn!("failed to remove page {}: {}", pageid, e);
+                }
+            }
+        }
+        Err(e) => return Err(e),
+    }
+
+    let extra = &mut extras.pages;
+    let mut used = 0;
+
+    for page in pages {
+        match extra.get(used) {
+            Some(&pageid) => {
+                channelid
+                    .edit_message(&ctx, pageid, |m| {
+                        m.content(render_terminal_layout(lang, page));
+                        m
+                    })
+                    .await?;
+            }
+            None => {
+                let message = channelid
+                    .send_message(&ctx, |m| {
+                        m.content(render_terminal_layout(lang, page));
+                        m
+                    })
+                    .await?;
+                extra.push(message.id);
+            }
+        }
+
+        used += 1;
+    }
+
+    for pageid in extra.split_off(used) {
+        if let Err(e) = channelid.delete_message(&ctx, pageid).await {
+            warn!("failed to remove unused page {}: {}", pageid, e);
+        }
+    }
+
+    if frame.full.is_some() {
+        replace_attachment(ctx, channelid, extras, attachment).await?;
+    }
+
+    Ok(replacement)
+}
+
+/// Send `contents` as a file in place of the previous one, or just remove the previous one if
+/// the output fits again
+async fn replace_attachment(
+    ctx: &Context,
+    channelid: ChannelId,
+    extras: &mut Extras,
+    contents: Option<&str>,
+) -> Result<(), serenity::Error> {
+    if let Some(previous) = extras.attachment.take() {
+        if let Err(e) = channelid.delete_message(&ctx, previous).await {
+            warn!("failed to remove attachment {}: {}", previous, e);
+        }
+    }
+
+    if let Some(contents) = contents {
+        let file = AttachmentType::Bytes {
+            data: Cow::Owned(contents.as_bytes().to_vec()),
+            filename: ATTACHMENT_NAME.to_string(),
+        };
+
+        let message = channelid
+            .send_message(&ctx, |m| {
+                m.add_file(file);
+                m
+            })
+            .await?;
+
+        extras.attachment = Some(message.id);
+    }
+
+    Ok(())
+}
+
+/// The messages a transcript is posted as, a line saying what ran followed by the output
+fn render_transcript(term: &str, transcript: &session::Transcript) -> Vec<String> {
+    let name = match &transcript.section {
+        Some(slot) => format!("{}.{}", term, slot),
+        None => term.to_string(),
+    };
+
+    let requested_by = match &transcript.requested_by {
+        Some(user) => format!(" for {}", user),
+        None => String::new(),
+    };
+
+    // the command itself is echoed at the start of its output
+    let header = format!(
+        "`{}` finished a command{}, {}",
+        name, requested_by, transcript.exit
+    );
+
+    let mut messages = vec![header];
+    messages.extend(
+        paginate(&transcript.output, page_limit(None))
+            .into_iter()
+            .map(|page| render_terminal_layout(None, page)),
+    );
+    messages
+}
+
+async fn sleep_until_unblocked(until: Option<tokio::time::Instant>) {
+    match until {
+        Some(until) => tokio::time::sleep_until(until).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Whether Discord refused the request because we're sending too many
+fn is_rate_limited(err: &serenity::Error) -> bool {
+    use serenity::http::{HttpError, StatusCode};
+
+    match err {
+        serenity::Error::Http(err) => match err.as_ref() {
+            HttpError::UnsuccessfulRequest(response) => {
+                response.status_code == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Whether Discord refused the request because the message no longer exists
+fn is_unknown_message(err: &serenity::Error) -> bool {
+    use serenity::http::HttpError;
+
+    match err {
+        serenity::Error::Http(err) => match err.as_ref() {
+            HttpError::UnsuccessfulRequest(response) => response.error.code == UNKNOWN_MESSAGE,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Split a frame into pages of whole lines that each fit within `limit` bytes
+///
+/// Lines that don't fit on a page by themselves are cut into several lines.
+fn paginate(frame: &str, limit: usize) -> Vec<String> {
+    let mut pages = Vec::new();
+    let mut page = String::new();
+    let mut page_has_lines = false;
+
+    for line in frame.split('\n') {
+        let mut rest = line;
+
+        loop {
+            let mut cut = rest.len().min(limit);
+            while !rest.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            // a character wider than the limit still has to go somewhere, or it'd never be cut off
+            if cut == 0 {
+                cut = rest.chars().next().map_or(0, char::len_utf8);
+            }
+            let (piece, tail) = rest.split_at(cut);
+
+            if page_has_lines && page.len() + 1 + piece.len() > limit {
+                pages.push(std::mem::take(&mut page));
+                page_has_lines = false;
+            }
+
+            if page_has_lines {
+                page.push('\n');
+            }
+            page.push_str(piece);
+            page_has_lines = true;
+
+            rest = tail;
+            if rest.is_empty() {
+                break;
+            }
+        }
+    }
+
+    pages.push(page);
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_the_terminal_from_its_command() {
+        assert_eq!(split_terminal_command("sh run ls -l"), ("sh", "run ls -l"));
+        assert_eq!(split_terminal_command("sh   cancel  "), ("sh", "cancel"));
+    }
+
+    #[test]
+    fn splits_empty_and_single_character_content() {
+        assert_eq!(split_terminal_command(""), ("", ""));
+        assert_eq!(split_terminal_command(" "), ("", ""));
+        assert_eq!(split_terminal_command("a"), ("a", ""));
+        assert_eq!(split_terminal_command("a "), ("a", ""));
+    }
+
+    #[test]
+    fn splits_at_any_whitespace() {
+        assert_eq!(
+            split_terminal_command("sh\n```\nls\n```"),
+            ("sh", "```\nls\n```")
+        );
+        assert_eq!(split_terminal_command("sh\trun ls"), ("sh", "run ls"));
+    }
+
+    #[test]
+    fn splits_multi_byte_characters() {
+        assert_eq!(
+            split_terminal_command("tërm run echo 🎉"),
+            ("tërm", "run echo 🎉")
+        );
+        assert_eq!(split_terminal_command("🎉 run ls"), ("🎉", "run ls"));
+        assert_eq!(split_terminal_command("é"), ("é", ""));
+        assert_eq!(split_terminal_command("sh\u{3000}é"), ("sh", "é"));
+
+        assert!(matches!(
+            validate_terminal_name("tërm"),
+            Err(Error::InvalidTerminalName)
+        ));
+        assert!(matches!(
+            validate_terminal_name("🎉"),
+            Err(Error::InvalidTerminalName)
+        ));
+        assert!(matches!(
+            validate_terminal_name("admin"),
+            Err(Error::ReservedTerminalName("admin"))
+        ));
+        assert!(matches!(
+            validate_terminal_name("status"),
+            Err(Error::ReservedTerminalName("status"))
+        ));
+        assert!(validate_terminal_name("status.1").is_ok());
+    }
+
+    #[test]
+    fn rendered_pages_fit_in_a_message() {
+        let frame = vec!["x".repeat(150); 100].join("\n");
+
+        for lang in &[None, Some("rust"), Some("typescript")] {
+            let limit = page_limit(*lang);
+            let pages = paginate(&frame, limit);
+            assert!(pages.len() > 1);
+
+            for page in pages {
+                assert!(page.len() <= limit);
+                assert!(render_terminal_layout(*lang, page).len() <= DISCORD_LENGTH_LIMIT);
+            }
+        }
+
+        let exact = "x".repeat(page_limit(Some("rust")));
+        assert_eq!(
+            render_terminal_layout(Some("rust"), exact).len(),
+            DISCORD_LENGTH_LIMIT
+        );
+    }
+
+    #[test]
+    fn paginates_characters_wider_than_the_limit() {
+        assert_eq!(paginate("aé\n漢", 1), vec!["a", "é", "漢"]);
+        assert_eq!(paginate("", 1), vec![""]);
+    }
+
+    #[test]
+    fn formats_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(59)), "0m");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 3600 + 120)), "3h 2m");
+        assert_eq!(
+            format_uptime(Duration::from_secs(2 * 86400 + 60)),
+            "2d 0h 1m"
+        );
+    }
+
+    #[test]
+    fn refuses_what_bypasses_the_allowlist() {
+        let check =
+            |cmd| check_bypasses_allowlist(&parser::parse(cmd).unwrap()).map_err(|e| e.to_string());
+        let bypasses = |what| Err(Error::BypassesAllowlist(what).to_string());
+
+        assert_eq!(check("new shell=python3"), bypasses("shell="));
+        assert_eq!(check("new log=/root/.bashrc"), bypasses("log="));
+        assert_eq!(check("follow /etc/shadow"), bypasses("follow"));
+        assert_eq!(check("env LD_PRELOAD=/tmp/evil.so"), bypasses("env"));
+
+        assert_eq!(check("new height=10 run ls"), Ok(()));
+        assert_eq!(check("env -PATH"), Ok(()));
+        assert_eq!(check("run ls"), Ok(()));
+    }
+
+    #[test]
+    fn only_allows_allowlisted_commands() {
+        let allowlist = vec![String::from("ls"), String::from("git status")];
+        let allowed = |cmd| check_allowlist(&allowlist, cmd).map_err(|e| e.to_string());
+
+        assert!(allowed("ls").is_ok());
+        assert!(allowed("ls -l 'some dir'").is_ok());
+        assert!(allowed("git  status --short").is_ok());
+
+        let denied = |cmd, what: &str| {
+            assert_eq!(
+                allowed(cmd),
+                Err(Error::CommandNotAllowed(what.to_string()).to_string())
+            )
+        };
+        denied("rm -rf /", "rm");
+        denied("git push", "git");
+        denied("lsblk", "lsblk");
+        denied("FOO=1 ls", "FOO=1");
+        denied("", "");
+        denied("ls; rm -rf /", ";");
+        denied("ls | sh", "|");
+        denied("ls && rm x", "&");
+        denied("ls $(rm x)", "$");
+        denied("ls `rm x`", "`");
+        denied("ls > x", ">");
+        denied("ls\nrm x", "\n");
+    }
+
+    #[test]
+    fn describes_commands_as_they_would_run() {
+        assert_eq!(describe_command(&[], "bash", "ls -l"), "bash -c 'ls -l'");
+        assert_eq!(
+            describe_command(
+                &[String::from("nice"), String::from("-n"), String::from("10")],
+                "sh",
+                "echo 'hi there'"
+            ),
+            r"nice -n 10 sh -c 'echo '\''hi there'\'''"
+        );
+        assert_eq!(describe_command(&[], "bash", ""), "bash -c ''");
+    }
+
+    #[test]
+    fn accepts_plain_terminal_names() {
+        assert!(validate_terminal_name("sh").is_ok());
+        assert!(validate_terminal_name("build-2.watch_logs").is_ok());
+        assert!(validate_terminal_name(&"a".repeat(MAX_NAME_LENGTH)).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_terminal_names() {
+        for name in &["", "`sh`", "a b", "@everyone", "sh\n", "térm"] {
+            assert!(matches!(
+                validate_terminal_name(name),
+                Err(Error::InvalidTerminalName)
+            ));
+        }
+
+        let long = "a".repeat(MAX_NAME_LENGTH + 1);
+        assert!(matches!(
+            validate_terminal_name(&long),
+            Err(Error::InvalidTerminalName)
+        ));
+    }
+
+    #[test]
+    fn parses_role_limits() {
+        let limits = parse_role_limits("1:3:40;2::20;3").unwrap();
+
+        assert_eq!(
+            limits[&RoleId(1)],
+            Limits {
+                max_terminals: Some(3),
+                max_height: Some(40)
+            }
+        );
+        assert_eq!(
+            limits[&RoleId(2)],
+            Limits {
+                max_terminals: None,
+                max_height: Some(20)
+            }
+        );
+        assert_eq!(limits[&RoleId(3)], Limits::default());
+        assert!(parse_role_limits("1:many").is_err());
+    }
+
+    #[test]
+    fn parses_guild_prefixes() {
+        let prefixes = parse_guild_prefixes("1:!;2:>>;").unwrap();
+        assert_eq!(prefixes[&GuildId(1)], "!");
+        assert_eq!(prefixes[&GuildId(2)], ">>");
+        assert_eq!(prefixes.len(), 2);
+
+        assert_eq!(parse_guild_prefixes("1:"), None);
+        assert_eq!(parse_guild_prefixes("1"), None);
+        assert_eq!(parse_guild_prefixes("guild:!"), None);
+    }
+
+    #[test]
+    fn uses_the_prefix_of_the_guild() {
+        let settings = Settings::builder()
+            .prefix("$")
+            .guild_prefix(GuildId(1), "!")
+            .build();
+
+        assert_eq!(settings.prefix_in(Some(GuildId(1))), "!");
+        assert_eq!(settings.prefix_in(Some(GuildId(2))), "$");
+        assert_eq!(settings.prefix_in(None), "$");
+    }
+
+    #[test]
+    fn builds_settings_on_top_of_the_defaults() {
+        let settings = Settings::builder()
+            .allowed_roles(vec![RoleId(1)])
+            .prefix("!")
+            .heartbeat(None)
+            .build();
+
+        assert_eq!(settings.allowed_roles, [RoleId(1)]);
+        assert_eq!(settings.prefix, "!");
+        assert_eq!(settings.heartbeat, None);
+        assert_eq!(settings.shell, DEFAULT_SHELL);
+    }
+
+    /// Held by tests that set environment variables, as the settings read all of them
+    static ENV: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    #[test]
+    fn reports_every_misconfigured_variable() {
+        let _env = ENV.lock().unwrap_or_else(|err| err.into_inner());
+        std::env::set_var("FRAME_COOLDOWN", "soon");
+        std::env::set_var("MAX_HEIGHT", "tall");
+        let err = Settings::try_from_env().err();
+        std::env::remove_var("FRAME_COOLDOWN");
+        std::env::remove_var("MAX_HEIGHT");
+
+        assert_eq!(
+            err,
+            Some(ConfigError(vec![
+                Misconfigured::NoAllowedUsers,
+                Misconfigured::Invalid {
+                    var: "FRAME_COOLDOWN",
+                    expected: "a number of seconds"
+                },
+                Misconfigured::Invalid {
+                    var: "MAX_HEIGHT",
+                    expected: "a number of lines"
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn raises_cooldowns_set_by_the_owner_to_the_minimum() {
+        let handler = Handler::new(Settings::builder().build());
+
+        let report = handler.set_cooldown(Duration::from_secs(0));
+        assert_eq!(report, "new terminals wait at least 1s between frames");
+        assert_eq!(handler.settings().cooldown, terminal::MIN_COOLDOWN);
+    }
+
+    #[test]
+    fn keeps_what_the_owner_changed_when_reloading() {
+        let _env = ENV.lock().unwrap_or_else(|err| err.into_inner());
+        let handler = Handler::new(Settings::builder().build());
+        handler.set_prefix(Some(GuildId(1)), String::from("!"));
+        handler.set_prefix(None, String::from("%"));
+        handler.set_cooldown(Duration::from_secs(5));
+
+        std::env::set_var("ALLOWED_USERS", "1");
+        let reloaded = handler.reload_settings();
+        std::env::remove_var("ALLOWED_USERS");
+        assert!(reloaded.is_ok());
+
+        let settings = handler.settings();
+        assert_eq!(settings.allowed_users, [UserId(1)]);
+        assert_eq!(settings.prefix_in(Some(GuildId(1))), "!");
+        assert_eq!(settings.prefix_in(Some(GuildId(2))), "%");
+        assert_eq!(settings.cooldown, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn reads_dry_run_as_a_boolean() {
+        let _env = ENV.lock().unwrap_or_else(|err| err.into_inner());
+        std::env::set_var("ALLOWED_USERS", "1");
+        let dry_run = |value: &str| {
+            std::env::set_var("DRY_RUN", value);
+            Settings::try_from_env().map(|settings| settings.dry_run)
+        };
+        let (on, off, unclear) = (dry_run("true"), dry_run("0"), dry_run("yes"));
+        std::env::remove_var("ALLOWED_USERS");
+        std::env::remove_var("DRY_RUN");
+
+        assert_eq!(on, Ok(true));
+        assert_eq!(off, Ok(false));
+        assert_eq!(
+            unclear,
+            Err(ConfigError(vec![Misconfigured::Invalid {
+                var: "DRY_RUN",
+                expected: "1, 0, true or false"
+            }]))
+        );
+    }
+
+    #[test]
+    fn reports_sizes_and_durations_that_overflow() {
+        let _env = ENV.lock().unwrap_or_else(|err| err.into_inner());
+        let huge = u64::MAX.to_string();
+        std::env::set_var("MAX_INPUT_SIZE", &huge);
+        std::env::set_var("LIMIT_MEMORY", &huge);
+        std::env::set_var("IDLE_TIMEOUT", &huge);
+        let err = Settings::try_from_env().err();
+        std::env::remove_var("MAX_INPUT_SIZE");
+        std::env::remove_var("LIMIT_MEMORY");
+        std::env::remove_var("IDLE_TIMEOUT");
+
+        assert_eq!(
+            err,
+            Some(ConfigError(vec![
+                Misconfigured::NoAllowedUsers,
+                Misconfigured::Invalid {
+                    var: "MAX_INPUT_SIZE",
+                    expected: "a number of kilobytes"
+                },
+                Misconfigured::Invalid {
+                    var: "LIMIT_MEMORY",
+                    expected: "a number of megabytes"
+                },
+                Misconfigured::Invalid {
+                    var: "IDLE_TIMEOUT",
+                    expected: "a number of minutes"
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn uses_the_most_generous_limits_of_the_roles() {
+        let mut settings = Settings::new(Vec::new(), String::from("$"));
+        settings.limits = Limits {
+            max_terminals: Some(1),
+            max_height: Some(10),
+        };
+        settings.role_limits = parse_role_limits("1:3:40;2::20").unwrap();
+
+        assert_eq!(settings.limits_of(&[]), settings.limits);
+        assert_eq!(settings.limits_of(&[RoleId(4)]), settings.limits);
+        assert_eq!(
+            settings.limits_of(&[RoleId(1), RoleId(2)]),
+            Limits {
+                max_terminals: None,
+                max_height: Some(40)
+            }
+        );
+    }
+
+    #[test]
+    fn parses_webhook_urls() {
+        assert_eq!(
+            parse_webhook_url("https://discord.com/api/webhooks/123/abc-DEF/"),
+            Some((123, String::from("abc-DEF")))
+        );
+        assert_eq!(parse_webhook_url("https://discord.com/api/123/abc"), None);
+        assert_eq!(
+            parse_webhook_url("https://discord.com/api/webhooks/abc"),
+            None
+        );
+    }
+
+    #[test]
+    fn loads_the_defaults_of_channels() {
+        let path = std::env::temp_dir().join(format!("termview-channels-{}", std::process::id()));
+        std::fs::write(&path, r#"{"1": {"height": 40, "shell": "zsh"}, "2": {}}"#).unwrap();
+
+        let defaults = load_channel_defaults(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(
+            defaults[&ChannelId(1)],
+            ChannelDefaults {
+                height: Some(40),
+                shell: Some(String::from("zsh")),
+                ..ChannelDefaults::default()
+            }
+        );
+        assert_eq!(defaults[&ChannelId(2)], ChannelDefaults::default());
+    }
+
+    #[test]
+    fn refuses_the_heights_new_would_refuse_in_channel_defaults() {
+        let path =
+            std::env::temp_dir().join(format!("termview-invalid-channels-{}", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{"3": {"height": 100000}, "1": {"height": 0}, "2": {"height": 40}}"#,
+        )
+        .unwrap();
+
+        let invalid = load_channel_defaults(&path).unwrap_err();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(
+            invalid,
+            vec![
+                Misconfigured::ChannelDefault {
+                    channel: 1,
+                    reason: parser::Error::InvalidNumber.to_string(),
+                },
+                Misconfigured::ChannelDefault {
+                    channel: 3,
+                    reason: parser::Error::HeightToLarge(100000).to_string(),
+                },
+            ]
+        );
+    }
+
+    fn tty(sender: channel::Sender<terminal::Command>) -> Tty {
+        let (target, target_reciever) = watch::channel((ChannelId(1), MessageId(1)));
+
+        Tty {
+            sender,
+            slots: HashMap::new(),
+            target: Arc::new(target),
+            target_reciever,
+            channel: ChannelId(1),
+            message: MessageId(1),
+            owner: UserId(1),
+            config: terminal::Config {
+                height: parser::DEFAULT_HEIGHT,
+                timeout: None,
+                cooldown: Duration::from_secs(1),
+                max_cooldown: Duration::from_secs(1),
+                scrollback: terminal::DEFAULT_SCROLLBACK,
+                idle_timeout: None,
+                heartbeat: None,
+                dedup: false,
+                width: None,
+                tui: false,
+                timestamps: false,
+                hex: false,
+                encoding: encoding_rs::UTF_8,
+                limits: terminal::ResourceLimits::default(),
+            },
+            layout: session::Layout::default(),
+            shell: String::from("sh"),
+            dir: None,
+            env: HashMap::new(),
+            log: None,
+            stderr: terminal::StderrMode::default(),
+            snapshot: Arc::default(),
+        }
+    }
+
+    #[test]
+    fn refuses_terminals_past_the_limit() {
+        let settings = Settings::builder().terminal_limit(2).build();
+        let handler = Handler::new(settings);
+        let sender = || channel::channel(1).0;
+
+        let a = String::from("a");
+        let b = String::from("b");
+        let c = String::from("c");
+        assert!(handler.register_terminal(&a, tty(sender())).is_ok());
+        assert!(handler.register_terminal(&b, tty(sender())).is_ok());
+        assert!(matches!(
+            handler.register_terminal(&c, tty(sender())),
+            Err(Error::TooManyTerminals(2, 2))
+        ));
+
+        // replacing a terminal doesn't take another slot, and removing one frees its slot
+        assert!(matches!(
+            handler.register_terminal(&a, tty(sender())),
+            Ok(Some(_))
+        ));
+        handler.ttys.lock().unwrap().remove(&b);
+        assert!(handler.register_terminal(&c, tty(sender())).is_ok());
+    }
+
+    #[tokio::test]
+    async fn commands_dont_wait_on_other_terminals() {
+        let handler = Arc::new(Handler::new(Settings::new(Vec::new(), String::from("$"))));
+
+        // nothing takes the commands of `stuck`, so sending it another waits forever
+        let (stuck, _commands) = channel::channel(1);
+        stuck.send(terminal::Command::Redraw).await.unwrap();
+        handler
+            .ttys
+            .lock()
+            .unwrap()
+            .insert(String::from("stuck"), tty(stuck));
+
+        let mut receivers = Vec::new();
+        for i in 0..20 {
+            let (sender, receiver) = channel::channel(1);
+            handler
+                .ttys
+                .lock()
+                .unwrap()
+                .insert(format!("t{}", i), tty(sender));
+            receivers.push(receiver);
+        }
+
+        let blocked = handler.clone();
+        tokio::spawn(async move {
+            blocked
+                .send_to_terminal(String::from("stuck"), terminal::Command::Clear)
+                .await
+        });
+
+        let sends = (0..20)
+            .map(|i| {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    handler
+                        .send_to_terminal(format!("t{}", i), terminal::Command::Clear)
+                        .await
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(1);
+        for send in sends {
+            let sent = tokio::time::timeout_at(deadline, send).await;
+            assert!(matches!(sent, Ok(Ok(Ok(())))), "blocked by `stuck`");
+        }
+
+        for receiver in &mut receivers {
+            assert!(matches!(
+                receiver.recv().await,
+                Some(terminal::Command::Clear)
+            ));
+        }
+    }
+
+    /// Start a terminal the way `spawn_new_terminal` does, rendering into the frames of `handler`
+    /// rather than a message on Discord
+    /// Start a terminal the way `spawn_new_terminal` does, other than posting its first message
+    ///
+    /// Creating a terminal, and the other commands that post messages, needs a `Context` that
+    /// can't be made without connecting to Discord, so those commands are left untested.
+    fn spawn_terminal(handler: &Handler, term: &str) {
+        let mut tty = tty(channel::channel(1).0);
+        let session = session::TTYSession::new(
+            tty.target_reciever.clone(),
+            handler.frame_sender.clone(),
+            tty.layout.clone(),
+        );
+
+        let (runner, sender) = terminal::Runner::init(session, tty.config.clone());
+        tokio::spawn(runner.listen());
+        tty.sender = sender;
+
+        handler
+            .messages
+            .lock()
+            .unwrap()
+            .insert(tty.message, term.to_string());
+        handler.ttys.lock().unwrap().insert(term.to_string(), tty);
+    }
+
+    fn origin() -> Origin {
+        Origin {
+            channel: ChannelId(1),
+            guild: None,
+            author: User::default(),
+            roles: Vec::new(),
+            message: None,
+            attachments: Vec::new(),
+        }
+    }
+
+    async fn next_packet(frames: &mut channel::Receiver<Packet>) -> Packet {
+        let limit = Duration::from_secs(5);
+        tokio::time::timeout(limit, frames.recv())
+            .await
+            .expect("nothing was sent to the renderer")
+            .expect("the renderer's channel closed")
+    }
+
+    #[tokio::test]
+    async fn renders_the_output_of_commands_into_the_message() {
+        let handler = Handler::new(Settings::builder().build());
+        let mut frames = handler.frame_reciever.lock().await.take().unwrap();
+        spawn_terminal(&handler, "t");
+
+        let run = handler
+            .parse_command(Access::Full, "t", "run echo hi")
+            .unwrap();
+        handler
+            .apply_command(&origin(), String::from("t"), run)
+            .await
+            .unwrap();
+
+        // the last frame before the terminal is ready again is the one the command ended with
+        let mut latest = String::new();
+        loop {
+            match next_packet(&mut frames).await {
+                (target, session::Event::Update(frame)) => {
+                    assert_eq!(target, (ChannelId(1), MessageId(1)));
+                    latest = frame.text;
+                }
+                (_, session::Event::Ready) => break,
+                _ => panic!("expected frames until the command exited"),
+            }
+        }
+
+        assert_eq!(
+            latest,
+            format!("$ echo hi\nhi\n[exit 0]\n{}", session::DEFAULT_PROMPT)
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_commands_that_cant_be_applied() {
+        let mut settings = Settings::builder().build();
+        settings.viewer_commands = vec![String::from("list")];
+        let handler = Handler::new(settings);
+        spawn_terminal(&handler, "t");
+
+        assert!(matches!(
+            handler.parse_command(Access::View, "t", "run echo hi"),
+            Err(Error::ViewOnly("run"))
+        ));
+        assert!(matches!(
+            handler.parse_command(Access::Full, "admin", "run echo hi"),
+            Err(Error::ReservedTerminalName("admin"))
+        ));
+
+        // it posts the list, which only `parse_and_apply_command` can do
+        let list = handler.parse_command(Access::View, "t", "list").unwrap();
+        assert!(matches!(
+            handler
+                .apply_command(&origin(), String::from("t"), list)
+                .await,
+            Err(Error::NeedsDiscord("list"))
+        ));
+    }
+
+    #[tokio::test]
+    async fn removing_a_terminal_closes_its_message() {
+        let handler = Handler::new(Settings::builder().build());
+        let mut frames = handler.frame_reciever.lock().await.take().unwrap();
+        spawn_terminal(&handler, "t");
+
+        let missing = handler
+            .apply_command(&origin(), String::from("u"), parser::Command::Clear)
+            .await;
+        assert!(matches!(missing, Err(Error::NoTerminal(term)) if term == "u"));
+
+        handler
+            .apply_command(&origin(), String::from("t"), parser::Command::Remove)
+            .await
+            .unwrap();
+        assert!(handler.ttys.lock().unwrap().is_empty());
+        assert!(handler.messages.lock().unwrap().is_empty());
+
+        let mut latest = String::new();
+        loop {
+            match next_packet(&mut frames).await {
+                (_, session::Event::Update(frame)) => latest = frame.text,
+                (_, session::Event::Closed { section: None }) => break,
+                _ => panic!("expected frames until the terminal closed"),
+            }
+        }
+        assert!(latest.contains("<session closed>"));
     }
 }
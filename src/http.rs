@@ -0,0 +1,388 @@
+//! A read-only HTTP endpoint for the output of terminals, so that tools outside of Discord can
+//! follow them.
+//!
+//! `GET /terminals/<name>` responds with the visible output of the terminal as plain text, or as
+//! JSON if the request accepts `application/json`. Every request has to carry the bearer token.
+
+use crate::config::{ConfigError, Env, Misconfigured};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use serde_json::json;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const TERMINALS_PATH: &str = "/terminals/";
+// requests are nothing but a request line and headers, anything longer isn't meant for us
+const MAX_REQUEST_LENGTH: usize = 8 * 1024;
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the output of the terminals is looked up
+#[async_trait]
+pub trait Terminals {
+    /// The visible output of the terminal, `None` if there's no such terminal
+    async fn snapshot(&self, term: &str) -> Option<String>;
+}
+
+pub struct Settings {
+    pub address: SocketAddr,
+    /// Requests have to carry it as `Authorization: Bearer <token>`
+    pub token: String,
+}
+
+impl Settings {
+    /// `None` unless `HTTP_PORT` is set
+    pub fn parse() -> Option<Self> {
+        Self::try_from_env().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Read the settings from the environment, reporting every misconfigured variable
+    pub fn try_from_env() -> Result<Option<Self>, ConfigError> {
+        if std::env::var_os("HTTP_PORT").is_none() {
+            return Ok(None);
+        }
+
+        let mut env = Env::default();
+
+        let port = env.read("HTTP_PORT", "a port number", |port| port.parse().ok());
+        let host = env
+            .read("HTTP_HOST", "an IP address", |host| host.parse().ok())
+            .unwrap_or(DEFAULT_HOST);
+
+        let token = std::env::var("HTTP_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+        if token.is_none() {
+            env.misconfigured.push(Misconfigured::Invalid {
+                var: "HTTP_TOKEN",
+                expected: "set along with HTTP_PORT",
+            });
+        }
+
+        match (port, token) {
+            (Some(port), Some(token)) if env.misconfigured.is_empty() => Ok(Some(Settings {
+                address: SocketAddr::new(host, port),
+                token,
+            })),
+            _ => Err(ConfigError(env.misconfigured)),
+        }
+    }
+}
+
+/// Serve the output of `terminals` until the program exits, only failing if the address can't
+/// be listened on
+pub async fn serve(
+    settings: Settings,
+    terminals: impl Terminals + Send + Sync + 'static,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(settings.address).await?;
+    info!("serving terminals over http on {}", settings.address);
+
+    accept(listener, Arc::new(settings.token), Arc::new(terminals)).await;
+    Ok(())
+}
+
+async fn accept<T: Terminals + Send + Sync + 'static>(
+    listener: TcpListener,
+    token: Arc<String>,
+    terminals: Arc<T>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("failed to accept http connection: {}", e);
+                continue;
+            }
+        };
+
+        let token = token.clone();
+        let terminals = terminals.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &token, &*terminals).await {
+                debug!("http connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection<T: Terminals + Sync>(
+    mut stream: TcpStream,
+    token: &str,
+    terminals: &T,
+) -> io::Result<()> {
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request took too long"))??;
+
+    let response = match head {
+        Some(head) => respond(&head, token, terminals).await,
+        None => Response::text(400, "Bad Request", String::from("malformed request")),
+    };
+
+    stream.write_all(response.to_string().as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Read the request line and headers, `None` if they're too long or not UTF-8
+pub(crate) async fn read_head(stream: &mut TcpStream) -> io::Result<Option<String>> {
+    let mut head = Vec::new();
+    let mut buffer = [0; 1024];
+
+    loop {
+        if let Some(end) = head.windows(4).position(|window| window == b"\r\n\r\n") {
+            head.truncate(end);
+            return Ok(String::from_utf8(head).ok());
+        }
+
+        if head.len() > MAX_REQUEST_LENGTH {
+            return Ok(None);
+        }
+
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+}
+
+/// Answer the request made up of `head`, its request line and headers
+async fn respond<T: Terminals + Sync>(head: &str, token: &str, terminals: &T) -> Response {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or("").split(' ');
+    let method = request_line.next().unwrap_or("");
+    let target = request_line.next().unwrap_or("");
+
+    let mut authorization = None;
+    let mut wants_json = false;
+
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value);
+        } else if name.eq_ignore_ascii_case("accept") {
+            wants_json = value.contains("application/json");
+        }
+    }
+
+    // checked before anything else, so that nothing is given away without the token
+    let given = authorization.and_then(|value| value.strip_prefix("Bearer "));
+    if !matches!(given, Some(given) if same_token(given.trim(), token)) {
+        return Response::text(401, "Unauthorized", String::from("missing or wrong token"));
+    }
+
+    if method != "GET" {
+        return Response::text(
+            405,
+            "Method Not Allowed",
+            String::from("only GET is allowed"),
+        );
+    }
+
+    let path = target.split('?').next().unwrap_or("");
+    let term = match path.strip_prefix(TERMINALS_PATH) {
+        Some(term) if !term.is_empty() => term,
+        _ => return Response::text(404, "Not Found", String::from("not found")),
+    };
+
+    match terminals.snapshot(term).await {
+        Some(output) if wants_json => Response {
+            status: 200,
+            reason: "OK",
+            content_type: "application/json",
+            body: json!({ "terminal": term, "output": output }).to_string(),
+        },
+        Some(output) => Response::text(200, "OK", output),
+        None => Response::text(404, "Not Found", format!("terminal `{}` not found", term)),
+    }
+}
+
+/// Compare without returning early, so that the time taken doesn't tell how much of it was right
+fn same_token(given: &str, token: &str) -> bool {
+    let (given, token) = (given.as_bytes(), token.as_bytes());
+
+    given.len() == token.len()
+        && given
+            .iter()
+            .zip(token)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+pub(crate) struct Response {
+    pub status: u16,
+    pub reason: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn text(status: u16, reason: &'static str, body: String) -> Self {
+        Response {
+            status,
+            reason,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+}
+
+impl std::fmt::Display for Response {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "HTTP/1.1 {} {}\r\n", self.status, self.reason)?;
+        if self.status == 401 {
+            f.write_str("WWW-Authenticate: Bearer\r\n")?;
+        }
+        write!(
+            f,
+            "Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.content_type,
+            self.body.len(),
+            self.body
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Fixed(HashMap<&'static str, &'static str>);
+
+    #[async_trait]
+    impl Terminals for Fixed {
+        async fn snapshot(&self, term: &str) -> Option<String> {
+            self.0.get(term).map(|output| output.to_string())
+        }
+    }
+
+    #[test]
+    fn reports_every_misconfigured_variable() {
+        std::env::set_var("HTTP_PORT", "eighty");
+        std::env::set_var("HTTP_HOST", "localhost");
+        std::env::remove_var("HTTP_TOKEN");
+
+        assert_eq!(
+            Settings::try_from_env().err(),
+            Some(ConfigError(vec![
+                Misconfigured::Invalid {
+                    var: "HTTP_PORT",
+                    expected: "a port number",
+                },
+                Misconfigured::Invalid {
+                    var: "HTTP_HOST",
+                    expected: "an IP address",
+                },
+                Misconfigured::Invalid {
+                    var: "HTTP_TOKEN",
+                    expected: "set along with HTTP_PORT",
+                },
+            ]))
+        );
+
+        std::env::set_var("HTTP_PORT", "8080");
+        std::env::remove_var("HTTP_HOST");
+        std::env::set_var("HTTP_TOKEN", "secret");
+
+        let settings = Settings::try_from_env().unwrap().unwrap();
+        assert_eq!(settings.address, SocketAddr::new(DEFAULT_HOST, 8080));
+        assert_eq!(settings.token, "secret");
+
+        std::env::remove_var("HTTP_PORT");
+        assert!(Settings::try_from_env().unwrap().is_none());
+    }
+
+    async fn request(terminals: &Fixed, head: &str) -> (u16, String) {
+        let response = respond(head, "secret", terminals).await;
+        (response.status, response.body)
+    }
+
+    #[tokio::test]
+    async fn serves_terminals_to_those_with_the_token() {
+        let terminals = Fixed(vec![("sh", "$ ls\nREADME.md")].into_iter().collect());
+
+        assert_eq!(
+            request(
+                &terminals,
+                "GET /terminals/sh HTTP/1.1\r\nAuthorization: Bearer secret"
+            )
+            .await,
+            (200, String::from("$ ls\nREADME.md"))
+        );
+        assert_eq!(
+            request(
+                &terminals,
+                "GET /terminals/sh HTTP/1.1\r\nauthorization: Bearer secret\r\nAccept: application/json"
+            )
+            .await,
+            (
+                200,
+                String::from(r#"{"output":"$ ls\nREADME.md","terminal":"sh"}"#)
+            )
+        );
+        assert_eq!(
+            request(
+                &terminals,
+                "GET /terminals/zsh HTTP/1.1\r\nAuthorization: Bearer secret"
+            )
+            .await
+            .0,
+            404
+        );
+        assert_eq!(
+            request(&terminals, "GET / HTTP/1.1\r\nAuthorization: Bearer secret")
+                .await
+                .0,
+            404
+        );
+        assert_eq!(
+            request(
+                &terminals,
+                "POST /terminals/sh HTTP/1.1\r\nAuthorization: Bearer secret"
+            )
+            .await
+            .0,
+            405
+        );
+
+        for head in &[
+            "GET /terminals/sh HTTP/1.1",
+            "GET /terminals/sh HTTP/1.1\r\nAuthorization: Bearer secre",
+            "GET /terminals/sh HTTP/1.1\r\nAuthorization: secret",
+            "POST /terminals/zsh HTTP/1.1\r\nAuthorization: Bearer wrong",
+        ] {
+            assert_eq!(request(&terminals, head).await.0, 401, "{}", head);
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_over_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let terminals = Fixed(vec![("sh", "hi")].into_iter().collect());
+        tokio::spawn(accept(
+            listener,
+            Arc::new(String::from("secret")),
+            Arc::new(terminals),
+        ));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /terminals/sh HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nhi"), "{}", response);
+    }
+}
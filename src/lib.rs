@@ -0,0 +1,19 @@
+//! Run shell commands and capture their output as frames of a fixed size window.
+//!
+//! The `terminal` module has no knowledge of Discord, implement `terminal::Handler` to render the
+//! frames anywhere. The Discord frontend lives in `discord` behind the default `discord` feature, and
+//! a read-only HTTP endpoint for the output of its terminals in `http` behind the `http` feature.
+//! `metrics` counts what the terminals are doing, and serves it behind the `metrics` feature.
+
+#[cfg(any(feature = "discord", feature = "http"))]
+pub mod config;
+#[cfg(feature = "discord")]
+pub mod discord;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod logfile;
+pub mod metrics;
+pub mod parser;
+pub mod persist;
+pub mod session;
+pub mod terminal;
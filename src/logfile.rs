@@ -0,0 +1,141 @@
+//! A terminal handler that appends every line of output to a file, so that there's a complete
+//! record of what ran even though the window only keeps the latest lines.
+
+use super::terminal::{self, Exit, Job, Stream, Window};
+use async_trait::async_trait;
+use log::warn;
+use std::io;
+use std::path::PathBuf;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+pub struct LogFile {
+    path: PathBuf,
+    // gone once the terminal has exited or writing failed
+    writer: Option<BufWriter<File>>,
+}
+
+impl LogFile {
+    /// Open the file at `path` to append to, creating it if it doesn't exist
+    pub async fn create(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        Ok(LogFile {
+            path,
+            writer: Some(BufWriter::new(file)),
+        })
+    }
+
+    async fn write(&mut self, line: &str) {
+        if let Some(writer) = &mut self.writer {
+            let written = writer.write_all(line.as_bytes()).await;
+            if let Err(e) = written.and(writer.write_all(b"\n").await) {
+                warn!("stopped logging to {}: {}", self.path.display(), e);
+                self.writer = None;
+            }
+        }
+    }
+
+    async fn flush(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.flush().await {
+                warn!("stopped logging to {}: {}", self.path.display(), e);
+                self.writer = None;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl terminal::Handler for LogFile {
+    async fn update(&mut self, _window: &mut Window) {}
+
+    async fn on_line(&mut self, _stream: Stream, line: &str) {
+        self.write(line).await;
+    }
+
+    async fn on_command_start(&mut self, _window: &mut Window, job: &Job) {
+        for (i, part) in job.line.lines().enumerate() {
+            let prefix = if i == 0 { "$ " } else { "> " };
+            self.write(&format!("{}{}", prefix, part)).await;
+        }
+    }
+
+    async fn on_command_exit(&mut self, _window: &mut Window, _exit: Exit) {
+        self.flush().await;
+    }
+
+    async fn on_terminal_exit(&mut self, _window: &mut Window) {
+        self.flush().await;
+        // closes the file
+        self.writer = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use terminal::{Command, Config, Runner};
+    use tokio::process;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn logs_every_line_of_output() {
+        let path = std::env::temp_dir().join(format!("termview-log-{}", std::process::id()));
+        std::fs::write(&path, "earlier\n").unwrap();
+
+        let config = Config {
+            height: 1,
+            timeout: None,
+            cooldown: Duration::from_millis(50),
+            max_cooldown: Duration::from_millis(50),
+            scrollback: 1,
+            idle_timeout: None,
+            heartbeat: None,
+            dedup: false,
+            width: None,
+            tui: false,
+            timestamps: false,
+            hex: false,
+            encoding: encoding_rs::UTF_8,
+            limits: terminal::ResourceLimits::default(),
+        };
+        let log = LogFile::create(path.clone()).await.unwrap();
+        let (runner, sender) = Runner::init(log, config);
+        tokio::spawn(runner.listen());
+
+        let mut exec = process::Command::new("sh");
+        exec.arg("-c").arg("seq 3");
+        let job = Job {
+            line: String::from("seq 3"),
+            exec,
+            requested_by: None,
+            stdin: None,
+        };
+        sender.send(Command::Run(Box::new(job))).await.unwrap();
+
+        // the job is queued until the runner gets to it
+        loop {
+            let (reply, status) = oneshot::channel();
+            sender.send(Command::Status(reply)).await.unwrap();
+            let status = status.await.unwrap();
+            if !status.running && status.queued == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let (removed, gone) = oneshot::channel();
+        sender.send(Command::Remove(removed)).await.unwrap();
+        gone.await.unwrap();
+
+        let logged = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(logged, "earlier\n$ seq 3\n1\n2\n3\n");
+    }
+}
@@ -1,23 +1,61 @@
+use discord_termview::discord;
+use log::{error, info};
 use serenity::prelude::*;
-
-pub mod discord;
-pub mod parser;
-pub mod session;
-pub mod terminal;
+use tokio::signal::unix::{signal, SignalKind};
 
 #[tokio::main]
 async fn main() {
+    // quiet unless asked otherwise through `RUST_LOG`
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
     let token =
         std::env::var("DISCORD_TOKEN").expect("DISCORD_TOKEN does not contain a valid token");
 
     let settings = discord::Settings::parse();
 
+    let handler = discord::Handler::new(settings);
+    let shutdown = handler.shutdown_handle();
+
+    #[cfg(feature = "http")]
+    if let Some(settings) = discord_termview::http::Settings::parse() {
+        let snapshots = handler.snapshots();
+        tokio::spawn(async move {
+            if let Err(e) = discord_termview::http::serve(settings, snapshots).await {
+                error!("failed to serve terminals over http: {}", e);
+            }
+        });
+    }
+
+    #[cfg(feature = "metrics")]
+    if let Some(settings) = discord_termview::metrics::Settings::parse() {
+        tokio::spawn(async move {
+            if let Err(e) = discord_termview::metrics::serve(settings).await {
+                error!("failed to serve metrics: {}", e);
+            }
+        });
+    }
+
     let mut client = Client::builder(&token)
-        .event_handler(discord::Handler::new(settings))
+        .event_handler(handler)
         .await
         .expect("error creating client");
 
-    if let Err(e) = client.start().await {
-        eprintln!("Client error: {:?}", e);
+    tokio::select! {
+        result = client.start() => if let Err(e) = result {
+            error!("Client error: {:?}", e);
+        },
+        _ = stop_signal() => info!("shutting down"),
+    }
+
+    shutdown.run().await;
+}
+
+/// Wait for the bot to be asked to stop
+async fn stop_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
     }
 }
@@ -0,0 +1,293 @@
+//! Counters of what the terminals are doing, in the Prometheus text format.
+//!
+//! The counters are plain atomics that are always kept. They're only served at `GET /metrics`
+//! with the `metrics` feature, and only when `METRICS_PORT` is set.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+
+static METRICS: Metrics = Metrics::new();
+
+struct Metrics {
+    commands_started: AtomicU64,
+    commands_finished: AtomicU64,
+    // the total time spent by the finished commands
+    command_micros: AtomicU64,
+    frames_rendered: AtomicU64,
+    render_errors: AtomicU64,
+    terminals: AtomicI64,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            commands_started: AtomicU64::new(0),
+            commands_finished: AtomicU64::new(0),
+            command_micros: AtomicU64::new(0),
+            frames_rendered: AtomicU64::new(0),
+            render_errors: AtomicU64::new(0),
+            terminals: AtomicI64::new(0),
+        }
+    }
+
+    fn render(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let seconds = Duration::from_micros(load(&self.command_micros)).as_secs_f64();
+
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(&str, String)]| {
+            writeln!(text, "# HELP {} {}", name, help).unwrap();
+            writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+            for (suffix, value) in samples {
+                writeln!(text, "{}{} {}", name, suffix, value).unwrap();
+            }
+        };
+
+        metric(
+            "termview_commands_started_total",
+            "counter",
+            "Commands that were started",
+            &[("", load(&self.commands_started).to_string())],
+        );
+        metric(
+            "termview_command_duration_seconds",
+            "summary",
+            "How long the finished commands ran for",
+            &[
+                ("_sum", seconds.to_string()),
+                ("_count", load(&self.commands_finished).to_string()),
+            ],
+        );
+        metric(
+            "termview_frames_rendered_total",
+            "counter",
+            "Frames that were rendered to their message",
+            &[("", load(&self.frames_rendered).to_string())],
+        );
+        metric(
+            "termview_render_errors_total",
+            "counter",
+            "Frames that failed to render, including the rate limited ones",
+            &[("", load(&self.render_errors).to_string())],
+        );
+        metric(
+            "termview_terminals",
+            "gauge",
+            "Terminals that are open",
+            &[("", self.terminals.load(Ordering::Relaxed).to_string())],
+        );
+
+        text
+    }
+}
+
+pub fn command_started() {
+    METRICS.commands_started.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn command_finished(duration: Duration) {
+    METRICS.commands_finished.fetch_add(1, Ordering::Relaxed);
+    METRICS
+        .command_micros
+        .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+}
+
+pub fn frame_rendered() {
+    METRICS.frames_rendered.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn render_failed() {
+    METRICS.render_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counts a terminal as open until it's dropped
+pub struct OpenTerminal(());
+
+pub fn open_terminal() -> OpenTerminal {
+    METRICS.terminals.fetch_add(1, Ordering::Relaxed);
+    OpenTerminal(())
+}
+
+impl Drop for OpenTerminal {
+    fn drop(&mut self) {
+        METRICS.terminals.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Every metric as it currently stands
+pub fn render() -> String {
+    METRICS.render()
+}
+
+#[cfg(feature = "metrics")]
+pub use server::{serve, Settings};
+
+#[cfg(feature = "metrics")]
+mod server {
+    use crate::config::{ConfigError, Env};
+    use crate::http::{read_head, Response, REQUEST_TIMEOUT};
+    use log::{debug, info, warn};
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    pub struct Settings {
+        pub address: SocketAddr,
+    }
+
+    impl Settings {
+        /// `None` unless `METRICS_PORT` is set
+        pub fn parse() -> Option<Self> {
+            Self::try_from_env().unwrap_or_else(|err| panic!("{}", err))
+        }
+
+        /// Read the settings from the environment, reporting every misconfigured variable
+        pub fn try_from_env() -> Result<Option<Self>, ConfigError> {
+            if std::env::var_os("METRICS_PORT").is_none() {
+                return Ok(None);
+            }
+
+            let mut env = Env::default();
+
+            let port = env.read("METRICS_PORT", "a port number", |port| port.parse().ok());
+            let host = env
+                .read("METRICS_HOST", "an IP address", |host| host.parse().ok())
+                .unwrap_or(DEFAULT_HOST);
+
+            match port {
+                Some(port) if env.misconfigured.is_empty() => Ok(Some(Settings {
+                    address: SocketAddr::new(host, port),
+                })),
+                _ => Err(ConfigError(env.misconfigured)),
+            }
+        }
+    }
+
+    /// Serve the metrics until the program exits, only failing if the address can't be listened on
+    pub async fn serve(settings: Settings) -> io::Result<()> {
+        let listener = TcpListener::bind(settings.address).await?;
+        info!("serving metrics on {}", settings.address);
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("failed to accept metrics connection: {}", e);
+                    continue;
+                }
+            };
+
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream).await {
+                    debug!("metrics connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(mut stream: TcpStream) -> io::Result<()> {
+        let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "request took too long"))??;
+
+        let response = match head {
+            Some(head) => respond(&head),
+            None => Response::text(400, "Bad Request", String::from("malformed request")),
+        };
+
+        stream.write_all(response.to_string().as_bytes()).await?;
+        stream.shutdown().await
+    }
+
+    fn respond(head: &str) -> Response {
+        let mut request_line = head.split("\r\n").next().unwrap_or("").split(' ');
+        let method = request_line.next().unwrap_or("");
+        let path = request_line.next().unwrap_or("").split('?').next();
+
+        match (method, path) {
+            ("GET", Some("/metrics")) => Response {
+                status: 200,
+                reason: "OK",
+                content_type: "text/plain; version=0.0.4",
+                body: super::render(),
+            },
+            (_, Some("/metrics")) => Response::text(
+                405,
+                "Method Not Allowed",
+                String::from("only GET is allowed"),
+            ),
+            _ => Response::text(404, "Not Found", String::from("not found")),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::config::Misconfigured;
+
+        #[test]
+        fn reports_every_misconfigured_variable() {
+            std::env::set_var("METRICS_PORT", "99999");
+            std::env::set_var("METRICS_HOST", "localhost");
+
+            assert_eq!(
+                Settings::try_from_env().err(),
+                Some(ConfigError(vec![
+                    Misconfigured::Invalid {
+                        var: "METRICS_PORT",
+                        expected: "a port number",
+                    },
+                    Misconfigured::Invalid {
+                        var: "METRICS_HOST",
+                        expected: "an IP address",
+                    },
+                ]))
+            );
+
+            std::env::set_var("METRICS_PORT", "9100");
+            std::env::remove_var("METRICS_HOST");
+            let settings = Settings::try_from_env().unwrap().unwrap();
+            assert_eq!(settings.address, SocketAddr::new(DEFAULT_HOST, 9100));
+
+            std::env::remove_var("METRICS_PORT");
+            assert!(Settings::try_from_env().unwrap().is_none());
+        }
+
+        #[test]
+        fn only_serves_the_metrics() {
+            assert_eq!(respond("GET /metrics HTTP/1.1\r\nHost: a").status, 200);
+            assert_eq!(respond("GET /metrics?x=1 HTTP/1.1").status, 200);
+            assert_eq!(respond("POST /metrics HTTP/1.1").status, 405);
+            assert_eq!(respond("GET / HTTP/1.1").status, 404);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_in_the_prometheus_format() {
+        let metrics = Metrics::new();
+        metrics.commands_started.store(3, Ordering::Relaxed);
+        metrics.commands_finished.store(2, Ordering::Relaxed);
+        metrics.command_micros.store(1_500_000, Ordering::Relaxed);
+        metrics.terminals.store(1, Ordering::Relaxed);
+
+        let text = metrics.render();
+
+        assert!(text.contains(
+            "# TYPE termview_commands_started_total counter\ntermview_commands_started_total 3\n"
+        ));
+        assert!(text.contains(
+            "termview_command_duration_seconds_sum 1.5\ntermview_command_duration_seconds_count 2\n"
+        ));
+        assert!(text.contains("termview_render_errors_total 0\n"));
+        assert!(text.contains("# TYPE termview_terminals gauge\ntermview_terminals 1\n"));
+    }
+}
@@ -1,38 +1,506 @@
+use super::session::Overflow;
+use super::terminal::StderrMode;
+use encoding_rs::Encoding;
+use regex::Regex;
 use std::fmt;
 
 const HEIGHT_LIMIT: usize = 1000;
+/// Columns, as `tui` terminals draw onto a screen of every row at this width
+const WIDTH_LIMIT: usize = 1000;
+
+/// The height of terminals created without one
+pub const DEFAULT_HEIGHT: usize = 20;
+const SCROLLBACK_LIMIT: usize = 10_000;
+/// Longer languages than any code block highlights would only eat into the room for output
+const LANG_LIMIT: usize = 32;
+/// Seconds, as every run of a scheduled command redraws the terminal
+const MIN_INTERVAL: u64 = 5;
+/// Every command `parse` knows, which mistyped ones are compared against
+const COMMANDS: &[&str] = &[
+    "new",
+    "remove",
+    "run",
+    "scroll",
+    "stderr",
+    "cancel",
+    "cancelall",
+    "stop",
+    "list",
+    "clear",
+    "move",
+    "rename",
+    "tee",
+    "resize",
+    "queue",
+    "ps",
+    "history",
+    "pause",
+    "resume",
+    "every",
+    "input",
+    "cd",
+    "follow",
+    "env",
+    "grep",
+];
+const ADMIN_COMMANDS: &[&str] = &["reload", "killall", "setcooldown", "setprefix"];
+/// Mistyped commands further from every command than this get no suggestion
+const MAX_SUGGESTION_DISTANCE: usize = 2;
 
 /// A syntatically valid parsed user command
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Command {
-    New { height: usize, private: bool },
+    New(Box<NewTerminal>),
     Remove,
     Run(String),
+    /// Run the latest command again
+    RunLast,
+    /// Run the command every this many seconds, until stopped
+    Every {
+        secs: u64,
+        command: String,
+    },
+    /// Stop running the scheduled command
+    Stop,
+    History,
+    Input(String),
+    /// Cancel the running command, the queued commands still run
+    Cancel,
+    /// Cancel the terminal along with every one of its slots, dropping all of their queues
+    CancelAll,
+    List,
+    Cd(String),
+    /// Show the lines appended to a file, like `tail -f`
+    Follow(String),
+    Clear,
+    Queue,
+    /// List the processes of the running command
+    Processes,
+    /// Set an environment variable for the commands run in the terminal
+    Env(String, String),
+    UnsetEnv(String),
+    ListEnv,
+    /// Scroll the window, by a full window if the amount of lines isn't given
+    Scroll {
+        up: bool,
+        lines: Option<usize>,
+    },
+    Rename(String),
+    /// Mirror the output to another terminal as well, or stop if there's none
+    Tee(Option<String>),
+    /// Change how many lines of the terminal are visible
+    Resize(usize),
+    /// Move the terminal to the channel the command was sent in
+    Move,
+    /// Only show output matching the pattern, or everything again if there's no pattern
+    Grep(Option<Pattern>),
+    /// Change how stderr is shown, right away for what's already in the window except that lines
+    /// dropped while it was off don't come back
+    Stderr(StderrMode),
+    /// Stop updating the message while the output keeps being read
+    Pause,
+    Resume,
+}
+
+impl Command {
+    /// The word the command is written with, `run` also covering code blocks
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::New(_) => "new",
+            Command::Remove => "remove",
+            Command::Run(_) => "run",
+            Command::RunLast => "!!",
+            Command::Every { .. } => "every",
+            Command::Stop => "stop",
+            Command::History => "history",
+            Command::Input(_) => "input",
+            Command::Cancel => "cancel",
+            Command::CancelAll => "cancelall",
+            Command::List => "list",
+            Command::Cd(_) => "cd",
+            Command::Follow(_) => "follow",
+            Command::Clear => "clear",
+            Command::Queue => "queue",
+            Command::Processes => "ps",
+            Command::Env(..) | Command::UnsetEnv(_) | Command::ListEnv => "env",
+            Command::Scroll { .. } => "scroll",
+            Command::Rename(_) => "rename",
+            Command::Tee(_) => "tee",
+            Command::Resize(_) => "resize",
+            Command::Move => "move",
+            Command::Grep(_) => "grep",
+            Command::Stderr(_) => "stderr",
+            Command::Pause => "pause",
+            Command::Resume => "resume",
+        }
+    }
+}
+
+/// A command about the bot itself, which only its owner may use
+#[derive(Debug, PartialEq)]
+pub enum Admin {
+    /// Read the settings again
+    Reload,
+    /// Remove every terminal, no matter who created it
+    KillAll,
+    /// Seconds between the frames of the terminals created from now on
+    SetCooldown(u64),
+    /// What commands sent where this one was have to start with from now on
+    SetPrefix(String),
+}
+
+/// A compiled regular expression, patterns are equal if they were written the same way
+#[derive(Debug, Clone)]
+pub struct Pattern(pub Regex);
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+/// The options given to the `new` command
+#[derive(Debug, PartialEq)]
+pub struct NewTerminal {
+    /// Missing unless given, so that it can fall back to the defaults of the channel
+    pub height: Option<usize>,
+    /// Lines longer than this wrap onto the next line of the window
+    pub width: Option<usize>,
+    pub private: bool,
+    pub shell: Option<String>,
+    pub timeout: Option<u64>,
+    pub dir: Option<String>,
+    pub raw: bool,
+    /// Show the colors of the output in an `ansi` code block
+    pub color: bool,
+    /// Draw the output onto a screen that commands can redraw in place, rather than appending it
+    pub tui: bool,
+    /// Prefix each line with the time since its command started
+    pub timestamps: bool,
+    /// Show binary looking lines as a hexdump
+    pub hex: bool,
+    /// What the output is decoded from, UTF-8 unless given
+    pub encoding: Option<&'static Encoding>,
+    pub dedup: bool,
+    pub lang: Option<String>,
+    pub cooldown: Option<u64>,
+    pub max_cooldown: Option<u64>,
+    pub scrollback: Option<usize>,
+    pub overflow: Overflow,
+    /// Append every line of output to the file at this path
+    pub log: Option<String>,
+    pub prompt: Option<String>,
+    /// The first command to run once the terminal has been created
+    pub run: Option<String>,
 }
 
 /// Attempt to parse `raw` to a command
 pub fn parse(raw: &str) -> Result<Command, Error> {
     if raw.starts_with('`') {
-        return parse_run(raw);
+        return parse_run(raw).map(Command::Run);
     }
 
-    let mut iter = raw.split(' ');
+    // a line break also ends the header, so a code block may start on the line after `run`
+    let mut iter = raw.split(&[' ', '\n'][..]);
 
-    let header = iter.next().ok_or(Error::NoAction)?;
+    let header = iter
+        .next()
+        .filter(|header| !header.is_empty())
+        .ok_or(Error::NoAction)?;
 
     match header {
-        "new" => parse_new(iter),
+        pat @ "new" => parse_new(&raw[pat.len()..]),
         "remove" => Ok(parse_remove(iter)),
-        pat @ "run" => Ok(Command::Run(raw[pat.len() + 1..].trim().to_string())),
-        faulty => Err(Error::UnrecognizedCommand(faulty.to_string())),
+        "scroll" => parse_scroll(iter),
+        "stderr" => parse_stderr(iter),
+        "cancel" => Ok(Command::Cancel),
+        "cancelall" => Ok(Command::CancelAll),
+        "stop" => Ok(Command::Stop),
+        "list" => Ok(Command::List),
+        "clear" => Ok(Command::Clear),
+        "move" => Ok(Command::Move),
+        "rename" => parse_rename(iter),
+        "tee" => Ok(parse_tee(iter)),
+        "resize" => parse_resize(iter),
+        "queue" => Ok(Command::Queue),
+        "ps" => Ok(Command::Processes),
+        "!!" => Ok(Command::RunLast),
+        "history" => Ok(Command::History),
+        "pause" => Ok(Command::Pause),
+        "resume" => Ok(Command::Resume),
+        pat @ "run" => parse_run_words(&raw[pat.len()..]).map(Command::Run),
+        pat @ "every" => parse_every(&raw[pat.len()..]),
+        pat @ "input" => Ok(parse_input(&raw[pat.len()..])),
+        pat @ "cd" => parse_cd(&raw[pat.len()..]),
+        pat @ "follow" => parse_follow(&raw[pat.len()..]),
+        pat @ "env" => parse_env(&raw[pat.len()..]),
+        pat @ "grep" => parse_grep(&raw[pat.len()..]),
+        faulty => Err(unrecognized(faulty, COMMANDS)),
     }
 }
 
+/// Attempt to parse `raw`, what follows `admin`, to an admin command
+pub fn parse_admin(raw: &str) -> Result<Admin, Error> {
+    let mut iter = raw.split_whitespace();
+
+    match iter.next().ok_or(Error::NoAction)? {
+        "reload" => Ok(Admin::Reload),
+        "killall" => Ok(Admin::KillAll),
+        "setcooldown" => iter
+            .next()
+            .ok_or(Error::MissingArgument("seconds after 'setcooldown'"))?
+            .parse()
+            .map(Admin::SetCooldown)
+            .map_err(|_| Error::InvalidNumber),
+        "setprefix" => iter
+            .next()
+            .map(|prefix| Admin::SetPrefix(prefix.to_string()))
+            .ok_or(Error::MissingArgument("prefix after 'setprefix'")),
+        faulty => Err(unrecognized(faulty, ADMIN_COMMANDS)),
+    }
+}
+
+fn unrecognized(got: &str, known: &[&'static str]) -> Error {
+    Error::UnrecognizedCommand {
+        got: got.to_string(),
+        suggestion: closest(got, known),
+    }
+}
+
+/// The command in `known` that `got` is most likely a typo of, if any is close enough
+///
+/// A command is only suggested if fewer of its letters have to change than it has, so that
+/// every short command isn't suggested for a single letter.
+fn closest(got: &str, known: &[&'static str]) -> Option<&'static str> {
+    known
+        .iter()
+        .map(|&command| (command, edit_distance(got, command)))
+        .filter(|&(command, distance)| {
+            distance <= MAX_SUGGESTION_DISTANCE && distance < command.chars().count()
+        })
+        .min_by_key(|&(_, distance)| distance)
+        .map(|(command, _)| command)
+}
+
+/// How many characters have to be inserted, removed or replaced to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // distances from the part of `a` seen so far to every prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, &cb) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
 /// parse the `run` command
-fn parse_run(raw: &str) -> Result<Command, Error> {
+fn parse_run(raw: &str) -> Result<String, Error> {
+    if let Some(fenced) = raw.strip_prefix("```") {
+        return parse_fenced_run(fenced);
+    }
+
     let ends_at = raw[1..].find('`').ok_or(Error::MissingEndToCodeBlock)?;
     let code = &raw[1..=ends_at];
-    Ok(Command::Run(code.to_string()))
+    Ok(code.to_string())
+}
+
+/// parse the `run` command given as a ```` ``` ```` code block, which may span multiple lines
+fn parse_fenced_run(raw: &str) -> Result<String, Error> {
+    let ends_at = raw.find("```").ok_or(Error::MissingEndToCodeBlock)?;
+    let mut code = &raw[..ends_at];
+
+    // like Discord, a single word on the first line is the language of the block
+    if let Some((first, rest)) = code.split_once('\n') {
+        if !first.is_empty() && !first.contains(char::is_whitespace) {
+            code = rest;
+        }
+    }
+
+    Ok(code.trim_matches('\n').to_string())
+}
+
+/// parse the `run` command when given as plain words, or as a code block following `run`
+fn parse_run_words(raw: &str) -> Result<String, Error> {
+    let cmd = raw.trim();
+    if cmd.is_empty() {
+        return Err(Error::MissingArgument("command after 'run'"));
+    }
+    if cmd.starts_with('`') {
+        return parse_run(cmd);
+    }
+    Ok(cmd.to_string())
+}
+
+/// parse the `every` command, such as `every 30s run uptime`
+fn parse_every(raw: &str) -> Result<Command, Error> {
+    let (interval, rest) = raw
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .ok_or(Error::MissingArgument("'run' after the interval"))?;
+
+    let secs = parse_interval(interval)?;
+    if secs < MIN_INTERVAL {
+        return Err(Error::IntervalTooShort(secs));
+    }
+
+    let command = rest
+        .trim_start()
+        .strip_prefix("run")
+        // so that a command such as `runner` isn't taken for `run ner`
+        .filter(|command| command.is_empty() || command.starts_with(char::is_whitespace))
+        .ok_or(Error::MissingArgument("'run' after the interval"))?;
+
+    Ok(Command::Every {
+        secs,
+        command: parse_run_words(command)?,
+    })
+}
+
+/// parse an interval such as `30s`, `5m` or `1h` to seconds, a plain number being seconds
+fn parse_interval(word: &str) -> Result<u64, Error> {
+    let (number, scale) = if let Some(number) = word.strip_suffix('s') {
+        (number, 1)
+    } else if let Some(number) = word.strip_suffix('m') {
+        (number, 60)
+    } else if let Some(number) = word.strip_suffix('h') {
+        (number, 60 * 60)
+    } else {
+        (word, 1)
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .ok_or(Error::InvalidNumber)
+}
+
+/// parse the `input` command
+fn parse_input(raw: &str) -> Command {
+    // only strip the seperating space, the rest of the whitespace is meant for the process
+    let text = raw.strip_prefix(' ').unwrap_or(raw);
+    Command::Input(text.to_string())
+}
+
+/// parse the `cd` command
+fn parse_cd(raw: &str) -> Result<Command, Error> {
+    let path = raw.trim();
+    if path.is_empty() {
+        return Err(Error::MissingArgument("path after 'cd'"));
+    }
+    Ok(Command::Cd(path.to_string()))
+}
+
+/// parse the `follow` command
+fn parse_follow(raw: &str) -> Result<Command, Error> {
+    let path = raw.trim();
+    if path.is_empty() {
+        return Err(Error::MissingArgument("path after 'follow'"));
+    }
+    Ok(Command::Follow(path.to_string()))
+}
+
+/// parse the `env` command, which is either `KEY=VALUE`, `-KEY` or nothing at all
+fn parse_env(raw: &str) -> Result<Command, Error> {
+    let raw = raw.trim();
+    if raw.is_empty() {
+        return Ok(Command::ListEnv);
+    }
+
+    if let Some(key) = raw.strip_prefix('-') {
+        if key.is_empty() {
+            return Err(Error::MissingArgument("variable name after 'env -'"));
+        }
+        return Ok(Command::UnsetEnv(key.to_string()));
+    }
+
+    match raw.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !key.contains(' ') => {
+            Ok(Command::Env(key.to_string(), value.to_string()))
+        }
+        _ => Err(Error::MissingArgument("KEY=VALUE after 'env'")),
+    }
+}
+
+/// parse the `scroll` command
+fn parse_scroll<'a>(mut iter: impl Iterator<Item = &'a str>) -> Result<Command, Error> {
+    let up = match iter.next() {
+        Some("up") => true,
+        Some("down") => false,
+        _ => return Err(Error::MissingArgument("'up' or 'down' after 'scroll'")),
+    };
+
+    let lines = iter
+        .find(|word| !word.is_empty())
+        .map(|lines| lines.parse().map_err(|_| Error::InvalidNumber))
+        .transpose()?;
+
+    Ok(Command::Scroll { up, lines })
+}
+
+/// parse the `stderr` command
+fn parse_stderr<'a>(mut iter: impl Iterator<Item = &'a str>) -> Result<Command, Error> {
+    match iter.find(|word| !word.is_empty()) {
+        Some("on") | Some("inline") => Ok(Command::Stderr(StderrMode::Inline)),
+        Some("off") => Ok(Command::Stderr(StderrMode::Off)),
+        Some("separate") => Ok(Command::Stderr(StderrMode::Separate)),
+        _ => Err(Error::MissingArgument(
+            "'on', 'off' or 'separate' after 'stderr'",
+        )),
+    }
+}
+
+/// parse the `resize` command
+fn parse_resize<'a>(mut iter: impl Iterator<Item = &'a str>) -> Result<Command, Error> {
+    let height = iter
+        .find(|word| !word.is_empty())
+        .ok_or(Error::MissingArgument("height after 'resize'"))?
+        .parse()
+        .map_err(|_| Error::InvalidNumber)?;
+
+    check_height(height)?;
+    Ok(Command::Resize(height))
+}
+
+/// Refuse heights that would show nothing, or more lines than a terminal may have
+pub fn check_height(height: usize) -> Result<(), Error> {
+    match height {
+        0 => Err(Error::InvalidNumber),
+        height if height > HEIGHT_LIMIT => Err(Error::HeightToLarge(height)),
+        _ => Ok(()),
+    }
+}
+
+/// parse the `grep` command
+fn parse_grep(raw: &str) -> Result<Command, Error> {
+    let pattern = raw.trim();
+    if pattern.is_empty() {
+        return Ok(Command::Grep(None));
+    }
+
+    Regex::new(pattern)
+        .map(|regex| Command::Grep(Some(Pattern(regex))))
+        .map_err(|e| Error::InvalidPattern(e.to_string()))
+}
+
+/// parse the `rename` command
+fn parse_rename<'a>(mut iter: impl Iterator<Item = &'a str>) -> Result<Command, Error> {
+    iter.find(|word| !word.is_empty())
+        .map(|name| Command::Rename(name.to_string()))
+        .ok_or(Error::MissingArgument("new name after 'rename'"))
+}
+
+fn parse_tee<'a>(mut iter: impl Iterator<Item = &'a str>) -> Command {
+    Command::Tee(iter.find(|word| !word.is_empty()).map(str::to_string))
 }
 
 /// parse the `remove` command
@@ -40,56 +508,905 @@ fn parse_remove<'a>(_iter: impl Iterator<Item = &'a str>) -> Command {
     Command::Remove
 }
 
-/// parse the `new` command
-fn parse_new<'a>(iter: impl Iterator<Item = &'a str>) -> Result<Command, Error> {
-    let mut height = 20;
+/// A word of the options of a command, with its quotes and escapes taken out
+struct Word {
+    text: String,
+    // where the word ends in the options
+    end: usize,
+    quoted: bool,
+}
+
+/// Splits options into words on spaces and line breaks
+///
+/// Like in a shell, quotes keep the spaces in a word and a backslash outside of single quotes
+/// escapes the character after it, so `dir="my files"` and `dir=my\ files` are the same word.
+struct Words<'a> {
+    raw: &'a str,
+    at: usize,
+}
+
+impl<'a> Words<'a> {
+    fn new(raw: &'a str) -> Self {
+        Words { raw, at: 0 }
+    }
+}
+
+impl<'a> Iterator for Words<'a> {
+    type Item = Result<Word, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.raw[self.at..];
+        let start = rest.find(|c| c != ' ' && c != '\n')?;
+
+        let mut chars = rest[start..].char_indices();
+        let mut text = String::new();
+        let mut quote = None;
+        let mut quoted = false;
+        let mut end = rest.len();
+
+        while let Some((i, c)) = chars.next() {
+            match (quote, c) {
+                (None, ' ') | (None, '\n') => {
+                    end = start + i;
+                    break;
+                }
+                (None, '\'') | (None, '"') => {
+                    quote = Some(c);
+                    quoted = true;
+                }
+                (Some(open), c) if c == open => quote = None,
+                (None, '\\') | (Some('"'), '\\') => match chars.next() {
+                    Some((_, escaped)) => text.push(escaped),
+                    None => text.push(c),
+                },
+                (_, c) => text.push(c),
+            }
+        }
+
+        if quote.is_some() {
+            self.at = self.raw.len();
+            return Some(Err(Error::MissingEndToQuote));
+        }
+
+        self.at += end;
+        Some(Ok(Word {
+            text,
+            end: self.at,
+            quoted,
+        }))
+    }
+}
+
+/// parse the `new` command, whose options may be followed by `run` and the first command to run
+fn parse_new(options: &str) -> Result<Command, Error> {
+    let mut words = Words::new(options);
+    let mut run = None;
+    let mut height = None;
+    let mut width = None;
     let mut private = false;
+    let mut shell = None;
+    let mut timeout = None;
+    let mut dir = None;
+    let mut raw = false;
+    let mut color = false;
+    let mut tui = false;
+    let mut timestamps = false;
+    let mut hex = false;
+    let mut encoding = None;
+    let mut dedup = false;
+    let mut lang = None;
+    let mut cooldown = None;
+    let mut max_cooldown = None;
+    let mut scrollback = None;
+    let mut overflow = Overflow::Pages;
+    let mut log = None;
+    let mut prompt = None;
 
-    for word in iter {
-        if word.starts_with("height") {
-            height = word
-                .get(7..)
-                .ok_or(Error::MissingArgument("int after 'height'"))
-                .and_then(|s| s.parse().map_err(|_| Error::InvalidNumber))?;
+    while let Some(word) = words.next() {
+        let word = word?;
+
+        // everything after it is the command, which is left as it was typed
+        if word.text == "run" && !word.quoted {
+            run = Some(parse_run_words(&options[word.end..])?);
+            break;
+        }
+
+        let word = word.text.as_str();
+
+        // accepts `height=20`, `height 20` and `height20`
+        if let Some(value) = word.strip_prefix("height") {
+            let next;
+            let value = match value.strip_prefix('=') {
+                Some(value) => value,
+                None if value.is_empty() => {
+                    next = words.next().transpose()?.map(|word| word.text);
+                    next.as_deref().unwrap_or("")
+                }
+                None => value,
+            };
+
+            if value.is_empty() {
+                return Err(Error::MissingArgument("int after 'height'"));
+            }
+            height = Some(value.parse().map_err(|_| Error::InvalidNumber)?);
         }
 
         if word == "private" {
             private = true;
         }
+
+        if word == "raw" {
+            raw = true;
+        }
+
+        if word == "color" {
+            color = true;
+        }
+
+        if word == "tui" {
+            tui = true;
+        }
+
+        if word == "timestamps" {
+            timestamps = true;
+        }
+
+        if word == "hex" {
+            hex = true;
+        }
+
+        if word == "dedup" {
+            dedup = true;
+        }
+
+        if let Some(name) = argument(word, "shell", "name after 'shell='")? {
+            shell = Some(name.to_string());
+        }
+
+        if let Some(secs) = argument(word, "timeout", "seconds after 'timeout='")? {
+            timeout = Some(secs.parse().map_err(|_| Error::InvalidNumber)?);
+        }
+
+        if let Some(path) = argument(word, "dir", "path after 'dir='")? {
+            dir = Some(path.to_string());
+        }
+
+        if let Some(name) = argument(word, "lang", "language after 'lang='")? {
+            if !is_language(name) {
+                return Err(Error::InvalidLanguage(name.to_string()));
+            }
+            lang = Some(name.to_string());
+        }
+
+        if let Some(columns) = argument(word, "width", "int after 'width='")? {
+            match columns.parse() {
+                Ok(0) | Err(_) => return Err(Error::InvalidNumber),
+                Ok(columns) => width = Some(columns),
+            }
+        }
+
+        if let Some(secs) = argument(word, "cooldown", "seconds after 'cooldown='")? {
+            cooldown = Some(secs.parse().map_err(|_| Error::InvalidNumber)?);
+        }
+
+        if let Some(secs) = argument(word, "max_cooldown", "seconds after 'max_cooldown='")? {
+            max_cooldown = Some(secs.parse().map_err(|_| Error::InvalidNumber)?);
+        }
+
+        if let Some(lines) = argument(word, "scrollback", "int after 'scrollback='")? {
+            scrollback = Some(lines.parse().map_err(|_| Error::InvalidNumber)?);
+        }
+
+        if let Some(mode) = argument(word, "overflow", "'pages' or 'file' after 'overflow='")? {
+            overflow = match mode {
+                "pages" => Overflow::Pages,
+                "file" => Overflow::File,
+                _ => return Err(Error::InvalidOverflow(mode.to_string())),
+            };
+        }
+
+        if let Some(name) = argument(word, "encoding", "name after 'encoding='")? {
+            // lines are split on `\n` bytes before they're decoded, which only works out if the
+            // encoding has ASCII in common with UTF-8
+            let found = Encoding::for_label(name.as_bytes())
+                .filter(|encoding| encoding.is_ascii_compatible())
+                .ok_or_else(|| Error::UnknownEncoding(name.to_string()))?;
+            encoding = Some(found);
+        }
+
+        if let Some(path) = argument(word, "log", "path after 'log='")? {
+            log = Some(path.to_string());
+        }
+
+        if let Some(text) = argument(word, "prompt", "text after 'prompt='")? {
+            prompt = Some(text.to_string());
+        }
+    }
+
+    if let Some(height) = height {
+        check_height(height)?;
     }
 
-    if height > HEIGHT_LIMIT {
-        return Err(Error::HeightToLarge(height));
+    if let Some(width) = width.filter(|&width| width > WIDTH_LIMIT) {
+        return Err(Error::WidthToLarge(width));
     }
 
-    Ok(Command::New { height, private })
+    if let Some(scrollback) = scrollback.filter(|&lines| lines > SCROLLBACK_LIMIT) {
+        return Err(Error::ScrollbackToLarge(scrollback));
+    }
+
+    Ok(Command::New(Box::new(NewTerminal {
+        height,
+        width,
+        private,
+        shell,
+        timeout,
+        dir,
+        raw,
+        color,
+        tui,
+        timestamps,
+        hex,
+        encoding,
+        dedup,
+        lang,
+        cooldown,
+        max_cooldown,
+        scrollback,
+        overflow,
+        log,
+        prompt,
+        run,
+    })))
+}
+
+/// Get the value of `word` if it's a `key=value` argument for `key`
+fn argument<'a>(word: &'a str, key: &str, missing: &'static str) -> Result<Option<&'a str>, Error> {
+    match word
+        .strip_prefix(key)
+        .and_then(|rest| rest.strip_prefix('='))
+    {
+        Some("") => Err(Error::MissingArgument(missing)),
+        value => Ok(value),
+    }
+}
+
+/// Whether `name` could be the language of a code block, such as `rust`, `c++` or `f#`
+fn is_language(name: &str) -> bool {
+    name.len() <= LANG_LIMIT
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '#'))
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum Error {
     NoAction,
-    UnrecognizedCommand(String),
+    UnrecognizedCommand {
+        got: String,
+        /// The known command that was most likely meant
+        suggestion: Option<&'static str>,
+    },
     MissingArgument(&'static str),
     HeightToLarge(usize),
+    WidthToLarge(usize),
+    ScrollbackToLarge(usize),
+    IntervalTooShort(u64),
     InvalidNumber,
     InvalidBool,
+    InvalidPattern(String),
+    InvalidOverflow(String),
+    InvalidLanguage(String),
+    UnknownEncoding(String),
     MissingEndToCodeBlock,
+    MissingEndToQuote,
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::NoAction => f.write_str("no command was supplied"),
-            Error::UnrecognizedCommand(faulty) => write!(f, "{} is not a valid command", faulty),
+            Error::UnrecognizedCommand { got, suggestion } => {
+                write!(f, "{} is not a valid command", got)?;
+                match suggestion {
+                    Some(command) => write!(f, ", did you mean `{}`?", command),
+                    None => Ok(()),
+                }
+            }
             Error::MissingArgument(missing) => write!(f, "missing required argument '{}'", missing),
             Error::InvalidNumber => f.write_str("not a valid number"),
             Error::InvalidBool => f.write_str("not a valid boolean"),
+            Error::InvalidPattern(err) => write!(f, "not a valid pattern: {}", err),
+            Error::InvalidOverflow(mode) => write!(f, "{} is not 'pages' or 'file'", mode),
+            Error::InvalidLanguage(name) => write!(
+                f,
+                "{} is not a language, which is at most {} letters, digits, '+', '-' or '#'",
+                name, LANG_LIMIT
+            ),
+            Error::UnknownEncoding(name) => write!(
+                f,
+                "{} is not an encoding output can be read in, such as 'latin1' or 'shift_jis'",
+                name
+            ),
             Error::HeightToLarge(height) => write!(
                 f,
                 "height limit is {} but you tried to set it to {}",
                 HEIGHT_LIMIT, height
             ),
+            Error::WidthToLarge(width) => write!(
+                f,
+                "width limit is {} but you tried to set it to {}",
+                WIDTH_LIMIT, width
+            ),
+            Error::ScrollbackToLarge(lines) => write!(
+                f,
+                "scrollback limit is {} but you tried to set it to {}",
+                SCROLLBACK_LIMIT, lines
+            ),
+            Error::IntervalTooShort(secs) => write!(
+                f,
+                "commands may run at most every {}s but you tried every {}s",
+                MIN_INTERVAL, secs
+            ),
             Error::MissingEndToCodeBlock => f.write_str("missing end to code block"),
+            Error::MissingEndToQuote => f.write_str("missing end to quote"),
         }
     }
 }
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `new` without any options parses to
+    fn new_terminal() -> NewTerminal {
+        NewTerminal {
+            height: None,
+            width: None,
+            private: false,
+            shell: None,
+            timeout: None,
+            dir: None,
+            raw: false,
+            color: false,
+            tui: false,
+            timestamps: false,
+            hex: false,
+            encoding: None,
+            dedup: false,
+            lang: None,
+            cooldown: None,
+            max_cooldown: None,
+            scrollback: None,
+            overflow: Overflow::Pages,
+            log: None,
+            prompt: None,
+            run: None,
+        }
+    }
+
+    #[test]
+    fn parses_every() {
+        assert_eq!(
+            parse("every 30s run uptime -p"),
+            Ok(Command::Every {
+                secs: 30,
+                command: String::from("uptime -p"),
+            })
+        );
+        assert_eq!(
+            parse("every 2m run `df -h`"),
+            Ok(Command::Every {
+                secs: 120,
+                command: String::from("df -h"),
+            })
+        );
+        assert_eq!(parse("every 1s run date"), Err(Error::IntervalTooShort(1)));
+        assert_eq!(parse("every soon run date"), Err(Error::InvalidNumber));
+        assert_eq!(
+            parse("every 30s date"),
+            Err(Error::MissingArgument("'run' after the interval"))
+        );
+        assert_eq!(
+            parse("every 30s runner"),
+            Err(Error::MissingArgument("'run' after the interval"))
+        );
+    }
+
+    #[test]
+    fn parses_commands_without_arguments() {
+        assert_eq!(parse("remove"), Ok(Command::Remove));
+        assert_eq!(parse("cancel"), Ok(Command::Cancel));
+        assert_eq!(parse("cancelall"), Ok(Command::CancelAll));
+        assert_eq!(parse("stop"), Ok(Command::Stop));
+        assert_eq!(parse("ps"), Ok(Command::Processes));
+        assert_eq!(parse("list"), Ok(Command::List));
+        assert_eq!(parse("queue"), Ok(Command::Queue));
+        assert_eq!(parse("env"), Ok(Command::ListEnv));
+        assert_eq!(parse("!!"), Ok(Command::RunLast));
+        assert_eq!(parse("history"), Ok(Command::History));
+        assert_eq!(parse("pause"), Ok(Command::Pause));
+        assert_eq!(parse("resume"), Ok(Command::Resume));
+    }
+
+    #[test]
+    fn parses_commands_with_arguments() {
+        assert_eq!(
+            parse("run echo hi"),
+            Ok(Command::Run(String::from("echo hi")))
+        );
+        assert_eq!(parse("input  y"), Ok(Command::Input(String::from(" y"))));
+        assert_eq!(parse("cd src"), Ok(Command::Cd(String::from("src"))));
+        assert_eq!(parse("resize  30"), Ok(Command::Resize(30)));
+        assert_eq!(
+            parse("tee  logs"),
+            Ok(Command::Tee(Some(String::from("logs"))))
+        );
+        assert_eq!(parse("tee"), Ok(Command::Tee(None)));
+        assert_eq!(
+            parse("follow /var/log/app.log"),
+            Ok(Command::Follow(String::from("/var/log/app.log")))
+        );
+        assert_eq!(
+            parse("env KEY=a=b"),
+            Ok(Command::Env(String::from("KEY"), String::from("a=b")))
+        );
+        assert_eq!(
+            parse("env -KEY"),
+            Ok(Command::UnsetEnv(String::from("KEY")))
+        );
+        assert_eq!(
+            parse("scroll up 5"),
+            Ok(Command::Scroll {
+                up: true,
+                lines: Some(5)
+            })
+        );
+        assert_eq!(
+            parse("grep ^err"),
+            Ok(Command::Grep(Some(Pattern(Regex::new("^err").unwrap()))))
+        );
+        assert_eq!(parse("stderr on"), Ok(Command::Stderr(StderrMode::Inline)));
+        assert_eq!(parse("stderr off"), Ok(Command::Stderr(StderrMode::Off)));
+        assert_eq!(
+            parse("stderr separate"),
+            Ok(Command::Stderr(StderrMode::Separate))
+        );
+    }
+
+    #[test]
+    fn names_commands_as_they_are_written() {
+        for raw in &["new", "remove", "run ls", "history", "scroll up"] {
+            let name = raw.split(' ').next().unwrap();
+            assert_eq!(parse(raw).unwrap().name(), name);
+        }
+
+        assert_eq!(parse("`ls`").unwrap().name(), "run");
+        assert_eq!(parse("env KEY=1").unwrap().name(), "env");
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert_eq!(
+            parse("scroll sideways"),
+            Err(Error::MissingArgument("'up' or 'down' after 'scroll'"))
+        );
+        assert_eq!(parse("scroll up many"), Err(Error::InvalidNumber));
+        assert!(matches!(parse("grep ("), Err(Error::InvalidPattern(_))));
+        assert_eq!(
+            parse("env =value"),
+            Err(Error::MissingArgument("KEY=VALUE after 'env'"))
+        );
+        assert_eq!(
+            parse("stderr"),
+            Err(Error::MissingArgument(
+                "'on', 'off' or 'separate' after 'stderr'"
+            ))
+        );
+    }
+
+    #[test]
+    fn parses_code_blocks_as_run() {
+        assert_eq!(
+            parse("`echo hi`"),
+            Ok(Command::Run(String::from("echo hi")))
+        );
+        assert_eq!(parse("`echo hi"), Err(Error::MissingEndToCodeBlock));
+    }
+
+    #[test]
+    fn parses_fenced_code_blocks_as_run() {
+        assert_eq!(
+            parse("```echo hi```"),
+            Ok(Command::Run(String::from("echo hi")))
+        );
+        assert_eq!(
+            parse("```\necho hi\nls -l\n```"),
+            Ok(Command::Run(String::from("echo hi\nls -l")))
+        );
+        assert_eq!(
+            parse("```sh\necho `date`\nls -l\n```"),
+            Ok(Command::Run(String::from("echo `date`\nls -l")))
+        );
+        assert_eq!(
+            parse("```echo a b\nls\n```"),
+            Ok(Command::Run(String::from("echo a b\nls")))
+        );
+    }
+
+    #[test]
+    fn parses_admin_commands() {
+        assert_eq!(parse_admin("reload"), Ok(Admin::Reload));
+        assert_eq!(parse_admin(" killall "), Ok(Admin::KillAll));
+        assert_eq!(parse_admin("setcooldown 3"), Ok(Admin::SetCooldown(3)));
+        assert_eq!(
+            parse_admin("setcooldown"),
+            Err(Error::MissingArgument("seconds after 'setcooldown'"))
+        );
+        assert_eq!(parse_admin("setcooldown -1"), Err(Error::InvalidNumber));
+        assert_eq!(
+            parse_admin("setprefix !!"),
+            Ok(Admin::SetPrefix(String::from("!!")))
+        );
+        assert_eq!(
+            parse_admin("setprefix "),
+            Err(Error::MissingArgument("prefix after 'setprefix'"))
+        );
+        assert_eq!(
+            parse_admin("shutdown"),
+            Err(Error::UnrecognizedCommand {
+                got: String::from("shutdown"),
+                suggestion: None
+            })
+        );
+        assert_eq!(parse_admin(""), Err(Error::NoAction));
+    }
+
+    #[test]
+    fn parses_code_blocks_after_run() {
+        assert_eq!(
+            parse("run\n```sh\nfor f in *; do\n  echo \"$f\"\ndone\n```"),
+            Ok(Command::Run(String::from(
+                "for f in *; do\n  echo \"$f\"\ndone"
+            )))
+        );
+        assert_eq!(
+            parse("run ```\ncat <<'EOF'\n$HOME `x` \\n 'a' \"b\"\nEOF\n```"),
+            Ok(Command::Run(String::from(
+                "cat <<'EOF'\n$HOME `x` \\n 'a' \"b\"\nEOF"
+            )))
+        );
+        assert_eq!(
+            parse("run `echo hi`"),
+            Ok(Command::Run(String::from("echo hi")))
+        );
+        assert_eq!(parse("run\n```echo hi"), Err(Error::MissingEndToCodeBlock));
+    }
+
+    #[test]
+    fn rejects_unclosed_fenced_code_blocks() {
+        assert_eq!(parse("```"), Err(Error::MissingEndToCodeBlock));
+        assert_eq!(
+            parse("```sh\necho `date`\n"),
+            Err(Error::MissingEndToCodeBlock)
+        );
+    }
+
+    #[test]
+    fn rejects_run_without_a_command() {
+        assert_eq!(
+            parse("run"),
+            Err(Error::MissingArgument("command after 'run'"))
+        );
+        assert_eq!(
+            parse("run  "),
+            Err(Error::MissingArgument("command after 'run'"))
+        );
+    }
+
+    #[test]
+    fn handles_empty_and_single_character_input() {
+        assert_eq!(parse(""), Err(Error::NoAction));
+        assert_eq!(parse(" "), Err(Error::NoAction));
+        assert_eq!(parse("`"), Err(Error::MissingEndToCodeBlock));
+        assert_eq!(
+            parse("r"),
+            Err(Error::UnrecognizedCommand {
+                got: String::from("r"),
+                suggestion: Some("run")
+            })
+        );
+        assert_eq!(
+            parse("é"),
+            Err(Error::UnrecognizedCommand {
+                got: String::from("é"),
+                suggestion: None
+            })
+        );
+    }
+
+    #[test]
+    fn handles_multi_byte_characters() {
+        assert_eq!(
+            parse("run echo 🎉 é"),
+            Ok(Command::Run(String::from("echo 🎉 é")))
+        );
+        assert_eq!(parse("`é🎉`"), Ok(Command::Run(String::from("é🎉"))));
+        assert_eq!(
+            parse("cd dossier-é"),
+            Ok(Command::Cd(String::from("dossier-é")))
+        );
+        assert_eq!(
+            parse("🎉 run"),
+            Err(Error::UnrecognizedCommand {
+                got: String::from("🎉"),
+                suggestion: None
+            })
+        );
+        assert_eq!(parse("rename 🎉"), Ok(Command::Rename(String::from("🎉"))));
+    }
+
+    #[test]
+    fn parses_new_without_options() {
+        assert_eq!(parse("new"), Ok(Command::New(Box::new(new_terminal()))));
+    }
+
+    #[test]
+    fn parses_new_with_options() {
+        assert_eq!(
+            parse("new height=30 private"),
+            Ok(Command::New(Box::new(NewTerminal {
+                height: Some(30),
+                private: true,
+                ..new_terminal()
+            })))
+        );
+        assert_eq!(
+            parse("new shell=zsh timeout=60  lang=rust scrollback=100"),
+            Ok(Command::New(Box::new(NewTerminal {
+                shell: Some(String::from("zsh")),
+                timeout: Some(60),
+                lang: Some(String::from("rust")),
+                scrollback: Some(100),
+                ..new_terminal()
+            })))
+        );
+        assert_eq!(
+            parse("new width=80 log=out.log prompt=$"),
+            Ok(Command::New(Box::new(NewTerminal {
+                width: Some(80),
+                log: Some(String::from("out.log")),
+                prompt: Some(String::from("$")),
+                ..new_terminal()
+            })))
+        );
+        assert_eq!(
+            parse("new overflow=file color"),
+            Ok(Command::New(Box::new(NewTerminal {
+                overflow: Overflow::File,
+                color: true,
+                ..new_terminal()
+            })))
+        );
+        assert_eq!(
+            parse("new cooldown=2 max_cooldown=8 raw dedup tui timestamps hex"),
+            Ok(Command::New(Box::new(NewTerminal {
+                cooldown: Some(2),
+                max_cooldown: Some(8),
+                raw: true,
+                tui: true,
+                timestamps: true,
+                hex: true,
+                dedup: true,
+                ..new_terminal()
+            })))
+        );
+    }
+
+    #[test]
+    fn parses_new_with_a_command_to_run() {
+        assert_eq!(
+            parse("new height=20 run echo hello"),
+            Ok(Command::New(Box::new(NewTerminal {
+                height: Some(20),
+                run: Some(String::from("echo hello")),
+                ..new_terminal()
+            })))
+        );
+        assert_eq!(
+            parse("new run\n```sh\necho a\necho b\n```"),
+            Ok(Command::New(Box::new(NewTerminal {
+                run: Some(String::from("echo a\necho b")),
+                ..new_terminal()
+            })))
+        );
+        assert_eq!(
+            parse("new prompt=run private run ls run"),
+            Ok(Command::New(Box::new(NewTerminal {
+                prompt: Some(String::from("run")),
+                private: true,
+                run: Some(String::from("ls run")),
+                ..new_terminal()
+            })))
+        );
+        assert_eq!(
+            parse("new private run "),
+            Err(Error::MissingArgument("command after 'run'"))
+        );
+    }
+
+    #[test]
+    fn parses_quoted_options_of_new() {
+        assert_eq!(
+            parse("new dir=\"/srv/my files\" prompt=' >>> ' log=a\\ b.log"),
+            Ok(Command::New(Box::new(NewTerminal {
+                dir: Some(String::from("/srv/my files")),
+                prompt: Some(String::from(" >>> ")),
+                log: Some(String::from("a b.log")),
+                ..new_terminal()
+            })))
+        );
+        assert_eq!(
+            parse(r#"new prompt="say \"hi\" " dir='C:\x' "run" run echo "it's""#),
+            Ok(Command::New(Box::new(NewTerminal {
+                prompt: Some(String::from("say \"hi\" ")),
+                dir: Some(String::from("C:\\x")),
+                run: Some(String::from("echo \"it's\"")),
+                ..new_terminal()
+            })))
+        );
+        assert_eq!(
+            parse("new dir=\"/srv/my files"),
+            Err(Error::MissingEndToQuote)
+        );
+    }
+
+    #[test]
+    fn parses_every_spelling_of_height() {
+        let expected = Ok(Command::New(Box::new(NewTerminal {
+            height: Some(30),
+            ..new_terminal()
+        })));
+
+        assert_eq!(parse("new height=30"), expected);
+        assert_eq!(parse("new height 30"), expected);
+        assert_eq!(parse("new height30"), expected);
+    }
+
+    #[test]
+    fn rejects_invalid_height() {
+        assert_eq!(parse("new height=x"), Err(Error::InvalidNumber));
+        assert_eq!(parse("new height x"), Err(Error::InvalidNumber));
+        assert_eq!(parse("new height-1"), Err(Error::InvalidNumber));
+        assert_eq!(
+            parse("new height="),
+            Err(Error::MissingArgument("int after 'height'"))
+        );
+        assert_eq!(
+            parse("new height"),
+            Err(Error::MissingArgument("int after 'height'"))
+        );
+    }
+
+    #[test]
+    fn parses_the_encoding_of_new() {
+        let encoding = |raw| match parse(raw) {
+            Ok(Command::New(options)) => options.encoding,
+            _ => panic!("expected a new terminal"),
+        };
+
+        assert_eq!(encoding("new"), None);
+        assert_eq!(
+            encoding("new encoding=latin1"),
+            Some(encoding_rs::WINDOWS_1252)
+        );
+        assert_eq!(
+            encoding("new encoding=Shift_JIS"),
+            Some(encoding_rs::SHIFT_JIS)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_new_options() {
+        assert_eq!(parse("new height=tall"), Err(Error::InvalidNumber));
+        assert_eq!(parse("new height=1001"), Err(Error::HeightToLarge(1001)));
+        assert_eq!(parse("resize 1001"), Err(Error::HeightToLarge(1001)));
+        assert_eq!(
+            parse("new tui width=1000000000000"),
+            Err(Error::WidthToLarge(1_000_000_000_000))
+        );
+        assert_eq!(parse("resize tall"), Err(Error::InvalidNumber));
+        assert_eq!(parse("new width=0"), Err(Error::InvalidNumber));
+        assert_eq!(parse("new height=0"), Err(Error::InvalidNumber));
+        assert_eq!(parse("new height 0"), Err(Error::InvalidNumber));
+        assert_eq!(parse("resize 0"), Err(Error::InvalidNumber));
+        assert_eq!(
+            parse("new scrollback=10001"),
+            Err(Error::ScrollbackToLarge(10001))
+        );
+        assert_eq!(
+            parse("new overflow=truncate"),
+            Err(Error::InvalidOverflow(String::from("truncate")))
+        );
+        assert_eq!(
+            parse("new lang=c++"),
+            Ok(Command::New(Box::new(NewTerminal {
+                lang: Some(String::from("c++")),
+                ..new_terminal()
+            })))
+        );
+        let long = "x".repeat(LANG_LIMIT + 1);
+        assert_eq!(
+            parse(&format!("new lang={}", long)),
+            Err(Error::InvalidLanguage(long))
+        );
+        assert_eq!(
+            parse("new lang=```"),
+            Err(Error::InvalidLanguage(String::from("```")))
+        );
+        assert_eq!(
+            parse("new encoding=klingon"),
+            Err(Error::UnknownEncoding(String::from("klingon")))
+        );
+        // lines couldn't be told apart by their `\n` bytes
+        assert_eq!(
+            parse("new encoding=utf-16"),
+            Err(Error::UnknownEncoding(String::from("utf-16")))
+        );
+        assert_eq!(
+            parse("new shell="),
+            Err(Error::MissingArgument("name after 'shell='"))
+        );
+    }
+
+    #[test]
+    fn suggests_the_closest_command() {
+        let suggestion = |raw| match parse(raw) {
+            Err(Error::UnrecognizedCommand { suggestion, .. }) => suggestion,
+            _ => panic!("`{}` is expected to be unrecognized", raw),
+        };
+
+        assert_eq!(suggestion("nwe"), Some("new"));
+        assert_eq!(suggestion("remvoe"), Some("remove"));
+        assert_eq!(suggestion("lst"), Some("list"));
+        assert_eq!(suggestion("cancl"), Some("cancel"));
+        assert_eq!(suggestion("xy"), None);
+        assert_eq!(
+            parse_admin("relaod"),
+            Err(Error::UnrecognizedCommand {
+                got: String::from("relaod"),
+                suggestion: Some("reload")
+            })
+        );
+
+        assert_eq!(
+            parse("nwe").unwrap_err().to_string(),
+            "nwe is not a valid command, did you mean `new`?"
+        );
+        assert_eq!(
+            parse("frobnicate").unwrap_err().to_string(),
+            "frobnicate is not a valid command"
+        );
+    }
+
+    #[test]
+    fn measures_the_edit_distance_in_characters() {
+        assert_eq!(edit_distance("", "run"), 3);
+        assert_eq!(edit_distance("run", "run"), 0);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("é", "e"), 1);
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert_eq!(
+            parse("frobnicate"),
+            Err(Error::UnrecognizedCommand {
+                got: String::from("frobnicate"),
+                suggestion: None
+            })
+        );
+    }
+}
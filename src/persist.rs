@@ -0,0 +1,136 @@
+use super::session::Overflow;
+use super::terminal::{StderrMode, Stream};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Everything needed to reattach to a terminal after a restart
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedTerminal {
+    pub id: String,
+    pub channel: u64,
+    pub message: u64,
+    // the user who created the terminal, counted towards their limits
+    #[serde(default)]
+    pub owner: u64,
+    pub height: usize,
+    #[serde(default = "default_scrollback")]
+    pub scrollback: usize,
+    pub shell: String,
+    pub dir: Option<PathBuf>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    pub buffer: Vec<(Stream, String)>,
+    /// Where the output is logged to, opened again to append to it
+    #[serde(default)]
+    pub log: Option<PathBuf>,
+    #[serde(default)]
+    pub options: SavedOptions,
+}
+
+/// The options a terminal was created with, all of which are missing from older saves
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedOptions {
+    pub lang: Option<String>,
+    pub raw: bool,
+    pub color: bool,
+    pub overflow: Overflow,
+    pub prompt: Option<String>,
+    pub width: Option<usize>,
+    pub tui: bool,
+    pub dedup: bool,
+    pub timestamps: bool,
+    pub hex: bool,
+    /// The name of the encoding, UTF-8 if missing
+    pub encoding: Option<String>,
+    /// The settings are used for the cooldowns that are missing
+    pub cooldown: Option<Duration>,
+    pub max_cooldown: Option<Duration>,
+    pub timeout: Option<Duration>,
+    pub stderr: StderrMode,
+}
+
+fn default_scrollback() -> usize {
+    crate::terminal::DEFAULT_SCROLLBACK
+}
+
+/// Read the terminals saved at `path`, a missing file means there's nothing to restore
+pub fn load(path: &Path) -> io::Result<Vec<SavedTerminal>> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Overwrite the terminals saved at `path`
+pub fn save(path: &Path, terminals: &[SavedTerminal]) -> io::Result<()> {
+    let json = serde_json::to_vec(terminals)?;
+
+    // write to the side first so that a crash mid-write doesn't lose the previous state
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(options: SavedOptions) -> SavedTerminal {
+        SavedTerminal {
+            id: String::from("t"),
+            channel: 1,
+            message: 2,
+            owner: 3,
+            height: 20,
+            scrollback: 100,
+            shell: String::from("sh"),
+            dir: None,
+            env: HashMap::new(),
+            buffer: vec![(Stream::Stdout, String::from("hi"))],
+            log: Some(PathBuf::from("/tmp/out.log")),
+            options,
+        }
+    }
+
+    #[test]
+    fn saves_and_loads_the_options_of_terminals() {
+        let path = std::env::temp_dir().join(format!("termview-saved-{}", std::process::id()));
+        let terminals = vec![saved(SavedOptions {
+            lang: Some(String::from("rust")),
+            raw: true,
+            color: true,
+            overflow: Overflow::File,
+            prompt: Some(String::from("$")),
+            width: Some(80),
+            tui: true,
+            dedup: true,
+            timestamps: true,
+            hex: true,
+            encoding: Some(String::from("Shift_JIS")),
+            cooldown: Some(Duration::from_secs(2)),
+            max_cooldown: Some(Duration::from_secs(8)),
+            timeout: Some(Duration::from_secs(60)),
+            stderr: StderrMode::Separate,
+        })];
+
+        save(&path, &terminals).unwrap();
+        let loaded = load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded, terminals);
+    }
+
+    #[test]
+    fn loads_terminals_saved_without_options() {
+        let json = r#"[{"id":"t","channel":1,"message":2,"height":20,"shell":"sh","dir":null,"buffer":[]}]"#;
+        let loaded: Vec<SavedTerminal> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(loaded[0].log, None);
+        assert_eq!(loaded[0].options, SavedOptions::default());
+    }
+}
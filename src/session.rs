@@ -1,72 +1,717 @@
 use super::terminal;
 use async_trait::async_trait;
-use std::collections::VecDeque;
-use terminal::Window;
-use tokio::sync::mpsc as channel;
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use terminal::{Exit, Job, Line, StderrMode, Stream, Window};
+use tokio::sync::mpsc::{self as channel, error::TrySendError};
+use tokio::sync::watch;
+
+/// Shown once the terminal is ready for another command
+pub const DEFAULT_PROMPT: &str = " >>> ";
 
 pub enum Event {
-    Update(String),
+    Update(Frame),
     Ready,
+    /// The terminal, or the section of it, is gone and won't send any more frames
+    Closed {
+        section: Option<String>,
+    },
+    /// A command has exited, only sent if the layout asks for transcripts
+    Finished(Transcript),
+}
+
+/// The output of a command once it has exited, along with what ran it
+pub struct Transcript {
+    pub command: String,
+    pub requested_by: Option<String>,
+    pub exit: Exit,
+    /// Every line of the window, including the output of earlier commands that's still kept
+    pub output: String,
+    pub section: Option<String>,
+}
+
+/// A rendered snapshot of the terminal
+#[derive(PartialEq)]
+pub struct Frame {
+    pub text: String,
+    pub lang: Option<String>,
+    /// Which section of the message the frame belongs to, `None` for the terminal itself
+    pub section: Option<String>,
+    /// Every line of the window, only rendered if output that doesn't fit should go to a file
+    pub full: Option<String>,
+}
+
+/// What to do with a frame that doesn't fit in a single message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Overflow {
+    /// Continue onto more messages, dropping the oldest lines if there'd be too many of them
+    #[default]
+    Pages,
+    /// Show the latest lines and attach all of them as a file
+    File,
+}
+
+/// How the frames of a session should be presented
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    /// Keep ANSI escape sequences in the rendered output
+    pub raw: bool,
+    /// Keep the colors Discord can show in `ansi` code blocks, unless `raw` keeps everything
+    pub color: bool,
+    /// Language used for syntax highlighting
+    pub lang: Option<String>,
+    /// Render the frames as a section below the terminal's own output
+    pub section: Option<String>,
+    pub overflow: Overflow,
+    /// Shown once the terminal is ready for another command, `DEFAULT_PROMPT` if missing
+    pub prompt: Option<String>,
+    /// Send a `Transcript` each time a command exits
+    pub transcripts: bool,
+}
+
+impl Layout {
+    pub fn prompt(&self) -> &str {
+        self.prompt.as_deref().unwrap_or(DEFAULT_PROMPT)
+    }
+}
+
+/// The command that's running, kept for the footer of its frames and its transcript
+struct Running {
+    line: String,
+    requested_by: Option<String>,
+    started: Instant,
 }
 
 /// Proxy between a Runner and a combinator
 pub struct TTYSession<ID> {
-    id: ID,
+    // the owner may change where frames are sent while the session is running
+    id: watch::Receiver<ID>,
     sender: channel::Sender<(ID, Event)>,
+    layout: Layout,
+    // commands waiting behind the running one, shown below the output
+    queued: usize,
+    running: Option<Running>,
+    // the newest frame that didn't fit in the channel, sent as soon as there's room for it
+    held: Arc<Mutex<Option<(ID, Frame)>>>,
+    // the visible output as of the latest frame, for whoever wants to read it outside of frames
+    snapshot: Arc<Mutex<String>>,
 }
 
 impl<ID> TTYSession<ID> {
-    pub fn new(id: ID, sender: channel::Sender<(ID, Event)>) -> Self {
-        Self { id, sender }
+    pub fn new(
+        id: watch::Receiver<ID>,
+        sender: channel::Sender<(ID, Event)>,
+        layout: Layout,
+    ) -> Self {
+        Self {
+            id,
+            sender,
+            layout,
+            queued: 0,
+            running: None,
+            held: Arc::new(Mutex::new(None)),
+            snapshot: Arc::new(Mutex::new(String::new())),
+        }
+    }
+
+    /// The visible output of the terminal, kept up to date with every frame
+    pub fn snapshot(&self) -> Arc<Mutex<String>> {
+        self.snapshot.clone()
+    }
+
+    fn id(&self) -> ID
+    where
+        ID: Clone,
+    {
+        self.id.borrow().clone()
     }
 
     pub fn append_prompt(&self, window: &mut Window) {
-        let prompt = String::from(" >>> ");
-        window.buffer.push_back(prompt.into_boxed_str());
+        *window += self.layout.prompt().to_string();
+    }
+
+    /// How long the running command has been running for, shown below its output
+    ///
+    /// Every heartbeat adds a dot, cycling through three of them, so that it changes the message
+    /// even once the seconds aren't shown.
+    fn footer(&self, beats: usize) -> Option<String> {
+        let running = self.running.as_ref()?;
+        let dots = match beats {
+            0 => String::new(),
+            beats => ".".repeat((beats - 1) % 3 + 1),
+        };
+
+        let elapsed = format_elapsed(running.started.elapsed());
+        Some(format!("[running {}{}]", elapsed, dots))
     }
 }
 
-fn render_snapshot(buffer: &VecDeque<Box<str>>) -> String {
-    let mut snapshot = String::with_capacity(buffer.iter().map(|line| line.len()).sum());
-    for line in buffer.iter().map(Box::as_ref) {
-        snapshot.push_str(line);
+/// Write out a duration as hours, minutes and seconds, leaving out the smaller units once they
+/// don't matter and the larger ones that are zero
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    match (hours, minutes) {
+        (0, 0) => format!("{}s", secs),
+        (0, _) => format!("{}m {}s", minutes, secs),
+        _ => format!("{}h {}m", hours, minutes),
+    }
+}
+
+/// Render the lines, below a line telling how many came before them if any did
+///
+/// Stderr is rendered below stdout when they're shown separately.
+fn render_snapshot<'a>(
+    hidden: usize,
+    lines: impl Iterator<Item = &'a Line> + Clone,
+    stderr: StderrMode,
+    timestamps: bool,
+) -> String {
+    let mut snapshot = String::with_capacity(lines.clone().map(|line| line.text.len()).sum());
+    if hidden > 0 {
+        let plural = if hidden == 1 { "" } else { "s" };
+        snapshot.push_str(&format!(
+            "... {} earlier line{} hidden ...\n",
+            hidden, plural
+        ));
+    }
+
+    // stdout followed by stderr when they're separate, everything in the order it was read otherwise
+    let separate = stderr == StderrMode::Separate;
+    let lines = lines.map(|line| (line.stream, line.shown(timestamps)));
+    let first = lines
+        .clone()
+        .filter(|(stream, _)| !separate || *stream == Stream::Stdout);
+    let errors = lines.filter(|(stream, _)| separate && *stream == Stream::Stderr);
+    let header = errors
+        .clone()
+        .next()
+        .map(|_| (Stream::Stdout, Cow::Borrowed("--- stderr ---")));
+
+    for (stream, line) in first.chain(header).chain(errors) {
+        if stream == Stream::Stderr {
+            snapshot.push_str("! ");
+        }
+        snapshot.push_str(&line);
         snapshot.push('\n');
     }
     snapshot.pop();
     snapshot
 }
 
+/// Render how a command ended, failures are shown as stderr output
+///
+/// Commands that failed to run have already had their error shown instead.
+fn describe_exit(exit: Exit) -> Option<(Stream, String)> {
+    let described = match exit {
+        Exit::Completed(0) => (Stream::Stdout, String::from("[exit 0]")),
+        Exit::Completed(code) => (Stream::Stderr, format!("[exit {}]", code)),
+        Exit::Cancelled => (Stream::Stdout, String::from(" <cancelled> ")),
+        Exit::TimedOut => (Stream::Stderr, String::from(" <timed out> ")),
+        Exit::Killed(signal) => (
+            Stream::Stderr,
+            format!(" <killed: {}> ", terminal::signal_name(signal)),
+        ),
+        Exit::Failed => return None,
+    };
+
+    Some(described)
+}
+
+/// Remove ANSI escape sequences such as colors and cursor movement from `text`, except for the
+/// colors Discord can show in `ansi` code blocks if `colors` is set
+///
+/// Sequences may span over several lines, in which case the newlines within them are kept.
+fn strip_ansi(text: &str, colors: bool) -> String {
+    enum State {
+        Text,
+        Escape,
+        Csi,
+        Osc,
+        OscEscape,
+    }
+
+    let mut stripped = String::with_capacity(text.len());
+    let mut state = State::Text;
+    // the parameters of the CSI sequence being read
+    let mut params = String::new();
+
+    for c in text.chars() {
+        if c == '\n' {
+            stripped.push(c);
+            continue;
+        }
+
+        state = match state {
+            State::Text if c == '\x1b' => State::Escape,
+            State::Text => {
+                stripped.push(c);
+                State::Text
+            }
+            State::Escape => match c {
+                '[' => {
+                    params.clear();
+                    State::Csi
+                }
+                ']' => State::Osc,
+                // two-character escapes such as `ESC =`
+                _ => State::Text,
+            },
+            // parameter and intermediate bytes, the sequence ends with the first other character
+            State::Csi => match c {
+                '\x20'..='\x3f' => {
+                    params.push(c);
+                    State::Csi
+                }
+                'm' if colors => {
+                    stripped.push_str(&supported_sgr(&params));
+                    State::Text
+                }
+                _ => State::Text,
+            },
+            State::Osc => match c {
+                '\x07' => State::Text,
+                '\x1b' => State::OscEscape,
+                _ => State::Osc,
+            },
+            State::OscEscape => match c {
+                '\\' => State::Text,
+                _ => State::Osc,
+            },
+        };
+    }
+
+    stripped
+}
+
+/// Keep the parts of an SGR sequence that Discord's `ansi` code blocks can show, which are bold,
+/// underline and the 8 basic colors, as a sequence of their own
+///
+/// Bright colors are shown as their basic ones, and nothing is left if none of them can be shown.
+fn supported_sgr(params: &str) -> String {
+    let mut kept = Vec::new();
+    let mut codes = params.split(';');
+
+    while let Some(code) = codes.next() {
+        // a missing code resets, like `ESC [ m`
+        let code = match code {
+            "" => 0,
+            code => match code.parse::<u16>() {
+                Ok(code) => code,
+                Err(_) => continue,
+            },
+        };
+
+        match code {
+            0 | 1 | 4 | 30..=37 | 40..=47 => kept.push(code),
+            90..=97 | 100..=107 => kept.push(code - 60),
+            // 256 colors and true colors take codes of their own that shouldn't be read as codes
+            38 | 48 => match codes.next() {
+                Some("5") => {
+                    codes.next();
+                }
+                Some("2") => {
+                    codes.nth(2);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    if kept.is_empty() {
+        return String::new();
+    }
+
+    let kept: Vec<String> = kept.iter().map(u16::to_string).collect();
+    format!("\x1b[{}m", kept.join(";"))
+}
+
+impl<ID: std::fmt::Debug + Clone + Send + Sync + 'static> TTYSession<ID> {
+    /// Render the window and send it, with `status` shown on a line of its own below the output
+    fn send_frame(&self, window: &Window, status: Option<String>) {
+        let id = self.id();
+        trace!("updating terminal `{:?}`", id);
+
+        let mut snapshot = render_snapshot(
+            window.hidden(),
+            window.visible(),
+            window.stderr,
+            window.timestamps,
+        );
+        let mut full = match self.layout.overflow {
+            Overflow::File => Some(render_snapshot(
+                window.dropped,
+                window.lines(),
+                window.stderr,
+                window.timestamps,
+            )),
+            Overflow::Pages => None,
+        };
+
+        if !self.layout.raw {
+            snapshot = strip_ansi(&snapshot, self.layout.color);
+            full = full.map(|full| strip_ansi(&full, false));
+        }
+
+        *self.snapshot.lock().unwrap() = snapshot.clone();
+
+        if let Some(status) = status {
+            snapshot.push('\n');
+            snapshot.push_str(&status);
+        }
+
+        if self.queued > 0 {
+            snapshot.push_str(&format!("\n[{} queued]", self.queued));
+        }
+
+        let frame = Frame {
+            text: snapshot,
+            lang: self.layout.lang.clone(),
+            section: self.layout.section.clone(),
+            full,
+        };
+
+        self.offer(id, frame);
+    }
+
+    /// Hand the frame to the renderer without waiting, so that the runner keeps reading output
+    ///
+    /// If the channel is full the frame is held back instead, replacing any older frame that was.
+    fn offer(&self, id: ID, frame: Frame) {
+        let mut held = self.held.lock().unwrap();
+
+        // sending right away would overtake the held back frame
+        if held.is_some() {
+            debug!("dropping an outdated frame of `{:?}`", id);
+            *held = Some((id, frame));
+            return;
+        }
+
+        match self.sender.try_reserve() {
+            Ok(permit) => permit.send((id, Event::Update(frame))),
+            Err(TrySendError::Full(())) => {
+                *held = Some((id, frame));
+
+                let sender = self.sender.clone();
+                let held = self.held.clone();
+                tokio::spawn(async move { send_held(&sender, &held).await });
+            }
+            Err(TrySendError::Closed(())) => {
+                warn!("TTY {:?} failed to send it's data: channel closed", id)
+            }
+        }
+    }
+
+    /// Send an event after the held back frame, if there is one, so that it isn't overtaken
+    async fn signal(&self, event: Event) -> Result<(), channel::error::SendError<(ID, Event)>> {
+        send_held(&self.sender, &self.held).await;
+        self.sender.send((self.id(), event)).await
+    }
+}
+
+/// Wait for room in the channel and send the held back frame, unless someone else already has
+async fn send_held<ID>(sender: &channel::Sender<(ID, Event)>, held: &Mutex<Option<(ID, Frame)>>) {
+    if let Ok(permit) = sender.reserve().await {
+        if let Some((id, frame)) = held.lock().unwrap().take() {
+            permit.send((id, Event::Update(frame)));
+        }
+    }
+}
+
 #[async_trait]
-impl<ID: std::fmt::Debug + Clone + Send + Sync> terminal::Handler for TTYSession<ID> {
+impl<ID: std::fmt::Debug + Clone + Send + Sync + 'static> terminal::Handler for TTYSession<ID> {
     async fn update(&mut self, window: &mut Window) {
-        println!("updating terminal `{:?}`", self.id);
+        self.send_frame(window, self.footer(0))
+    }
 
-        let snapshot = render_snapshot(&window.buffer);
+    // lines are rendered from the window instead
+    async fn on_line(&mut self, _stream: Stream, _line: &str) {}
 
-        if let Err(e) = self
-            .sender
-            .send((self.id.clone(), Event::Update(snapshot)))
-            .await
-        {
-            eprintln!("TTY {:?} failed to send it's data: {}", self.id, e)
-        }
+    async fn on_command_start(&mut self, _window: &mut Window, job: &Job) {
+        self.running = Some(Running {
+            line: job.line.clone(),
+            requested_by: job.requested_by.clone(),
+            started: Instant::now(),
+        });
     }
 
-    async fn on_command_exit(&mut self, window: &mut Window) {
+    async fn on_command_exit(&mut self, window: &mut Window, exit: Exit) {
+        if let Some(described) = describe_exit(exit) {
+            *window += described;
+        }
+
+        // taken before the window is updated, so that the footer makes way for the prompt
+        let transcript = match self.running.take() {
+            Some(running) if self.layout.transcripts => {
+                let mut output = render_snapshot(
+                    window.dropped,
+                    window.lines(),
+                    window.stderr,
+                    window.timestamps,
+                );
+                if !self.layout.raw {
+                    output = strip_ansi(&output, false);
+                }
+
+                Some(Transcript {
+                    command: running.line,
+                    requested_by: running.requested_by,
+                    exit,
+                    output,
+                    section: self.layout.section.clone(),
+                })
+            }
+            _ => None,
+        };
+
         self.append_prompt(window);
 
         self.update(window).await;
 
-        if let Err(e) = self.sender.send((self.id.clone(), Event::Ready)).await {
-            eprintln!("TTY {:?} failed to send exit signal: {}", self.id, e)
+        if let Some(transcript) = transcript {
+            if let Err(e) = self.signal(Event::Finished(transcript)).await {
+                warn!("TTY {:?} failed to send transcript: {}", self.id(), e)
+            }
+        }
+
+        if let Err(e) = self.signal(Event::Ready).await {
+            warn!("TTY {:?} failed to send exit signal: {}", self.id(), e)
         }
     }
 
     async fn on_terminal_exit(&mut self, window: &mut Window) {
-        window
-            .buffer
-            .push_back(String::from(" <session closed> ").into_boxed_str());
+        *window += String::from(" <session closed> ");
+
+        self.update(window).await;
+
+        let closed = Event::Closed {
+            section: self.layout.section.clone(),
+        };
+        if let Err(e) = self.signal(closed).await {
+            warn!("TTY {:?} failed to send close signal: {}", self.id(), e)
+        }
+    }
+
+    async fn on_clear(&mut self, window: &mut Window) {
+        self.append_prompt(window);
 
         self.update(window).await
     }
+
+    async fn on_queue_change(&mut self, _window: &mut Window, queued: usize) {
+        self.queued = queued;
+    }
+
+    async fn on_heartbeat(&mut self, window: &mut Window, beats: usize) {
+        self.send_frame(window, self.footer(beats))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(text: &str) -> Frame {
+        Frame {
+            text: text.to_string(),
+            lang: None,
+            section: None,
+            full: None,
+        }
+    }
+
+    fn text(event: Option<(u8, Event)>) -> String {
+        match event {
+            Some((_, Event::Update(frame))) => frame.text,
+            _ => panic!("expected a frame"),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_newest_held_back_frame_is_sent() {
+        let (_, id) = watch::channel(0);
+        let (sender, mut reciever) = channel::channel(1);
+        let session = TTYSession::new(id, sender, Layout::default());
+
+        session.offer(0, frame("1"));
+        session.offer(0, frame("2"));
+        session.offer(0, frame("3"));
+
+        assert_eq!(text(reciever.recv().await), "1");
+        assert_eq!(text(reciever.recv().await), "3");
+
+        // the signal waits for the held back frame to be sent first
+        session.offer(0, frame("4"));
+        session.offer(0, frame("5"));
+        let (signalled, _) = tokio::join!(session.signal(Event::Ready), async {
+            assert_eq!(text(reciever.recv().await), "4");
+            assert_eq!(text(reciever.recv().await), "5");
+            assert!(matches!(reciever.recv().await, Some((0, Event::Ready))));
+        });
+        assert!(signalled.is_ok());
+    }
+
+    #[test]
+    fn keeps_only_the_colors_discord_shows() {
+        let text = "\x1b[1;31merror\x1b[0m: \x1b[2Kdone \x1b[38;5;196;4mx\x1b[m\x1b[3m";
+
+        assert_eq!(strip_ansi(text, false), "error: done x");
+        assert_eq!(
+            strip_ansi(text, true),
+            "\x1b[1;31merror\x1b[0m: done \x1b[4mx\x1b[0m"
+        );
+        assert_eq!(
+            strip_ansi("\x1b[92mok\x1b[38;2;1;2;3;44m", true),
+            "\x1b[32mok\x1b[44m"
+        );
+    }
+
+    #[test]
+    fn shows_how_many_lines_are_hidden() {
+        let lines = [
+            Line::new(Stream::Stdout, "b"),
+            Line::new(Stream::Stderr, "c"),
+        ];
+        let render = |hidden| render_snapshot(hidden, lines.iter(), StderrMode::Inline, false);
+
+        assert_eq!(render(0), "b\n! c");
+        assert_eq!(render(1), "... 1 earlier line hidden ...\nb\n! c");
+        assert_eq!(render(12), "... 12 earlier lines hidden ...\nb\n! c");
+    }
+
+    #[test]
+    fn marks_how_commands_ended() {
+        let marker = |exit| describe_exit(exit).map(|(_, text)| text);
+
+        assert_eq!(marker(Exit::Completed(2)).as_deref(), Some("[exit 2]"));
+        assert_eq!(marker(Exit::Cancelled).as_deref(), Some(" <cancelled> "));
+        assert_eq!(marker(Exit::TimedOut).as_deref(), Some(" <timed out> "));
+        assert_eq!(
+            marker(Exit::Killed(libc::SIGKILL)).as_deref(),
+            Some(" <killed: SIGKILL> ")
+        );
+        assert_eq!(marker(Exit::Failed), None);
+    }
+
+    #[test]
+    fn shows_stderr_below_stdout_when_separate() {
+        let lines = [
+            Line::new(Stream::Stderr, "warning"),
+            Line::new(Stream::Stdout, "a"),
+            Line::new(Stream::Stderr, "error"),
+            Line::new(Stream::Stdout, "b"),
+        ];
+
+        assert_eq!(
+            render_snapshot(0, lines.iter(), StderrMode::Separate, false),
+            "a\nb\n--- stderr ---\n! warning\n! error"
+        );
+        assert_eq!(
+            render_snapshot(0, lines[1..2].iter(), StderrMode::Separate, false),
+            "a"
+        );
+    }
+
+    #[tokio::test]
+    async fn shares_the_output_of_the_latest_frame() {
+        use terminal::Handler;
+
+        let (_, id) = watch::channel(0);
+        let (sender, mut reciever) = channel::channel(5);
+        let mut session = TTYSession::new(id, sender, Layout::default());
+        let snapshot = session.snapshot();
+
+        let mut window = Window::new(2, 2);
+        window += String::from("a");
+        window += String::from("\x1b[31mb\x1b[0m");
+        session.on_queue_change(&mut window, 1).await;
+        session.update(&mut window).await;
+
+        assert_eq!(text(reciever.recv().await), "a\nb\n[1 queued]");
+        assert_eq!(*snapshot.lock().unwrap(), "a\nb");
+    }
+
+    #[tokio::test]
+    async fn shows_how_long_the_command_has_been_running() {
+        use terminal::Handler;
+
+        let (_, id) = watch::channel(0);
+        let (sender, mut reciever) = channel::channel(5);
+        let mut session = TTYSession::new(id, sender, Layout::default());
+
+        let job = Job {
+            line: String::from("sleep 1"),
+            exec: tokio::process::Command::new("true"),
+            requested_by: None,
+            stdin: None,
+        };
+
+        let mut window = Window::new(5, 5);
+        window += String::from("$ sleep 1");
+        session.on_command_start(&mut window, &job).await;
+        session.update(&mut window).await;
+        assert_eq!(text(reciever.recv().await), "$ sleep 1\n[running 0s]");
+
+        session.on_heartbeat(&mut window, 2).await;
+        assert_eq!(text(reciever.recv().await), "$ sleep 1\n[running 0s..]");
+
+        session
+            .on_command_exit(&mut window, Exit::Completed(0))
+            .await;
+        assert_eq!(text(reciever.recv().await), "$ sleep 1\n[exit 0]\n >>> ");
+    }
+
+    #[test]
+    fn formats_the_time_commands_have_been_running() {
+        assert_eq!(format_elapsed(Duration::from_millis(12_900)), "12s");
+        assert_eq!(format_elapsed(Duration::from_secs(125)), "2m 5s");
+        assert_eq!(format_elapsed(Duration::from_secs(2 * 3600 + 180)), "2h 3m");
+    }
+
+    #[tokio::test]
+    async fn sends_a_transcript_once_a_command_exits() {
+        use terminal::Handler;
+
+        let (_, id) = watch::channel(0);
+        let (sender, mut reciever) = channel::channel(5);
+        let layout = Layout {
+            transcripts: true,
+            ..Layout::default()
+        };
+        let mut session = TTYSession::new(id, sender, layout);
+
+        let job = Job {
+            line: String::from("echo hi"),
+            exec: tokio::process::Command::new("true"),
+            requested_by: Some(String::from("someone")),
+            stdin: None,
+        };
+
+        let mut window = Window::new(5, 5);
+        window += String::from("$ echo hi");
+        session.on_command_start(&mut window, &job).await;
+        window += String::from("hi");
+        session
+            .on_command_exit(&mut window, Exit::Completed(0))
+            .await;
+
+        assert_eq!(
+            text(reciever.recv().await),
+            "$ echo hi\nhi\n[exit 0]\n >>> "
+        );
+        match reciever.recv().await {
+            Some((_, Event::Finished(transcript))) => {
+                assert_eq!(transcript.command, "echo hi");
+                assert_eq!(transcript.requested_by.as_deref(), Some("someone"));
+                assert_eq!(transcript.output, "$ echo hi\nhi\n[exit 0]");
+            }
+            _ => panic!("expected a transcript"),
+        }
+        assert!(matches!(reciever.recv().await, Some((0, Event::Ready))));
+    }
 }
@@ -1,30 +1,372 @@
+use super::metrics;
 use async_trait::async_trait;
+use encoding_rs::Encoding;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::VecDeque;
+use std::fmt;
+use std::io::SeekFrom;
 use std::ops::AddAssign;
-use std::process::Stdio;
-use std::time::{Duration, SystemTime};
-use tokio::io::{AsyncBufReadExt, BufReader};
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::{ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncSeekExt, AsyncWriteExt, BufReader};
 use tokio::process;
-use tokio::sync::mpsc as channel;
+use tokio::sync::mpsc::{self as channel, error::TrySendError};
+use tokio::sync::oneshot;
 
-const COOLDOWN: u64 = 4;
+/// Default time between frames when there's little output
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(2);
+
+/// Default time between frames when output is flooding in
+pub const DEFAULT_MAX_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Anything faster than this risks getting the bot rate limited by Discord
+pub const MIN_COOLDOWN: Duration = Duration::from_secs(1);
+
+/// Default time a running command may be quiet before it's shown to still be running
+pub const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(5);
+
+/// Lines per second at which the cooldown reaches its maximum
+const FLOOD_RATE: f64 = 50.0;
+
+/// Most lines read within a `THROTTLE_PERIOD`, a command printing faster than that has to wait
+const LINE_BURST: usize = 1000;
+const THROTTLE_PERIOD: Duration = Duration::from_millis(100);
+/// Lines taken at once out of what a command has already printed, before commands are handled
+const MAX_DRAINED: usize = 100;
+
+/// How long output has to be quiet before it's rendered without waiting for the cooldown
+const FORCED_FLUSH_DELAY: Duration = Duration::from_secs(1);
+
+/// How many lines are kept by default, including the ones that are visible
+pub const DEFAULT_SCROLLBACK: usize = 500;
+
+/// Columns between tab stops, as tabs are expanded to spaces when wrapping
+const TAB_WIDTH: usize = 8;
+
+/// How wide the screen of a `tui` terminal is when no width is given
+const DEFAULT_SCREEN_WIDTH: usize = 80;
+
+/// How many of the latest command lines are remembered by each terminal
+const HISTORY_LIMIT: usize = 50;
+
+/// How long a command that has closed its output gets to exit before it's killed
+const EXIT_GRACE: Duration = Duration::from_secs(1);
+
+/// How often a followed file is checked for new lines once everything in it has been read
+const FOLLOW_POLL: Duration = Duration::from_millis(500);
+
+/// The share of a line's characters that have to be unprintable for it to be shown as a hexdump
+const BINARY_RATIO: f64 = 0.3;
+
+/// Which output of a command a line was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// How the stderr of commands is shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum StderrMode {
+    /// Mixed in with stdout in the order it was read, the way `2>&1` would
+    #[default]
+    Inline,
+    /// Dropped as it's read, so that only stdout makes it into the window
+    Off,
+    /// Shown below stdout rather than between it
+    Separate,
+}
 
 /// Create your own listener to capture each frame outputted by the terminal
 ///
 /// Frame rate is low enough to comply with rate limits and will dynamically change depending on
-/// the amount of output.
+/// the amount of output. The events that not every listener cares about do nothing unless they're
+/// implemented.
 #[async_trait]
 pub trait Handler {
     async fn update(&mut self, window: &mut Window);
-    async fn on_command_exit(&mut self, window: &mut Window);
+    /// A line of output was read, whether or not the filter lets it into the window
+    async fn on_line(&mut self, _stream: Stream, _line: &str) {}
+    /// The job is about to be started, after its line has been echoed into the window
+    async fn on_command_start(&mut self, _window: &mut Window, _job: &Job) {}
+    /// The window doesn't show how the command ended yet, that's up to the handler
+    async fn on_command_exit(&mut self, window: &mut Window, exit: Exit);
+    /// The runner has stopped taking commands by now, so its senders can tell that it's closed.
+    /// It's the last event, and only ever sent once
     async fn on_terminal_exit(&mut self, window: &mut Window);
+    async fn on_clear(&mut self, _window: &mut Window) {}
+    async fn on_queue_change(&mut self, _window: &mut Window, _queued: usize) {}
+    /// The running command has been quiet for `beats` heartbeats, counting from 1 until it prints
+    /// again
+    async fn on_heartbeat(&mut self, _window: &mut Window, _beats: usize) {}
 }
 
 /// Signals sent via the command buffer to control the terminal.
 #[derive(Debug)]
 pub enum Command {
-    Run(process::Command),
-    Remove,
+    Run(Box<Job>),
+    /// Run the job every interval from now on, skipping a run while the previous one is still going
+    Schedule {
+        interval: Duration,
+        job: MakeJob,
+    },
+    /// Stop running the scheduled job, a run that's already going is left to finish
+    Unschedule,
+    /// Show the lines appended to a file from now on, until cancelled
+    Follow(PathBuf),
+    /// Mirror the lines read from now on to another terminal as well, or stop if `None`
+    ///
+    /// Lines are dropped rather than mirrored while the other terminal has too many commands
+    /// waiting, so it may miss some of the output of a command that prints a lot.
+    Tee(Option<channel::Sender<Command>>),
+    /// Append a line mirrored from another terminal
+    ///
+    /// These aren't mirrored any further, so terminals teeing into each other can't loop forever.
+    Feed(String),
+    Input(String, oneshot::Sender<Result<(), InputError>>),
+    /// Kill the running command or stop following the file, the queued commands still run
+    Cancel,
+    /// Drop the schedule and the queue, and only then cancel, so that nothing queued starts in
+    /// place of the cancelled command
+    CancelAll,
+    Clear,
+    /// Only keep the output lines matching the pattern, `None` keeps everything
+    Filter(Option<Regex>),
+    /// Change how stderr is shown, which `Off` only does for the lines read from now on
+    Stderr(StderrMode),
+    /// Render the window again even though nothing has changed
+    Redraw,
+    /// Stop rendering the window, output is still read into it
+    Pause,
+    /// Render the window again, and keep doing so as it changes
+    Resume,
+    Status(oneshot::Sender<Status>),
+    /// Request the command lines waiting to be executed, in the order they'll run
+    Queue(oneshot::Sender<Vec<String>>),
+    /// Request the lines of the latest commands that were started, oldest first
+    History(oneshot::Sender<Vec<String>>),
+    /// Show this many lines of the window from now on
+    Resize(usize),
+    /// Move the visible part of the window this many lines further back into the scrollback
+    ScrollUp(usize),
+    /// Move the visible part of the window this many lines towards the latest output
+    ScrollDown(usize),
+    /// Request the processes of the running command, each followed by its children
+    Processes(oneshot::Sender<Vec<ProcessInfo>>),
+    /// Request a copy of the lines currently in the window
+    Snapshot(oneshot::Sender<Vec<(Stream, String)>>),
+    /// Kill the running command, drop the queue and exit, the sender is notified once the terminal is
+    /// gone
+    Remove(oneshot::Sender<()>),
+}
+
+/// A command line waiting to be executed
+#[derive(Debug)]
+pub struct Job {
+    /// What the user typed, used when displaying the queue
+    pub line: String,
+    pub exec: process::Command,
+    /// Who asked for the command to be run, if the handler wants to know
+    pub requested_by: Option<String>,
+    /// All of the input of the command, its stdin is closed once it's been written
+    pub stdin: Option<Vec<u8>>,
+}
+
+/// Makes a fresh job for every run of a schedule, as a `process::Command` can only be spawned once
+pub struct MakeJob(Box<dyn FnMut() -> Job + Send>);
+
+impl MakeJob {
+    pub fn new(make: impl FnMut() -> Job + Send + 'static) -> Self {
+        MakeJob(Box::new(make))
+    }
+}
+
+impl fmt::Debug for MakeJob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("MakeJob")
+    }
+}
+
+/// A process of the running command
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// How many of its parents are part of the command, 0 for the command itself
+    pub depth: usize,
+    pub command: String,
+}
+
+/// How a command came to an end
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exit {
+    /// Exited by itself with this code
+    Completed(i32),
+    Cancelled,
+    /// Killed for running longer than its timeout
+    TimedOut,
+    /// Ended by this signal, whether it was sent by the terminal or by something else
+    Killed(i32),
+    /// Couldn't be started or waited on, or the followed file couldn't be read, which has already
+    /// been shown
+    Failed,
+}
+
+impl From<ExitStatus> for Exit {
+    fn from(status: ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => Exit::Completed(code),
+            (None, Some(signal)) => Exit::Killed(signal),
+            (None, None) => Exit::Failed,
+        }
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exit::Completed(code) => write!(f, "exited with {}", code),
+            Exit::Cancelled => f.write_str("cancelled"),
+            Exit::TimedOut => f.write_str("timed out"),
+            Exit::Killed(signal) => write!(f, "killed by {}", signal_name(*signal)),
+            Exit::Failed => f.write_str("failed"),
+        }
+    }
+}
+
+/// The name of a signal such as `SIGKILL`, or its number if it's not one of the common ones
+pub fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        _ => return format!("signal {}", signal),
+    };
+
+    name.to_string()
+}
+
+/// A snapshot of what the terminal is currently doing
+#[derive(Debug, Clone, Copy)]
+pub struct Status {
+    pub running: bool,
+    pub queued: usize,
+}
+
+/// Reasons for why input couldn't be handed to the running command
+#[derive(Debug)]
+pub enum InputError {
+    NotRunning,
+    /// The command was given all of its input when it was started
+    Closed,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::NotRunning => f.write_str("no command is currently running"),
+            InputError::Closed => f.write_str("the command was given all of its input already"),
+            InputError::Io(err) => write!(f, "failed to write to stdin: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for InputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InputError::NotRunning | InputError::Closed => None,
+            InputError::Io(err) => Some(err),
+        }
+    }
+}
+
+/// How a `Runner` should behave
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub height: usize,
+    pub timeout: Option<Duration>,
+    /// The shortest time between frames, used while there's little output
+    pub cooldown: Duration,
+    /// The longest time between frames, used while output is flooding in
+    pub max_cooldown: Duration,
+    /// Lines to keep in total, values lower than `height` means no scrollback
+    pub scrollback: usize,
+    /// Remove the terminal once it hasn't run anything for this long
+    pub idle_timeout: Option<Duration>,
+    /// How often to show that a quiet command is still running, never if missing
+    pub heartbeat: Option<Duration>,
+    /// Collapse identical lines in a row into one line with a count
+    pub dedup: bool,
+    /// Wrap lines longer than this many characters onto the following lines
+    pub width: Option<usize>,
+    /// Draw output onto a screen of `height` rows that commands can move the cursor around on,
+    /// rather than appending it line by line
+    pub tui: bool,
+    /// Prefix each line with how long the command had been running when it was printed
+    pub timestamps: bool,
+    /// Show the lines of a command's output that look binary as a hexdump
+    pub hex: bool,
+    /// What the output is decoded from, anything that isn't valid in it is replaced
+    pub encoding: &'static Encoding,
+    pub limits: ResourceLimits,
+}
+
+/// Caps on the resources of each command and the processes it starts, `None` meaning no cap
+///
+/// These are rlimits set right before the command is executed, so they're best-effort: a command
+/// may lower them further but never raise them, and root isn't held to the process limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResourceLimits {
+    /// Seconds of CPU time, after which the command is killed by `SIGXCPU`
+    pub cpu: Option<u64>,
+    /// Bytes of memory each process may map, allocating more fails
+    pub memory: Option<u64>,
+    /// Processes the user the bot runs as may have in total, which stops fork bombs
+    pub processes: Option<u64>,
+}
+
+impl ResourceLimits {
+    /// Set the limits of the current process, meant to be called between fork and exec
+    fn apply(&self) -> std::io::Result<()> {
+        let limits = [
+            (libc::RLIMIT_CPU, self.cpu),
+            (libc::RLIMIT_AS, self.memory),
+            (libc::RLIMIT_NPROC, self.processes),
+        ];
+
+        for &(resource, limit) in limits.iter() {
+            if let Some(limit) = limit {
+                let limit = libc::rlimit {
+                    rlim_cur: limit as libc::rlim_t,
+                    rlim_max: limit as libc::rlim_t,
+                };
+
+                // SAFETY: setrlimit only reads the struct it's given
+                if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// Runner represents the controlled execution of a command where the commands output is being
@@ -32,174 +374,1397 @@ pub enum Command {
 pub struct Runner<H: Handler> {
     window: Window,
     timer: Timer,
+    throttle: Throttle,
+    timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    limits: ResourceLimits,
+    // when the terminal was last seen running a command
+    active_at: Instant,
+    heartbeat: Option<Duration>,
+    // when the running command last printed a line, or was started
+    heard_at: Instant,
+    // heartbeats since then
+    beats: usize,
+    filter: Option<Regex>,
+    paused: bool,
+    hex: bool,
+    encoding: &'static Encoding,
+
+    // when the latest line that hasn't been rendered yet was read
+    unrendered_since: Option<Instant>,
 
     running: Option<Process>,
-    pending: VecDeque<process::Command>,
+    // takes the place of a running command, but only ends once cancelled
+    following: Option<Follow>,
+    pending: VecDeque<Job>,
+    schedule: Option<Schedule>,
+    // the terminal the lines that are read are mirrored to
+    tee: Option<channel::Sender<Command>>,
+    // kept across `clear`, as that only concerns the output
+    history: VecDeque<String>,
 
-    should_be_removed: bool,
+    exit_listeners: Vec<oneshot::Sender<()>>,
+    // set once the terminal has shut down, after which the handlers aren't told anything more
+    closed: bool,
 
-    handler: H,
+    handler: Handlers<H>,
     command_buffer: channel::Receiver<Command>,
 }
 
+/// The handler the runner was created with, followed by the ones added to it
+///
+/// Every event goes to each of them in that order, so each sees what the ones before it added to
+/// the window.
+struct Handlers<H> {
+    first: H,
+    rest: Vec<Box<dyn Handler + Send>>,
+}
+
+#[async_trait]
+impl<H: Handler + Send> Handler for Handlers<H> {
+    async fn update(&mut self, window: &mut Window) {
+        self.first.update(window).await;
+        for handler in &mut self.rest {
+            handler.update(window).await;
+        }
+    }
+
+    async fn on_line(&mut self, stream: Stream, line: &str) {
+        self.first.on_line(stream, line).await;
+        for handler in &mut self.rest {
+            handler.on_line(stream, line).await;
+        }
+    }
+
+    async fn on_command_start(&mut self, window: &mut Window, job: &Job) {
+        self.first.on_command_start(window, job).await;
+        for handler in &mut self.rest {
+            handler.on_command_start(window, job).await;
+        }
+    }
+
+    async fn on_command_exit(&mut self, window: &mut Window, exit: Exit) {
+        self.first.on_command_exit(window, exit).await;
+        for handler in &mut self.rest {
+            handler.on_command_exit(window, exit).await;
+        }
+    }
+
+    async fn on_terminal_exit(&mut self, window: &mut Window) {
+        self.first.on_terminal_exit(window).await;
+        for handler in &mut self.rest {
+            handler.on_terminal_exit(window).await;
+        }
+    }
+
+    async fn on_clear(&mut self, window: &mut Window) {
+        self.first.on_clear(window).await;
+        for handler in &mut self.rest {
+            handler.on_clear(window).await;
+        }
+    }
+
+    async fn on_queue_change(&mut self, window: &mut Window, queued: usize) {
+        self.first.on_queue_change(window, queued).await;
+        for handler in &mut self.rest {
+            handler.on_queue_change(window, queued).await;
+        }
+    }
+
+    async fn on_heartbeat(&mut self, window: &mut Window, beats: usize) {
+        self.first.on_heartbeat(window, beats).await;
+        for handler in &mut self.rest {
+            handler.on_heartbeat(window, beats).await;
+        }
+    }
+}
+
 /// The state of an OS process
 struct Process {
-    reader: tokio::io::Lines<BufReader<tokio::process::ChildStdout>>,
+    stdout: Output<process::ChildStdout>,
+    stderr: Output<process::ChildStderr>,
+    // gone if the input was given along with the command
+    stdin: Option<process::ChildStdin>,
     process: process::Child,
+    // the command is the leader of a process group of its own, which its children are part of
+    group: libc::pid_t,
+    started: Instant,
+}
+
+impl Drop for Process {
+    /// Kill whatever is left of the process group, so that nothing the command started outlives it
+    fn drop(&mut self) {
+        // SAFETY: killpg only sends a signal, the group is gone already if it fails
+        unsafe {
+            libc::killpg(self.group, libc::SIGKILL);
+        }
+    }
+}
+
+impl Process {
+    /// Read whichever of stdout and stderr has a line ready first.
+    ///
+    /// When one of the streams end we keep draining the other. Returns `None` once both are done.
+    async fn next_line(&mut self) -> Option<(Stream, String)> {
+        loop {
+            tokio::select! {
+                line = self.stdout.next_line(), if !self.stdout.done => match line {
+                    Some(line) => return Some((Stream::Stdout, line)),
+                    None => self.stdout.done = true,
+                },
+                line = self.stderr.next_line(), if !self.stderr.done => match line {
+                    Some(line) => return Some((Stream::Stderr, line)),
+                    None => self.stderr.done = true,
+                },
+                else => return None,
+            }
+        }
+    }
+
+    /// Take a line that has already been read from either stream, without waiting for one
+    fn buffered_line(&mut self) -> Option<(Stream, String)> {
+        let stdout = self.stdout.buffered_line();
+        let line = stdout.map(|line| (Stream::Stdout, line));
+        line.or_else(|| Some((Stream::Stderr, self.stderr.buffered_line()?)))
+    }
+}
+
+/// One of the output streams of a process, read line by line
+///
+/// Commands are free to print whatever bytes they like, so anything that isn't valid in the
+/// encoding is replaced rather than treated as an error.
+struct Output<R> {
+    reader: BufReader<R>,
+    // bytes of the line currently being read, kept here so that a cancelled read loses nothing
+    partial: Vec<u8>,
+    done: bool,
+    // binary looking lines are shown as a hexdump
+    hex: bool,
+    encoding: &'static Encoding,
+}
+
+impl<R: AsyncRead + Unpin> Output<R> {
+    fn new(stream: R, hex: bool, encoding: &'static Encoding) -> Self {
+        Output {
+            reader: BufReader::new(stream),
+            partial: Vec::new(),
+            done: false,
+            hex,
+            encoding,
+        }
+    }
+
+    /// Read the next line without its line ending. Returns `None` once the stream has ended.
+    async fn next_line(&mut self) -> Option<String> {
+        match self.reader.read_until(b'\n', &mut self.partial).await {
+            Ok(_) if self.partial.is_empty() => None,
+            Ok(_) => Some(self.decode_partial()),
+            Err(e) => {
+                // the stream is unusable, so report it once and treat it as finished
+                self.done = true;
+                self.partial.clear();
+                Some(format!(" <failed to read output: {}> ", e))
+            }
+        }
+    }
+
+    /// Take the next line if all of it is in the buffer already, which takes no waiting
+    fn buffered_line(&mut self) -> Option<String> {
+        let buffer = self.reader.buffer();
+        let end = buffer.iter().position(|&byte| byte == b'\n')? + 1;
+
+        self.partial.extend_from_slice(&buffer[..end]);
+        Pin::new(&mut self.reader).consume(end);
+        Some(self.decode_partial())
+    }
+
+    fn decode_partial(&mut self) -> String {
+        let bytes = std::mem::take(&mut self.partial);
+        if self.hex {
+            decode_line_or_hex(bytes, self.encoding)
+        } else {
+            decode_line(bytes, self.encoding)
+        }
+    }
+}
+
+/// A job that's run again every interval
+struct Schedule {
+    job: MakeJob,
+    interval: Duration,
+    next_at: Instant,
+}
+
+/// A file being read from its end, like `tail -f`
+struct Follow {
+    path: PathBuf,
+    reader: BufReader<fs::File>,
+    // bytes of a line that hasn't been fully written yet
+    partial: Vec<u8>,
+    encoding: &'static Encoding,
+}
+
+impl Follow {
+    /// Open the file at `path`, only the lines appended to it from now on are read
+    async fn open(path: PathBuf, encoding: &'static Encoding) -> std::io::Result<Self> {
+        let mut file = fs::File::open(&path).await?;
+        file.seek(SeekFrom::End(0)).await?;
+
+        Ok(Follow {
+            path,
+            reader: BufReader::new(file),
+            partial: Vec::new(),
+            encoding,
+        })
+    }
+
+    /// Wait for the next complete line to be appended to the file
+    ///
+    /// A file that shrinks has been truncated, such as by log rotation, and is read from the start.
+    async fn next_line(&mut self) -> std::io::Result<String> {
+        loop {
+            self.reader.read_until(b'\n', &mut self.partial).await?;
+
+            if self.partial.last() == Some(&b'\n') {
+                return Ok(decode_line(
+                    std::mem::take(&mut self.partial),
+                    self.encoding,
+                ));
+            }
+
+            // everything written so far has been read
+            tokio::time::sleep(FOLLOW_POLL).await;
+
+            let read = self.reader.get_mut().seek(SeekFrom::Current(0)).await?;
+            if fs::metadata(&self.path).await?.len() < read {
+                self.reader = BufReader::new(fs::File::open(&self.path).await?);
+                self.partial.clear();
+            }
+        }
+    }
+}
+
+/// Turn the bytes of a line into text without the line ending, replacing what isn't valid in
+/// `encoding`
+///
+/// Only encodings compatible with ASCII are used, so a `\n` byte is always a line ending.
+fn decode_line(mut bytes: Vec<u8>, encoding: &'static Encoding) -> String {
+    trim_line_ending(&mut bytes);
+    let (line, _) = encoding.decode_without_bom_handling(&bytes);
+    line.into_owned()
+}
+
+/// Like `decode_line`, except that a line that's mostly unprintable is shown as a hexdump of its
+/// bytes such as `<hex: 7f 45 4c 46>`
+fn decode_line_or_hex(mut bytes: Vec<u8>, encoding: &'static Encoding) -> String {
+    trim_line_ending(&mut bytes);
+    let (line, _) = encoding.decode_without_bom_handling(&bytes);
+
+    // escapes are kept for the colors, and invalid bytes count as unprintable
+    let unprintable = line
+        .chars()
+        .filter(|&c| {
+            c == char::REPLACEMENT_CHARACTER || (c.is_control() && c != '\t' && c != '\x1b')
+        })
+        .count();
+
+    let total = line.chars().count();
+    if total == 0 || (unprintable as f64) < total as f64 * BINARY_RATIO {
+        return line.into_owned();
+    }
+
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>();
+    format!("<hex: {}>", hex.join(" "))
+}
+
+fn trim_line_ending(bytes: &mut Vec<u8>) {
+    if bytes.last() == Some(&b'\n') {
+        bytes.pop();
+        if bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+    }
 }
 
 impl AddAssign<String> for Window {
+    /// Appends a line of stdout
     fn add_assign(&mut self, line: String) {
+        *self += (Stream::Stdout, line);
+    }
+}
+
+impl AddAssign<(Stream, String)> for Window {
+    /// Appends a line that was read just now
+    fn add_assign(&mut self, (stream, line): (Stream, String)) {
+        *self += Line::new(stream, line);
+    }
+}
+
+impl AddAssign<Line> for Window {
+    fn add_assign(
+        &mut self,
+        Line {
+            text,
+            stream,
+            elapsed,
+        }: Line,
+    ) {
         debug_assert!(
-            !line.contains('\n'),
+            !text.contains('\n'),
             "line characters aren't allowed to be appended to Window"
         );
 
-        self.buffer.push_back(line.into_boxed_str());
-        self.shrink_to_limit();
+        if let Some(screen) = &mut self.screen {
+            screen.draw(&text);
+            screen.draw("\n");
+            self.buffer = screen
+                .rows()
+                .map(|row| Line::new(Stream::Stdout, row))
+                .collect();
+            return;
+        }
+
+        let line = apply_carriage_returns(String::from(text));
+
+        if self.dedup {
+            if let Some((last_stream, last, count)) = &mut self.repeated {
+                if *last_stream == stream && **last == *line && !self.buffer.is_empty() {
+                    *count += 1;
+                    // the count goes on the end of the line, so it takes up no more lines
+                    let tail = match self.width {
+                        Some(width) => wrap(last, width).pop().unwrap_or_default(),
+                        None => last.to_string(),
+                    };
+                    let collapsed = format!("{} (x{})", tail, count).into_boxed_str();
+                    self.buffer.back_mut().unwrap().text = collapsed;
+                    return;
+                }
+            }
+
+            self.repeated = Some((stream, line.clone().into_boxed_str(), 1));
+        }
+
+        let rows = match self.width {
+            Some(width) => wrap(&line, width),
+            None => vec![line],
+        };
+
+        for (i, row) in rows.into_iter().enumerate() {
+            self.buffer.push_back(Line {
+                text: row.into_boxed_str(),
+                stream,
+                // the time goes in front of the line, not each row it's wrapped onto
+                elapsed: elapsed.filter(|_| i == 0),
+            });
+
+            // keep showing the same lines while scrolled up
+            if self.offset != 0 {
+                self.offset += 1;
+            }
+
+            self.shrink_to_limit();
+        }
     }
 }
 
 impl<H: Handler + Send + 'static> Runner<H> {
-    pub fn new(handler: H, height: usize, command_buffer: channel::Receiver<Command>) -> Runner<H> {
+    pub fn new(
+        handler: H,
+        config: Config,
+        command_buffer: channel::Receiver<Command>,
+    ) -> Runner<H> {
+        let cooldown = config.cooldown.max(MIN_COOLDOWN);
+        let max_cooldown = config.max_cooldown.max(cooldown);
+
+        let mut window = Window::new(config.height, config.scrollback);
+        window.dedup = config.dedup;
+        window.width = config.width;
+        window.timestamps = config.timestamps;
+        if config.tui {
+            let width = config.width.unwrap_or(DEFAULT_SCREEN_WIDTH);
+            window.screen = Some(Screen::new(config.height, width));
+        }
+
         Runner {
-            window: Window::new(height),
-            timer: Timer {
-                // we set it up so that the first update will happen after one second
-                last: SystemTime::now() - Duration::from_secs(COOLDOWN + 1),
-            },
+            window,
+            timer: Timer::new(cooldown, max_cooldown),
+            throttle: Throttle::new(),
+            timeout: config.timeout,
+            idle_timeout: config.idle_timeout,
+            limits: config.limits,
+            active_at: Instant::now(),
+            heartbeat: config.heartbeat.map(|interval| interval.max(MIN_COOLDOWN)),
+            heard_at: Instant::now(),
+            beats: 0,
+            filter: None,
+            paused: false,
+            hex: config.hex,
+            encoding: config.encoding,
+            unrendered_since: None,
             running: None,
-            should_be_removed: false,
+            following: None,
+            exit_listeners: Vec::new(),
+            closed: false,
             pending: VecDeque::new(),
-            handler,
+            schedule: None,
+            tee: None,
+            history: VecDeque::new(),
+            handler: Handlers {
+                first: handler,
+                rest: Vec::new(),
+            },
             command_buffer,
         }
     }
 
-    pub fn init(handler: H, height: usize) -> (Runner<H>, channel::Sender<Command>) {
+    pub fn init(handler: H, config: Config) -> (Runner<H>, channel::Sender<Command>) {
         let (sender, reciever) = channel::channel(10);
-        let runner = Runner::new(handler, height, reciever);
+        let runner = Runner::new(handler, config, reciever);
         (runner, sender)
     }
 
-    /// Waits for commands forever
+    /// Send every event to `handler` as well, after the handlers that were there before it
+    pub fn add_handler(&mut self, handler: impl Handler + Send + 'static) {
+        self.handler.rest.push(Box::new(handler));
+    }
+
+    /// Fill the window with output from a previous session
+    pub fn restore(&mut self, lines: Vec<(Stream, String)>) {
+        for (stream, text) in lines {
+            self.window += Line::new(stream, text);
+        }
+    }
+
+    /// Waits for commands until the terminal is removed
     pub async fn listen(mut self) {
-        loop {
+        let _open = metrics::open_terminal();
+
+        while !self.closed {
+            if self.is_busy() {
+                self.active_at = Instant::now();
+            }
+
+            let flush_at = self.flush_deadline();
+            let heartbeat_at = self.heartbeat_deadline();
+            let read_at = self.throttle.resume_at();
+            let scheduled_at = self.schedule_deadline();
+            let idle_at = self.idle_deadline();
+
             tokio::select! {
                 msg = self.command_buffer.recv() => {
                     match msg {
-                        Some(Command::Run(cmd)) => self.pending.push_front(cmd),
-                        Some(Command::Remove) => self.should_be_removed = true,
+                        Some(Command::Run(job)) => {
+                            self.pending.push_front(*job);
+                            self.queue_changed().await;
+                        }
+                        Some(Command::Schedule { interval, job }) => {
+                            self.schedule = Some(Schedule {
+                                job,
+                                interval,
+                                next_at: Instant::now(),
+                            });
+                        }
+                        Some(Command::Unschedule) => self.schedule = None,
+                        Some(Command::Follow(path)) => self.follow(path).await,
+                        Some(Command::Tee(tee)) => self.tee = tee,
+                        Some(Command::Feed(line)) => self.push_line((Stream::Stdout, line)).await,
+                        Some(Command::Input(text, reply)) => {
+                            let result = self.write_input(text).await;
+                            reply.send(result).ok();
+                        }
+                        Some(Command::Cancel) => self.cancel().await,
+                        Some(Command::CancelAll) => self.cancel_all().await,
+                        Some(Command::Clear) => self.clear().await,
+                        Some(Command::Filter(filter)) => self.filter = filter,
+                        Some(Command::Stderr(mode)) => {
+                            self.window.stderr = mode;
+                            self.render().await;
+                        }
+                        Some(Command::Redraw) => self.render().await,
+                        Some(Command::Pause) => self.paused = true,
+                        Some(Command::Resume) => {
+                            self.paused = false;
+                            self.render().await;
+                        }
+                        Some(Command::Resize(height)) => {
+                            self.window.resize(height);
+                            self.render().await;
+                        }
+                        Some(Command::ScrollUp(lines)) => {
+                            self.window.scroll_up(lines);
+                            self.render().await;
+                        }
+                        Some(Command::ScrollDown(lines)) => {
+                            self.window.scroll_down(lines);
+                            self.render().await;
+                        }
+                        Some(Command::Status(reply)) => {
+                            reply.send(self.status()).ok();
+                        }
+                        Some(Command::Queue(reply)) => {
+                            let lines = self.pending.iter().rev().map(|job| job.line.clone());
+                            reply.send(lines.collect()).ok();
+                        }
+                        Some(Command::History(reply)) => {
+                            reply.send(self.history.iter().cloned().collect()).ok();
+                        }
+                        Some(Command::Processes(reply)) => {
+                            let group = self.running.as_ref().map(|running| running.group);
+                            reply.send(group.map(list_group).unwrap_or_default()).ok();
+                        }
+                        Some(Command::Snapshot(reply)) => {
+                            let lines = self
+                                .window
+                                .buffer
+                                .iter()
+                                .map(|line| {
+                                    (line.stream, line.shown(self.window.timestamps).into_owned())
+                                });
+                            reply.send(lines.collect()).ok();
+                        }
+                        Some(Command::Remove(ack)) => {
+                            self.exit_listeners.push(ack);
+                            self.shut_down().await;
+                        }
                         None => {
                             // oh huh, our only way to communicate with the terminal has been
                             // killed. Probably for the best to just remove everything so we
                             // don't end up with a zombie processes.
-                            self.handler.on_terminal_exit(&mut self.window).await;
-                            self.clean_command().await;
-                            return;
+                            self.shut_down().await;
                         },
                     }
                 }
 
-                // whenever we're not recieving a signal
-                _ = async{} => {
-                    match self.running.as_mut() {
-
-                        // we're currently running a command
-                        Some(runtime) => {
-                            // so lets read another line of stdout
-                            if let Some(line) = runtime.reader.next_line().await.unwrap() {
-                                self.window += line.clone();
-                                self.update_if_should().await;
-                            } else {
-                                // there are no more lines, must mean the command is finished
-                                self.handler.on_command_exit(&mut self.window).await;
-                                self.clean_command().await;
+                // we're currently running a command, so lets read another line of output unless
+                // we run out of time while waiting for it
+                line = next_line(self.running.as_mut(), self.timeout), if self.running.is_some() && !self.throttle.is_saturated() => {
+                    match line {
+                        Ok(Some(line)) => {
+                            self.take_line(line).await;
+                            self.drain_lines().await;
+                        }
+                        Ok(None) => {
+                            // there are no more lines, must mean the command is finished
+                            if let Some(exit) = self.wait_command().await {
+                                self.handler.on_command_exit(&mut self.window, exit).await;
                             }
-                        },
-
-                        // we're not running a command
-                        None => {
-                            match self.pending.pop_back() {
-                                Some(cmd) => self.run(cmd),
-                                None if self.should_be_removed => {
-                                    self.handler.on_terminal_exit(&mut self.window).await;
-                                    return;
-                                }
-
-                                // we have nothing to do. So let's wait a bit to not waste cycles
-                                None => tokio::time::sleep(Duration::from_millis(200)).await,
+                        }
+                        Err(_elapsed) => {
+                            if let Some(exit) = self.clean_command(Exit::TimedOut).await {
+                                self.handler.on_command_exit(&mut self.window, exit).await;
                             }
                         }
                     }
                 }
+
+                // a line was appended to the file we're following
+                line = next_followed(self.following.as_mut()), if self.following.is_some() && !self.throttle.is_saturated() => {
+                    match line {
+                        Ok(line) => {
+                            self.throttle.record_line();
+                            self.forward(&line);
+                            self.push_line((Stream::Stdout, line)).await;
+                        }
+                        Err(e) => {
+                            let follow = self.following.take().unwrap();
+                            self.window += (
+                                Stream::Stderr,
+                                format!(" <failed to read {}: {}> ", follow.path.display(), e),
+                            );
+                            self.handler.on_command_exit(&mut self.window, Exit::Failed).await;
+                        }
+                    }
+                }
+
+                // the command is printing faster than we're willing to read, which leaves it
+                // blocked on a full pipe until the period is over
+                _ = tokio::time::sleep_until(read_at), if self.is_busy() && self.throttle.is_saturated() => {}
+
+                // the output has gone quiet with lines that are still waiting on the cooldown
+                _ = tokio::time::sleep_until(flush_at), if self.unrendered_since.is_some() => {
+                    self.timer.reset();
+                    self.unrendered_since = None;
+                    self.render().await;
+                }
+
+                // the scheduled job is due to run again
+                _ = tokio::time::sleep_until(scheduled_at), if self.schedule.is_some() => {
+                    self.run_scheduled().await;
+                }
+
+                // the command has been quiet for long enough that it might look like it's stuck
+                _ = tokio::time::sleep_until(heartbeat_at), if self.running.is_some() && self.heartbeat.is_some() && !self.paused => {
+                    self.beats += 1;
+                    self.handler.on_heartbeat(&mut self.window, self.beats).await;
+                }
+
+                // we're not running a command, but there's one waiting to be run
+                _ = async {}, if !self.is_busy() && !self.pending.is_empty() => self.start_next().await,
+
+                // nothing has been run for as long as the terminal may be left idle
+                _ = tokio::time::sleep_until(idle_at), if !self.is_busy() && self.pending.is_empty() && self.may_idle_out() => {
+                    self.window += String::from(" <closed after being idle> ");
+                    self.shut_down().await;
+                }
             }
         }
     }
 
-    /// Start execution and monitoring of a shell command
-    fn run(&mut self, exec: process::Command) {
+    /// Start the job that has been waiting the longest
+    async fn start_next(&mut self) {
+        let job = match self.pending.pop_back() {
+            Some(job) => job,
+            None => return,
+        };
+
+        self.handler
+            .on_queue_change(&mut self.window, self.pending.len())
+            .await;
+        self.window.dropped = 0;
+        self.echo(&job.line);
+        self.handler.on_command_start(&mut self.window, &job).await;
+        self.remember(job.line);
+
+        if let Err(e) = self.run(job.exec, job.stdin) {
+            self.window += format!("error: failed to start command: {}", e);
+            self.handler
+                .on_command_exit(&mut self.window, Exit::Failed)
+                .await;
+        }
+    }
+
+    /// Start execution and monitoring of a shell command, writing `input` to its stdin if given
+    fn run(&mut self, exec: process::Command, input: Option<Vec<u8>>) -> std::io::Result<()> {
         assert!(self.running.is_none());
-        let mut child = self.spawn(exec);
+        self.window.scroll_to_bottom();
+        let mut child = self.spawn(exec)?;
+
+        let stdout = child.stdout.take().ok_or_else(|| unavailable("stdout"))?;
+        let stderr = child.stderr.take().ok_or_else(|| unavailable("stderr"))?;
+        let stdin = child.stdin.take().ok_or_else(|| unavailable("stdin"))?;
+        let stdin = match input {
+            Some(input) => {
+                tokio::spawn(feed(stdin, input));
+                None
+            }
+            None => Some(stdin),
+        };
+        let group = child.id().ok_or_else(|| unavailable("pid"))? as libc::pid_t;
 
-        let stdout = child.stdout.take().expect("stdout unavailable");
-        let reader = BufReader::new(stdout).lines();
+        self.heard_at = Instant::now();
+        self.beats = 0;
 
         self.running = Some(Process {
             process: child,
-            reader,
+            group,
+            stdin,
+            stdout: Output::new(stdout, self.hex, self.encoding),
+            stderr: Output::new(stderr, self.hex, self.encoding),
+            started: Instant::now(),
         });
-    }
+        metrics::command_started();
 
-    /// Spawn a shell command
-    fn spawn(&mut self, mut exec: process::Command) -> process::Child {
-        exec.stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .unwrap()
+        Ok(())
     }
 
-    /// checks the timer and updates if needed
-    async fn update_if_should(&mut self) {
-        let should_update_frame = self.timer.check_and_update(Duration::from_secs(COOLDOWN));
-        if should_update_frame {
-            self.handler.update(&mut self.window).await;
+    /// Start showing the lines appended to the file at `path`, once nothing else is running
+    async fn follow(&mut self, path: PathBuf) {
+        if self.is_busy() || !self.pending.is_empty() {
+            self.window += (
+                Stream::Stderr,
+                String::from("error: can't follow a file while commands are running"),
+            );
+            self.render().await;
+            return;
         }
-    }
 
-    /// sets self.running to `None` and makes sure the running process is dead or dies
-    async fn clean_command(&mut self) -> Option<Process> {
-        let mut cmd = self.running.take()?;
+        self.window.scroll_to_bottom();
 
-        if cmd.process.id().is_some() {
-            // seems to still be running
-            cmd.process.kill().await.ok();
+        match Follow::open(path, self.encoding).await {
+            Ok(follow) => {
+                self.window += format!(" <following {}> ", follow.path.display());
+                self.following = Some(follow);
+                self.render().await;
+            }
+            Err(e) => {
+                self.window += format!("error: failed to follow file: {}", e);
+                self.handler
+                    .on_command_exit(&mut self.window, Exit::Failed)
+                    .await;
+            }
         }
+    }
 
-        Some(cmd)
+    /// Take in a line of the running command's output
+    async fn take_line(&mut self, line: (Stream, String)) {
+        self.throttle.record_line();
+        self.heard_at = Instant::now();
+        self.beats = 0;
+        self.forward(&line.1);
+        self.push_line(line).await;
     }
-}
 
-/// Lines of output that adhere to the height limit
-pub struct Window {
-    pub buffer: VecDeque<Box<str>>,
-    pub height: usize,
-}
+    /// Take the lines the running command has already printed in one go, rather than going
+    /// through the `select!` for each of them
+    ///
+    /// It's bounded by `MAX_DRAINED` and the throttle, so that commands don't wait on a flood.
+    async fn drain_lines(&mut self) {
+        for _ in 0..MAX_DRAINED {
+            if self.throttle.is_saturated() {
+                return;
+            }
+
+            match self.running.as_mut().and_then(Process::buffered_line) {
+                Some(line) => self.take_line(line).await,
+                None => return,
+            }
+        }
+    }
+
+    /// Mirror a line that was read to the terminal that's being teed into
+    ///
+    /// Lines are dropped while its commands are backed up rather than waited on, as it may well be
+    /// waiting on this terminal in turn, and the output here shouldn't stall on a slow terminal.
+    fn forward(&mut self, line: &str) {
+        if let Some(tee) = &self.tee {
+            match tee.try_send(Command::Feed(line.to_string())) {
+                Ok(()) | Err(TrySendError::Full(_)) => {}
+                // the other terminal has been removed
+                Err(TrySendError::Closed(_)) => self.tee = None,
+            }
+        }
+    }
+
+    /// Add a line of output to the window, unless it's filtered out
+    async fn push_line(&mut self, (stream, text): (Stream, String)) {
+        self.handler.on_line(stream, &text).await;
+
+        if stream == Stream::Stderr && self.window.stderr == StderrMode::Off {
+            return;
+        }
+
+        if !self.matches_filter(&text) {
+            return;
+        }
+
+        // followed files have no command to time their lines by
+        let elapsed = self
+            .running
+            .as_ref()
+            .map(|running| running.started.elapsed());
+
+        self.timer.record_line();
+        self.window += Line {
+            text: text.into_boxed_str(),
+            stream,
+            elapsed,
+        };
+        self.unrendered_since = Some(Instant::now());
+        self.update_if_should().await;
+    }
+
+    /// Whether a command is running or a file is being followed
+    fn is_busy(&self) -> bool {
+        self.running.is_some() || self.following.is_some()
+    }
+
+    /// Show the command line ahead of its output, the way a terminal would
+    ///
+    /// Lines after the first one of a multi-line command are shown as continuations.
+    fn echo(&mut self, line: &str) {
+        for (i, part) in line.lines().enumerate() {
+            let prefix = if i == 0 { "$ " } else { "> " };
+            self.window += format!("{}{}", prefix, part);
+        }
+    }
+
+    /// Add a command line to the history, forgetting the oldest one if it's full
+    fn remember(&mut self, line: String) {
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        self.history.push_back(line);
+    }
+
+    fn may_idle_out(&self) -> bool {
+        // a scheduled job keeps the terminal in use between its runs
+        self.idle_timeout.is_some() && self.schedule.is_none()
+    }
+
+    /// When the terminal closes for being idle, unless it runs something before then
+    fn idle_deadline(&self) -> tokio::time::Instant {
+        let limit = self.idle_timeout.unwrap_or_default();
+
+        tokio::time::Instant::from_std(self.active_at + limit)
+    }
+
+    fn matches_filter(&self, line: &str) -> bool {
+        match &self.filter {
+            Some(filter) => filter.is_match(line),
+            None => true,
+        }
+    }
+
+    /// Stop everything and tell the handlers that the terminal is gone, only the first time
+    ///
+    /// The command buffer is closed before the handlers are told, so that its senders can tell
+    /// that the runner is gone by then.
+    async fn shut_down(&mut self) {
+        if self.closed {
+            return;
+        }
+        self.closed = true;
+
+        self.cancel().await;
+        self.command_buffer.close();
+        self.handler.on_terminal_exit(&mut self.window).await;
+        self.acknowledge_exit();
+    }
+
+    fn acknowledge_exit(&mut self) {
+        for ack in self.exit_listeners.drain(..) {
+            ack.send(()).ok();
+        }
+    }
+
+    fn status(&self) -> Status {
+        Status {
+            running: self.is_busy(),
+            queued: self.pending.len(),
+        }
+    }
+
+    /// Empty the window and the queue, a running command keeps writing to the window
+    async fn clear(&mut self) {
+        self.window.buffer.clear();
+        self.window.offset = 0;
+        self.window.dropped = 0;
+        self.window.repeated = None;
+        if let Some(screen) = &mut self.window.screen {
+            screen.reset();
+        }
+
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            self.handler.on_queue_change(&mut self.window, 0).await;
+        }
+
+        if self.is_busy() {
+            self.render().await;
+        } else {
+            self.handler.on_clear(&mut self.window).await;
+        }
+    }
+
+    /// Kill the running command, or stop following a file, leaving the queue to start next
+    async fn cancel(&mut self) {
+        let unfollowed = self.following.take().is_some();
+        let exit = match self.clean_command(Exit::Cancelled).await {
+            None if unfollowed => Some(Exit::Cancelled),
+            exit => exit,
+        };
+        if let Some(exit) = exit {
+            self.handler.on_command_exit(&mut self.window, exit).await;
+        }
+    }
+
+    /// Drop everything that's queued up or scheduled before cancelling, as the next queued command
+    /// would otherwise start as soon as the running one has been killed
+    async fn cancel_all(&mut self) {
+        self.schedule = None;
+
+        if !self.pending.is_empty() {
+            self.pending.clear();
+            self.handler.on_queue_change(&mut self.window, 0).await;
+        }
+
+        self.cancel().await;
+    }
+
+    /// Hand a line of input to the stdin of the running command
+    async fn write_input(&mut self, mut text: String) -> Result<(), InputError> {
+        let runtime = self.running.as_mut().ok_or(InputError::NotRunning)?;
+        let stdin = runtime.stdin.as_mut().ok_or(InputError::Closed)?;
+        text.push('\n');
+
+        stdin
+            .write_all(text.as_bytes())
+            .await
+            .map_err(InputError::Io)?;
+
+        stdin.flush().await.map_err(InputError::Io)
+    }
+
+    /// Spawn a shell command
+    fn spawn(&mut self, mut exec: process::Command) -> std::io::Result<process::Child> {
+        let limits = self.limits;
+        // SAFETY: the closure only calls setsid and setrlimit, which are safe to call after a fork
+        unsafe {
+            exec.pre_exec(move || {
+                // a session of its own puts the command and everything it starts in a process
+                // group that can be killed as a whole
+                if libc::setsid() == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                limits.apply()
+            });
+        }
+
+        exec.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // don't leave the command running if the bot goes down before it's cleaned up
+            .kill_on_drop(true)
+            .spawn()
+    }
+
+    /// Let the handler know the queue grew, the frame is only redrawn once the cooldown allows
+    async fn queue_changed(&mut self) {
+        self.handler
+            .on_queue_change(&mut self.window, self.pending.len())
+            .await;
+
+        if self.is_busy() {
+            self.unrendered_since = Some(Instant::now());
+            self.update_if_should().await;
+        }
+    }
+
+    /// Hand the window to the handler, unless rendering is paused
+    ///
+    /// The exit of a command and clearing the terminal are still shown while paused.
+    async fn render(&mut self) {
+        if !self.paused {
+            self.handler.update(&mut self.window).await;
+        }
+    }
+
+    /// checks the timer and updates if needed
+    async fn update_if_should(&mut self) {
+        let should_update_frame = self.timer.check_and_update();
+        if should_update_frame {
+            self.unrendered_since = None;
+            self.render().await;
+        }
+    }
+
+    /// When output that's been waiting on the cooldown should be rendered regardless
+    ///
+    /// That's once the output has been quiet for a while, but never more often than the delay.
+    fn flush_deadline(&self) -> tokio::time::Instant {
+        let now = Instant::now();
+        let quiet_since = self.unrendered_since.unwrap_or(now);
+        let last_frame = self.timer.last.unwrap_or(quiet_since);
+
+        tokio::time::Instant::from_std(quiet_since.max(last_frame) + FORCED_FLUSH_DELAY)
+    }
+
+    /// When the scheduled job should run next
+    fn schedule_deadline(&self) -> tokio::time::Instant {
+        let next_at = match &self.schedule {
+            Some(schedule) => schedule.next_at,
+            None => Instant::now(),
+        };
+
+        tokio::time::Instant::from_std(next_at)
+    }
+
+    /// Queue up the scheduled job, unless anything else is still running or waiting to
+    async fn run_scheduled(&mut self) {
+        let idle = !self.is_busy() && self.pending.is_empty();
+
+        let schedule = match &mut self.schedule {
+            Some(schedule) => schedule,
+            None => return,
+        };
+        schedule.next_at = Instant::now() + schedule.interval;
+
+        if idle {
+            let job = (schedule.job.0)();
+            self.pending.push_front(job);
+            self.queue_changed().await;
+        }
+    }
+
+    /// When to show that the running command is still alive, if it stays quiet until then
+    fn heartbeat_deadline(&self) -> tokio::time::Instant {
+        let interval = self.heartbeat.unwrap_or_default();
+        let quiet_for = interval * (self.beats as u32 + 1);
+
+        tokio::time::Instant::from_std(self.heard_at + quiet_for)
+    }
+
+    /// Wait for the command to exit now that its output has ended, killing it if it lingers
+    async fn wait_command(&mut self) -> Option<Exit> {
+        let mut cmd = self.running.take()?;
+        // the exit is rendered with everything that was read
+        self.unrendered_since = None;
+        metrics::command_finished(cmd.started.elapsed());
+
+        let exit = match tokio::time::timeout(EXIT_GRACE, cmd.process.wait()).await {
+            Ok(Ok(status)) => Exit::from(status),
+            Ok(Err(_)) => Exit::Failed,
+            Err(_elapsed) => {
+                cmd.process.kill().await.ok();
+                Exit::Killed(libc::SIGKILL)
+            }
+        };
+
+        Some(exit)
+    }
+
+    /// sets self.running to `None` and makes sure the running process is dead or dies
+    ///
+    /// The command ended for `reason` if it had to be killed, otherwise it's reported the way it
+    /// exited by itself.
+    async fn clean_command(&mut self, reason: Exit) -> Option<Exit> {
+        let mut cmd = self.running.take()?;
+        self.unrendered_since = None;
+        metrics::command_finished(cmd.started.elapsed());
+
+        match cmd.process.try_wait() {
+            Ok(Some(status)) => Some(Exit::from(status)),
+            // seems to still be running
+            _ => {
+                cmd.process.kill().await.ok();
+                Some(reason)
+            }
+        }
+    }
+}
+
+/// Write all of `input` to the stdin of a command and close it
+///
+/// It's done apart from the runner, as the command may not read any more of it until its output
+/// has been read. Commands may exit without reading all of it, so failing to write isn't an error.
+async fn feed(mut stdin: process::ChildStdin, input: Vec<u8>) {
+    stdin.write_all(&input).await.ok();
+}
+
+/// List the processes in the process group, each followed by its children
+///
+/// They're read from `/proc`, without it the list is empty.
+fn list_group(group: libc::pid_t) -> Vec<ProcessInfo> {
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut members = Vec::new();
+
+    for entry in entries.flatten() {
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+
+        // the process may have exited since it was listed
+        let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+
+        let (name, parent, member_of) = match parse_stat(&stat) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+
+        if member_of != group as u32 {
+            continue;
+        }
+
+        let cmdline = std::fs::read(entry.path().join("cmdline")).unwrap_or_default();
+        let command = String::from_utf8_lossy(&cmdline).replace('\0', " ");
+        let command = match command.trim() {
+            // zombies and kernel threads have no command line
+            "" => format!("[{}]", name),
+            command => command.to_string(),
+        };
+
+        members.push((pid, parent, command));
+    }
+
+    tree_order(members)
+}
+
+/// Get the name, parent and process group out of the contents of `/proc/<pid>/stat`
+fn parse_stat(stat: &str) -> Option<(&str, u32, u32)> {
+    // the name is in parentheses, and may itself contain spaces and parentheses
+    let name = &stat[stat.find('(')? + 1..stat.rfind(')')?];
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace().skip(1);
+
+    let parent = fields.next()?.parse().ok()?;
+    let group = fields.next()?.parse().ok()?;
+    Some((name, parent, group))
+}
+
+/// Order `(pid, parent, command)` so that each process is followed by its children
+fn tree_order(mut members: Vec<(u32, u32, String)>) -> Vec<ProcessInfo> {
+    fn add_children(
+        members: &[(u32, u32, String)],
+        parent: u32,
+        depth: usize,
+        tree: &mut Vec<ProcessInfo>,
+    ) {
+        for (pid, _, command) in members.iter().filter(|member| member.1 == parent) {
+            tree.push(ProcessInfo {
+                pid: *pid,
+                depth,
+                command: command.clone(),
+            });
+            add_children(members, *pid, depth + 1, tree);
+        }
+    }
+
+    members.sort_by_key(|member| member.0);
+
+    let mut tree = Vec::with_capacity(members.len());
+    for (pid, parent, command) in &members {
+        // the ones whose parent isn't part of the group are at the top
+        if members.iter().all(|member| member.0 != *parent) {
+            tree.push(ProcessInfo {
+                pid: *pid,
+                depth: 0,
+                command: command.clone(),
+            });
+            add_children(&members, *pid, 1, &mut tree);
+        }
+    }
+    tree
+}
+
+/// The error for when a piped stream of a spawned command is missing
+fn unavailable(stream: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::BrokenPipe,
+        format!("{} unavailable", stream),
+    )
+}
+
+/// Apply carriage returns the way a terminal would, by letting the text following each `\r`
+/// overwrite the start of the line.
+///
+/// This keeps progress bars from tools such as curl and pip to a single line of output.
+fn apply_carriage_returns(line: String) -> String {
+    if !line.contains('\r') {
+        return line;
+    }
+
+    let mut rendered: Vec<char> = Vec::with_capacity(line.len());
+    for segment in line.split('\r') {
+        for (column, c) in segment.chars().enumerate() {
+            match rendered.get_mut(column) {
+                Some(existing) => *existing = c,
+                None => rendered.push(c),
+            }
+        }
+    }
+
+    rendered.into_iter().collect()
+}
+
+/// Split `line` into rows of at most `width` characters, with tabs expanded to spaces
+fn wrap(line: &str, width: usize) -> Vec<String> {
+    let mut columns: Vec<char> = Vec::with_capacity(line.len());
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = TAB_WIDTH - columns.len() % TAB_WIDTH;
+            columns.resize(columns.len() + spaces, ' ');
+        } else {
+            columns.push(c);
+        }
+    }
+
+    if columns.is_empty() {
+        return vec![String::new()];
+    }
+
+    columns
+        .chunks(width.max(1))
+        .map(|row| row.iter().collect())
+        .collect()
+}
+
+/// Read the next line of output from `process`, giving up once it has run for longer than `timeout`
+async fn next_line(
+    process: Option<&mut Process>,
+    timeout: Option<Duration>,
+) -> Result<Option<(Stream, String)>, tokio::time::error::Elapsed> {
+    let process = match process {
+        Some(process) => process,
+        None => return std::future::pending().await,
+    };
+
+    match timeout {
+        Some(limit) => {
+            let remaining = limit
+                .checked_sub(process.started.elapsed())
+                .unwrap_or_default();
+            tokio::time::timeout(remaining, process.next_line()).await
+        }
+        None => Ok(process.next_line().await),
+    }
+}
+
+/// Read the next line appended to the followed file, if there is one
+async fn next_followed(follow: Option<&mut Follow>) -> std::io::Result<String> {
+    match follow {
+        Some(follow) => follow.next_line().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// A line of the window, along with where it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub text: Box<str>,
+    pub stream: Stream,
+    /// How long the command had been running when the line was read, missing for the lines that
+    /// weren't printed by a command
+    pub elapsed: Option<Duration>,
+}
+
+impl Line {
+    /// A line that wasn't printed by a command
+    pub fn new(stream: Stream, text: impl Into<Box<str>>) -> Self {
+        Line {
+            text: text.into(),
+            stream,
+            elapsed: None,
+        }
+    }
+
+    /// The text as it's shown, after how long the command had been running if `timestamps` is set
+    pub fn shown(&self, timestamps: bool) -> Cow<'_, str> {
+        match self.elapsed {
+            Some(elapsed) if timestamps => {
+                Cow::Owned(format!("[{:.2}s] {}", elapsed.as_secs_f64(), self.text))
+            }
+            _ => Cow::Borrowed(&self.text),
+        }
+    }
+}
+
+/// Lines of output that adhere to the scrollback limit, of which `height` lines are visible
+pub struct Window {
+    pub buffer: VecDeque<Line>,
+    pub height: usize,
+    pub scrollback: usize,
+    /// How many lines the visible part is scrolled up from the latest output
+    pub offset: usize,
+    /// Collapse identical lines in a row into `line (xN)`
+    pub dedup: bool,
+    /// Wrap longer lines onto as many rows as they need, each taking up a line of the window
+    pub width: Option<usize>,
+    /// How many of the oldest lines were dropped to stay within the scrollback, since the window was
+    /// cleared or the latest command started
+    pub dropped: usize,
+    /// Output is drawn onto this instead of being appended, with the buffer holding its rows
+    pub screen: Option<Screen>,
+    pub stderr: StderrMode,
+    /// Show how long the command had been running in front of each line it printed
+    pub timestamps: bool,
+    // the latest line as it was read, and how many times in a row, while deduplicating
+    repeated: Option<(Stream, Box<str>, usize)>,
+}
 
 impl Window {
-    pub fn new(height: usize) -> Self {
+    pub fn new(height: usize, scrollback: usize) -> Self {
+        let scrollback = scrollback.max(height);
+
         Window {
             buffer: VecDeque::with_capacity(height),
             height,
+            scrollback,
+            offset: 0,
+            dedup: false,
+            width: None,
+            dropped: 0,
+            screen: None,
+            stderr: StderrMode::Inline,
+            timestamps: false,
+            repeated: None,
+        }
+    }
+
+    /// The lines that should be rendered
+    pub fn visible(&self) -> impl Iterator<Item = &Line> + Clone {
+        let (start, end) = self.visible_range();
+        self.buffer.range(start..end)
+    }
+
+    /// How many lines come before the visible ones, whether they were dropped or are scrolled out
+    /// of view
+    pub fn hidden(&self) -> usize {
+        self.dropped + self.visible_range().0
+    }
+
+    fn visible_range(&self) -> (usize, usize) {
+        let end = self.buffer.len() - self.offset.min(self.buffer.len());
+        (end.saturating_sub(self.height), end)
+    }
+
+    /// Every line that's kept, including the ones scrolled out of view
+    pub fn lines(&self) -> impl Iterator<Item = &Line> + Clone {
+        self.buffer.iter()
+    }
+
+    /// Show `height` lines, dropping the oldest ones if there's no longer room for them
+    ///
+    /// A scrollback that only held the visible lines keeps doing so, otherwise it's only ever
+    /// grown to fit the new height.
+    pub fn resize(&mut self, height: usize) {
+        self.scrollback = if self.scrollback == self.height {
+            height
+        } else {
+            self.scrollback.max(height)
+        };
+        self.height = height;
+
+        if let Some(screen) = &mut self.screen {
+            screen.resize(height);
+            self.buffer = screen
+                .rows()
+                .map(|row| Line::new(Stream::Stdout, row))
+                .collect();
         }
+
+        while self.shrink_to_limit().is_some() {}
+        self.offset = self.offset.min(self.max_offset());
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.offset = (self.offset + lines).min(self.max_offset());
     }
 
-    fn over_height_limit(&self) -> bool {
-        self.buffer.len() > self.height
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.offset = self.offset.saturating_sub(lines);
     }
 
-    fn shrink_to_limit(&mut self) -> Option<Box<str>> {
-        if self.over_height_limit() {
+    pub fn scroll_to_bottom(&mut self) {
+        self.offset = 0;
+    }
+
+    /// Scrolling any further would show less than a full window
+    fn max_offset(&self) -> usize {
+        self.buffer.len().saturating_sub(self.height)
+    }
+
+    fn over_scrollback_limit(&self) -> bool {
+        self.buffer.len() > self.scrollback
+    }
+
+    fn shrink_to_limit(&mut self) -> Option<Line> {
+        if self.over_scrollback_limit() {
+            self.offset = self.offset.min(self.max_offset() - 1);
+            self.dropped += 1;
             self.buffer.pop_front()
         } else {
             None
@@ -207,23 +1772,1052 @@ impl Window {
     }
 }
 
+/// A grid of characters that output is drawn onto the way a terminal would, so that programs which
+/// move the cursor around to redraw themselves in place show what they currently look like
+///
+/// Only the escape sequences that move the cursor or erase are understood, any other escape
+/// sequence is dropped.
+#[derive(Debug, Clone)]
+pub struct Screen {
+    rows: Vec<Vec<char>>,
+    width: usize,
+    // where the next character is drawn
+    row: usize,
+    column: usize,
+    // what has followed an escape character, while that escape sequence is unfinished
+    escape: Option<String>,
+}
+
+impl Screen {
+    pub fn new(height: usize, width: usize) -> Self {
+        let width = width.max(1);
+
+        Screen {
+            rows: vec![vec![' '; width]; height.max(1)],
+            width,
+            row: 0,
+            column: 0,
+            escape: None,
+        }
+    }
+
+    /// The rows as they're currently drawn, without trailing spaces
+    pub fn rows(&self) -> impl Iterator<Item = Box<str>> + '_ {
+        self.rows
+            .iter()
+            .map(|row| row.iter().collect::<String>().trim_end().into())
+    }
+
+    /// Erase everything and move the cursor back to the top left
+    pub fn reset(&mut self) {
+        self.erase(0, self.rows.len() * self.width);
+        self.row = 0;
+        self.column = 0;
+        self.escape = None;
+    }
+
+    /// Keep `height` rows, adding or removing them at the bottom
+    pub fn resize(&mut self, height: usize) {
+        self.rows.resize(height.max(1), vec![' '; self.width]);
+        self.row = self.row.min(self.rows.len() - 1);
+    }
+
+    /// Draw `text` from the cursor onwards
+    pub fn draw(&mut self, text: &str) {
+        for c in text.chars() {
+            match self.escape.take() {
+                Some(mut sequence) => {
+                    sequence.push(c);
+                    if !self.apply_escape(&sequence) {
+                        self.escape = Some(sequence);
+                    }
+                }
+                None => self.put(c),
+            }
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        match c {
+            // output has `\n` translated to `\r\n` on its way to a terminal
+            '\n' => {
+                self.line_feed();
+                self.column = 0;
+            }
+            '\r' => self.column = 0,
+            '\x08' => self.column = self.column.saturating_sub(1),
+            '\t' => self.column = ((self.column / TAB_WIDTH + 1) * TAB_WIDTH).min(self.width - 1),
+            '\x1b' => self.escape = Some(String::new()),
+            c if c.is_control() => {}
+            c => {
+                if self.column >= self.width {
+                    self.line_feed();
+                    self.column = 0;
+                }
+                self.rows[self.row][self.column] = c;
+                self.column += 1;
+            }
+        }
+    }
+
+    /// Move down a row, scrolling everything up once the cursor is on the bottom one
+    fn line_feed(&mut self) {
+        if self.row + 1 < self.rows.len() {
+            self.row += 1;
+        } else {
+            self.rows.remove(0);
+            self.rows.push(vec![' '; self.width]);
+        }
+    }
+
+    /// Act on `sequence`, what followed an escape character so far. Returns `false` if it isn't
+    /// finished yet.
+    fn apply_escape(&mut self, sequence: &str) -> bool {
+        match sequence.as_bytes() {
+            [b'['] => false,
+            [b'[', .., last] => match last {
+                0x40..=0x7e => {
+                    let parameters = &sequence[1..sequence.len() - 1];
+                    self.apply_csi(parameters, *last as char);
+                    true
+                }
+                // still reading parameters
+                0x20..=0x3f => false,
+                // not an escape sequence after all
+                _ => true,
+            },
+            // operating system commands, such as setting the title, end with BEL or ST
+            [b']', ..] => sequence.ends_with('\x07') || sequence.ends_with("\x1b\\"),
+            [b'c'] => {
+                self.reset();
+                true
+            }
+            _ => true,
+        }
+    }
+
+    fn apply_csi(&mut self, parameters: &str, command: char) {
+        let numbers: Vec<usize> = parameters
+            .split(';')
+            .map(|number| number.parse().unwrap_or(0))
+            .collect();
+        // most parameters count from 1, with 0 or nothing meaning the same as 1
+        let count = |i: usize| numbers.get(i).copied().unwrap_or(0).max(1);
+        let (last_row, last_column) = (self.rows.len() - 1, self.width - 1);
+
+        match command {
+            'H' | 'f' => {
+                self.row = (count(0) - 1).min(last_row);
+                self.column = (count(1) - 1).min(last_column);
+            }
+            'A' => self.row = self.row.saturating_sub(count(0)),
+            'B' => self.row = (self.row + count(0)).min(last_row),
+            'C' => self.column = (self.column + count(0)).min(last_column),
+            'D' => self.column = self.column.min(last_column).saturating_sub(count(0)),
+            'G' => self.column = (count(0) - 1).min(last_column),
+            'd' => self.row = (count(0) - 1).min(last_row),
+            'J' | 'K' => {
+                let (start, end) = if command == 'J' {
+                    (0, self.rows.len() * self.width)
+                } else {
+                    (self.row * self.width, (self.row + 1) * self.width)
+                };
+                let cursor = self.row * self.width + self.column.min(last_column);
+
+                match numbers[0] {
+                    0 => self.erase(cursor, end),
+                    1 => self.erase(start, cursor + 1),
+                    _ => self.erase(start, end),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Erase the cells from `start` up to `end`, counting left to right and then top to bottom
+    fn erase(&mut self, start: usize, end: usize) {
+        for cell in start..end {
+            self.rows[cell / self.width][cell % self.width] = ' ';
+        }
+    }
+}
+
 /// We use `Timer` to control whether a discord message should be edited to produce a new frame or not.
 ///
 /// Serenity does have internal rate-limiting. However; we don't want to queue up hundreds of
 /// message edit commands for serenity to go through.
+///
+/// The cooldown is picked between `min` and `max` depending on how many lines were read since the
+/// previous frame, so that quiet commands update quickly while floods of output back off.
 struct Timer {
-    last: SystemTime,
+    last: Option<Instant>,
+    lines: usize,
+    cooldown: Duration,
+    min: Duration,
+    max: Duration,
 }
 
 impl Timer {
-    fn check_and_update(&mut self, cooldown: Duration) -> bool {
-        let now = SystemTime::now();
+    fn new(min: Duration, max: Duration) -> Self {
+        Timer {
+            // nothing has been rendered yet so the first update will happen right away
+            last: None,
+            lines: 0,
+            cooldown: min,
+            min,
+            max,
+        }
+    }
+
+    fn record_line(&mut self) {
+        self.lines += 1;
+    }
 
-        let past_limit = now.duration_since(self.last).unwrap() > cooldown;
+    fn check_and_update(&mut self) -> bool {
+        let now = Instant::now();
+
+        let past_limit = match self.last {
+            Some(last) => now.duration_since(last) > self.cooldown,
+            None => true,
+        };
         if past_limit {
-            self.last = now;
+            self.frame_produced(now);
         }
 
         past_limit
     }
+
+    /// Restart the cooldown as a frame was just produced
+    fn reset(&mut self) {
+        self.frame_produced(Instant::now());
+    }
+
+    fn frame_produced(&mut self, now: Instant) {
+        if let Some(last) = self.last {
+            let elapsed = now.duration_since(last).as_secs_f64().max(0.001);
+            let rate = self.lines as f64 / elapsed;
+            self.cooldown = self.min + (self.max - self.min).mul_f64((rate / FLOOD_RATE).min(1.0));
+        }
+
+        self.last = Some(now);
+        self.lines = 0;
+    }
+}
+
+/// Counts the lines read in the current period, so that floods of output can't keep a core busy
+///
+/// Only the latest lines of a flood are ever seen, so there's little point in reading them any
+/// faster than this.
+struct Throttle {
+    since: Instant,
+    lines: usize,
+}
+
+impl Throttle {
+    fn new() -> Self {
+        Throttle {
+            since: Instant::now(),
+            lines: 0,
+        }
+    }
+
+    fn record_line(&mut self) {
+        let now = Instant::now();
+        if now.duration_since(self.since) >= THROTTLE_PERIOD {
+            self.since = now;
+            self.lines = 0;
+        }
+
+        self.lines += 1;
+    }
+
+    /// No more lines should be read until `resume_at`
+    fn is_saturated(&self) -> bool {
+        self.lines >= LINE_BURST && self.since.elapsed() < THROTTLE_PERIOD
+    }
+
+    fn resume_at(&self) -> tokio::time::Instant {
+        tokio::time::Instant::from_std(self.since + THROTTLE_PERIOD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(height: usize, scrollback: usize, lines: &[&str]) -> Window {
+        let mut window = Window::new(height, scrollback);
+        for line in lines {
+            window += line.to_string();
+        }
+        window
+    }
+
+    fn visible(window: &Window) -> Vec<&str> {
+        window.visible().map(|line| &*line.text).collect()
+    }
+
+    #[test]
+    fn keeps_at_most_the_scrollback() {
+        let window = window(3, 5, &["1", "2", "3", "4", "5", "6", "7"]);
+
+        assert_eq!(window.buffer.len(), 5);
+        assert_eq!(visible(&window), ["5", "6", "7"]);
+    }
+
+    #[test]
+    fn counts_the_lines_before_the_visible_ones() {
+        let mut window = window(3, 5, &["1", "2", "3", "4", "5", "6", "7"]);
+
+        assert_eq!(window.dropped, 2);
+        assert_eq!(window.hidden(), 4);
+
+        window.scroll_up(2);
+        assert_eq!(window.hidden(), 2);
+
+        let window = self::window(3, 5, &["1", "2"]);
+        assert_eq!(window.hidden(), 0);
+    }
+
+    #[test]
+    fn collapses_repeated_lines() {
+        let mut window = Window::new(3, 3);
+        window.dedup = true;
+
+        for line in &["a", "b", "b", "b", "a"] {
+            window += line.to_string();
+        }
+        window += (Stream::Stderr, String::from("a"));
+        window += (Stream::Stderr, String::from("a"));
+
+        assert_eq!(visible(&window), ["b (x3)", "a", "a (x2)"]);
+    }
+
+    #[test]
+    fn resizing_keeps_the_latest_lines() {
+        let mut window = window(3, 3, &["1", "2", "3"]);
+
+        window.resize(2);
+        assert_eq!(visible(&window), ["2", "3"]);
+
+        window.resize(4);
+        window += String::from("4");
+        window += String::from("5");
+        assert_eq!(visible(&window), ["2", "3", "4", "5"]);
+
+        // lines scrolled out of view are kept
+        let mut window = self::window(2, 5, &["1", "2", "3", "4"]);
+        window.resize(1);
+        assert_eq!(window.buffer.len(), 4);
+        assert_eq!(visible(&window), ["4"]);
+    }
+
+    #[test]
+    fn wraps_long_lines_within_the_height() {
+        let mut window = Window::new(3, 3);
+        window.width = Some(4);
+
+        window += String::from("abcdefghij");
+        window += String::from("a\tb");
+
+        assert_eq!(visible(&window), ["a   ", "    ", "b"]);
+    }
+
+    #[test]
+    fn draws_onto_the_screen_in_tui_mode() {
+        let mut window = Window::new(3, 3);
+        window.screen = Some(Screen::new(3, 10));
+
+        window += String::from("load: 1");
+        window += String::from("cpu: 50%");
+        assert_eq!(visible(&window), ["load: 1", "cpu: 50%", ""]);
+
+        // redraw in place from the top left, as programs such as top do
+        window += String::from("\x1b[H\x1b[2Jload: 2");
+        window += String::from("\x1b[1;31mcpu\x1b[0m: 7%");
+        assert_eq!(visible(&window), ["load: 2", "cpu: 7%", ""]);
+
+        window += String::from("\x1b[2;6H\x1b[K99%\x1b[A\rLOAD\x1b]0;title\x07");
+        assert_eq!(visible(&window), ["LOAD: 2", "cpu: 99%", ""]);
+
+        // ending a line on the bottom row scrolls everything up
+        window += String::from("\x1b[3;1Hlast");
+        assert_eq!(visible(&window), ["cpu: 99%", "last", ""]);
+        window += String::from("more");
+        assert_eq!(visible(&window), ["last", "more", ""]);
+
+        window.resize(2);
+        assert_eq!(visible(&window), ["last", "more"]);
+    }
+
+    #[test]
+    fn keeps_repeated_lines_without_dedup() {
+        let window = window(3, 3, &["b", "b", "b"]);
+
+        assert_eq!(visible(&window), ["b", "b", "b"]);
+    }
+
+    #[test]
+    fn collapses_repeated_lines_printed_at_different_times() {
+        let mut window = Window::new(3, 3);
+        window.dedup = true;
+        window.timestamps = true;
+        for secs in 1..=3 {
+            window += Line {
+                text: "tick".into(),
+                stream: Stream::Stdout,
+                elapsed: Some(Duration::from_secs(secs)),
+            };
+        }
+
+        let shown = |window: &Window| {
+            let timestamps = window.timestamps;
+            let lines = window
+                .visible()
+                .map(|line| line.shown(timestamps).into_owned());
+            lines.collect::<Vec<_>>()
+        };
+        assert_eq!(shown(&window), ["[1.00s] tick (x3)"]);
+
+        window.timestamps = false;
+        assert_eq!(shown(&window), ["tick (x3)"]);
+    }
+
+    #[test]
+    fn scrollback_is_at_least_the_height() {
+        let window = window(3, 1, &["1", "2", "3", "4"]);
+
+        assert_eq!(visible(&window), ["2", "3", "4"]);
+    }
+
+    #[test]
+    fn scrolls_within_the_scrollback() {
+        let mut window = window(3, 5, &["1", "2", "3", "4", "5", "6", "7"]);
+
+        window.scroll_up(1);
+        assert_eq!(visible(&window), ["4", "5", "6"]);
+
+        // never further than a full window from the top
+        window.scroll_up(10);
+        assert_eq!(visible(&window), ["3", "4", "5"]);
+
+        window.scroll_down(1);
+        assert_eq!(visible(&window), ["4", "5", "6"]);
+
+        window.scroll_down(10);
+        assert_eq!(visible(&window), ["5", "6", "7"]);
+    }
+
+    #[test]
+    fn new_lines_keep_the_view_while_scrolled_up() {
+        let mut window = window(3, 10, &["1", "2", "3", "4", "5"]);
+        window.scroll_up(2);
+
+        window += String::from("6");
+        assert_eq!(visible(&window), ["1", "2", "3"]);
+
+        window.scroll_to_bottom();
+        assert_eq!(visible(&window), ["4", "5", "6"]);
+    }
+
+    #[test]
+    fn dropped_lines_move_the_view_down() {
+        let mut window = window(3, 5, &["1", "2", "3", "4", "5", "6", "7"]);
+        window.scroll_up(10);
+
+        window += String::from("8");
+        assert_eq!(visible(&window), ["4", "5", "6"]);
+    }
+
+    #[test]
+    fn throttles_floods_of_lines() {
+        let mut throttle = Throttle::new();
+
+        for _ in 0..LINE_BURST - 1 {
+            throttle.record_line();
+        }
+        assert!(!throttle.is_saturated());
+
+        throttle.record_line();
+        assert!(throttle.is_saturated());
+
+        std::thread::sleep(THROTTLE_PERIOD);
+        assert!(!throttle.is_saturated());
+
+        throttle.record_line();
+        assert_eq!(throttle.lines, 1);
+    }
+
+    /// Records which events the runner sent, in order
+    #[derive(Clone, Default)]
+    struct Recorder(std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>);
+
+    impl Recorder {
+        fn events(&self) -> Vec<&'static str> {
+            self.0.lock().unwrap().clone()
+        }
+
+        fn record(&self, event: &'static str) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[async_trait]
+    impl Handler for Recorder {
+        async fn update(&mut self, _window: &mut Window) {}
+
+        async fn on_line(&mut self, _stream: Stream, _line: &str) {}
+
+        async fn on_command_start(&mut self, _window: &mut Window, _job: &Job) {}
+
+        async fn on_command_exit(&mut self, _window: &mut Window, _exit: Exit) {
+            self.record("command exit");
+        }
+
+        async fn on_terminal_exit(&mut self, _window: &mut Window) {
+            self.record("terminal exit");
+        }
+
+        async fn on_clear(&mut self, _window: &mut Window) {}
+
+        async fn on_queue_change(&mut self, _window: &mut Window, _queued: usize) {}
+
+        async fn on_heartbeat(&mut self, _window: &mut Window, _beats: usize) {
+            self.record("heartbeat");
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            height: 5,
+            timeout: None,
+            cooldown: MIN_COOLDOWN,
+            max_cooldown: MIN_COOLDOWN,
+            scrollback: 5,
+            idle_timeout: None,
+            heartbeat: None,
+            dedup: false,
+            width: None,
+            tui: false,
+            timestamps: false,
+            hex: false,
+            encoding: encoding_rs::UTF_8,
+            limits: ResourceLimits::default(),
+        }
+    }
+
+    fn job(line: &str) -> Box<Job> {
+        let mut exec = process::Command::new("sh");
+        exec.arg("-c").arg(line);
+
+        Box::new(Job {
+            line: line.to_string(),
+            exec,
+            requested_by: None,
+            stdin: None,
+        })
+    }
+
+    /// Start a runner with `line` running in it
+    async fn running(
+        line: &str,
+    ) -> (
+        Recorder,
+        channel::Sender<Command>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        running_with(config(), line).await
+    }
+
+    async fn running_with(
+        config: Config,
+        line: &str,
+    ) -> (
+        Recorder,
+        channel::Sender<Command>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let recorder = Recorder::default();
+        let (runner, sender) = Runner::init(recorder.clone(), config);
+        let listener = tokio::spawn(runner.listen());
+
+        sender.send(Command::Run(job(line))).await.unwrap();
+        wait_until("the command never started", || async {
+            let (reply, status) = oneshot::channel();
+            sender.send(Command::Status(reply)).await.unwrap();
+            status.await.unwrap().running
+        })
+        .await;
+
+        (recorder, sender, listener)
+    }
+
+    /// Check `done` every few milliseconds until it is, failing with `what` if that takes too long
+    async fn wait_until<F: std::future::Future<Output = bool>>(
+        what: &str,
+        mut done: impl FnMut() -> F,
+    ) {
+        let limit = tokio::time::Instant::now() + Duration::from_secs(5);
+        while !done().await {
+            assert!(tokio::time::Instant::now() < limit, "{}", what);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn removing_kills_the_command_and_exits() {
+        let (recorder, sender, listener) = running("sleep 30").await;
+
+        let (ack, exited) = oneshot::channel();
+        sender.send(Command::Remove(ack)).await.unwrap();
+
+        let limit = Duration::from_secs(5);
+        tokio::time::timeout(limit, exited).await.unwrap().unwrap();
+        tokio::time::timeout(limit, listener)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(recorder.events(), ["command exit", "terminal exit"]);
+    }
+
+    #[tokio::test]
+    async fn shuts_down_once_when_removed_and_closed() {
+        let (recorder, sender, listener) = running("sleep 30").await;
+
+        let (ack, exited) = oneshot::channel();
+        sender.send(Command::Remove(ack)).await.unwrap();
+        let (again, _) = oneshot::channel();
+        sender.send(Command::Remove(again)).await.ok();
+        drop(sender);
+
+        let limit = Duration::from_secs(5);
+        tokio::time::timeout(limit, exited).await.unwrap().unwrap();
+        tokio::time::timeout(limit, listener)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(recorder.events(), ["command exit", "terminal exit"]);
+    }
+
+    /// Whether the process exists and hasn't exited
+    fn is_alive(pid: u32) -> bool {
+        match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+            Ok(stat) => !stat[stat.rfind(')').unwrap() + 1..].starts_with(" Z"),
+            Err(_) => false,
+        }
+    }
+
+    #[tokio::test]
+    async fn cancelling_kills_everything_the_command_started() {
+        let (_, sender, _) = running("sleep 30 & sleep 30").await;
+
+        let processes = || async {
+            let (reply, response) = oneshot::channel();
+            sender.send(Command::Processes(reply)).await.unwrap();
+            response.await.unwrap()
+        };
+        // until they've exec'd, the forks still go by the command line of sh
+        wait_until("the sleeps never started", || async {
+            let processes = processes().await;
+            processes.len() == 3 && processes[1..].iter().all(|p| p.command == "sleep 30")
+        })
+        .await;
+        let processes = processes().await;
+
+        let depths: Vec<_> = processes.iter().map(|process| process.depth).collect();
+        assert_eq!(depths, [0, 1, 1]);
+
+        sender.send(Command::Cancel).await.unwrap();
+
+        wait_until("the sleeps outlived sh", || async {
+            !processes.iter().any(|process| is_alive(process.pid))
+        })
+        .await;
+    }
+
+    #[test]
+    fn tells_how_a_command_ended_from_its_status() {
+        assert_eq!(Exit::from(ExitStatus::from_raw(0)), Exit::Completed(0));
+        assert_eq!(Exit::from(ExitStatus::from_raw(3 << 8)), Exit::Completed(3));
+        assert_eq!(
+            Exit::from(ExitStatus::from_raw(libc::SIGTERM)),
+            Exit::Killed(libc::SIGTERM)
+        );
+        assert_eq!(Exit::Killed(libc::SIGKILL).to_string(), "killed by SIGKILL");
+        assert_eq!(Exit::Killed(64).to_string(), "killed by signal 64");
+    }
+
+    #[test]
+    fn orders_processes_as_a_tree() {
+        let members = vec![
+            (12, 10, String::from("c")),
+            (10, 1, String::from("a")),
+            (11, 10, String::from("b")),
+            (13, 11, String::from("d")),
+            (20, 5, String::from("e")),
+        ];
+
+        let tree: Vec<_> = tree_order(members)
+            .into_iter()
+            .map(|process| (process.pid, process.depth))
+            .collect();
+        assert_eq!(tree, [(10, 0), (11, 1), (13, 2), (12, 1), (20, 0)]);
+
+        assert_eq!(
+            parse_stat("42 (a (b) c) S 7 40 40 0"),
+            Some(("a (b) c", 7, 40))
+        );
+    }
+
+    #[tokio::test]
+    async fn every_handler_is_told_about_events() {
+        let (first, second) = (Recorder::default(), Recorder::default());
+        let (mut runner, sender) = Runner::init(first.clone(), config());
+        runner.add_handler(second.clone());
+        let listener = tokio::spawn(runner.listen());
+
+        sender.send(Command::Run(job("true"))).await.unwrap();
+        wait_until("`true` never exited", || async {
+            !second.events().is_empty()
+        })
+        .await;
+
+        let (ack, exited) = oneshot::channel();
+        sender.send(Command::Remove(ack)).await.unwrap();
+        exited.await.unwrap();
+        listener.await.unwrap();
+
+        assert_eq!(first.events(), ["command exit", "terminal exit"]);
+        assert_eq!(second.events(), first.events());
+    }
+
+    #[tokio::test]
+    async fn beats_while_the_command_is_quiet() {
+        let config = Config {
+            heartbeat: Some(MIN_COOLDOWN),
+            ..config()
+        };
+        let (recorder, sender, listener) = running_with(config, "sleep 30").await;
+
+        tokio::time::sleep(MIN_COOLDOWN * 2 + Duration::from_millis(500)).await;
+
+        let (ack, exited) = oneshot::channel();
+        sender.send(Command::Remove(ack)).await.unwrap();
+        exited.await.unwrap();
+        listener.await.unwrap();
+
+        assert_eq!(
+            recorder.events(),
+            ["heartbeat", "heartbeat", "command exit", "terminal exit"]
+        );
+    }
+
+    async fn history(sender: &channel::Sender<Command>) -> Vec<String> {
+        let (reply, history) = oneshot::channel();
+        sender.send(Command::History(reply)).await.unwrap();
+        history.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn cancelling_leaves_the_queue_to_run_next() {
+        let (_recorder, sender, _listener) = running("sleep 30").await;
+        sender.send(Command::Run(job("echo queued"))).await.unwrap();
+        sender.send(Command::Cancel).await.unwrap();
+
+        wait_until("the queued command never started", || async {
+            history(&sender).await.len() == 2
+        })
+        .await;
+        assert_eq!(history(&sender).await, ["sleep 30", "echo queued"]);
+    }
+
+    #[tokio::test]
+    async fn cancelling_all_drops_the_queue_before_killing() {
+        let (recorder, sender, _listener) = running("sleep 30").await;
+        sender.send(Command::Run(job("echo queued"))).await.unwrap();
+        sender.send(Command::CancelAll).await.unwrap();
+
+        wait_until("the command was never killed", || async {
+            !recorder.events().is_empty()
+        })
+        .await;
+        // long enough for the queued command to have started if it were still there
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(history(&sender).await, ["sleep 30"]);
+        assert_eq!(recorder.events(), ["command exit"]);
+    }
+
+    #[tokio::test]
+    async fn remembers_started_commands_across_clear() {
+        let (_recorder, sender, _listener) = running("sleep 30").await;
+        sender.send(Command::Cancel).await.unwrap();
+        sender.send(Command::Run(job("true"))).await.unwrap();
+
+        wait_until("`true` never started", || async {
+            history(&sender).await.len() >= 2
+        })
+        .await;
+
+        sender.send(Command::Clear).await.unwrap();
+        assert_eq!(history(&sender).await, ["sleep 30", "true"]);
+    }
+
+    async fn snapshot(sender: &channel::Sender<Command>) -> Vec<String> {
+        let (reply, lines) = oneshot::channel();
+        sender.send(Command::Snapshot(reply)).await.unwrap();
+        lines
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, line)| line)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn gives_commands_the_input_they_came_with() {
+        let recorder = Recorder::default();
+        let (runner, sender) = Runner::init(recorder.clone(), config());
+        tokio::spawn(runner.listen());
+
+        let mut job = job("sort; sleep 30");
+        job.stdin = Some(b"b\nc\na\n".to_vec());
+        sender.send(Command::Run(job)).await.unwrap();
+
+        // sort only prints once its input has been closed
+        wait_until("the sorted lines never showed up", || async {
+            snapshot(&sender).await.len() >= 4
+        })
+        .await;
+
+        let (reply, written) = oneshot::channel();
+        let text = String::from("d");
+        sender.send(Command::Input(text, reply)).await.unwrap();
+        assert!(matches!(written.await.unwrap(), Err(InputError::Closed)));
+
+        sender.send(Command::Cancel).await.unwrap();
+        assert_eq!(snapshot(&sender).await[1..], ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn follows_lines_appended_to_a_file() {
+        let path = std::env::temp_dir().join(format!("termview-follow-{}", std::process::id()));
+        std::fs::write(&path, "before\n").unwrap();
+
+        let recorder = Recorder::default();
+        let (runner, sender) = Runner::init(recorder.clone(), config());
+        tokio::spawn(runner.listen());
+        sender.send(Command::Follow(path.clone())).await.unwrap();
+        wait_until("the file was never followed", || async {
+            !snapshot(&sender).await.is_empty()
+        })
+        .await;
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, b"first\nsecond\nunfinis").unwrap();
+
+        wait_until("the lines never showed up", || async {
+            snapshot(&sender).await.len() >= 3
+        })
+        .await;
+
+        sender.send(Command::Cancel).await.unwrap();
+        let lines = snapshot(&sender).await;
+        std::fs::remove_file(&path).ok();
+
+        let following = format!(" <following {}> ", path.display());
+        assert_eq!(lines, [&following, "first", "second"]);
+        assert_eq!(recorder.events(), ["command exit"]);
+    }
+
+    #[tokio::test]
+    async fn limits_the_resources_of_commands() {
+        let config = Config {
+            limits: ResourceLimits {
+                cpu: Some(10),
+                memory: Some(64 * 1024 * 1024),
+                processes: None,
+            },
+            ..config()
+        };
+
+        let recorder = Recorder::default();
+        let (runner, sender) = Runner::init(recorder.clone(), config);
+        tokio::spawn(runner.listen());
+        sender
+            .send(Command::Run(job("ulimit -t\nulimit -v")))
+            .await
+            .unwrap();
+
+        wait_until("the command never exited", || async {
+            !recorder.events().is_empty()
+        })
+        .await;
+
+        assert_eq!(
+            snapshot(&sender).await,
+            ["$ ulimit -t", "> ulimit -v", "10", "65536"]
+        );
+    }
+
+    #[test]
+    fn decodes_lines_in_the_encoding_of_the_terminal() {
+        assert_eq!(
+            decode_line(b"caf\xe9\r\n".to_vec(), encoding_rs::WINDOWS_1252),
+            "café"
+        );
+        assert_eq!(
+            decode_line(b"\x93\xfa\x96\x7b\n".to_vec(), encoding_rs::SHIFT_JIS),
+            "日本"
+        );
+        assert_eq!(
+            decode_line(b"caf\xe9\n".to_vec(), encoding_rs::UTF_8),
+            "caf\u{fffd}"
+        );
+    }
+
+    #[tokio::test]
+    async fn takes_only_whole_lines_that_are_already_buffered() {
+        let mut output = Output::new(&b"first\nsecond\nthird"[..], false, encoding_rs::UTF_8);
+        assert_eq!(output.buffered_line(), None);
+
+        assert_eq!(output.next_line().await.as_deref(), Some("first"));
+        assert_eq!(output.buffered_line().as_deref(), Some("second"));
+        // not ended by a newline yet, so it's left for the next read
+        assert_eq!(output.buffered_line(), None);
+        assert_eq!(output.next_line().await.as_deref(), Some("third"));
+        assert_eq!(output.next_line().await, None);
+    }
+
+    #[test]
+    fn shows_binary_lines_as_a_hexdump() {
+        assert_eq!(
+            decode_line_or_hex(b"plain\ttext\r\n".to_vec(), encoding_rs::UTF_8),
+            "plain\ttext"
+        );
+        assert_eq!(
+            decode_line_or_hex(b"\x1b[31mred\x1b[0m\n".to_vec(), encoding_rs::UTF_8),
+            "\x1b[31mred\x1b[0m"
+        );
+        assert_eq!(
+            decode_line_or_hex(b"\x7fELF\x02\x01\x01\x00\n".to_vec(), encoding_rs::UTF_8),
+            "<hex: 7f 45 4c 46 02 01 01 00>"
+        );
+        assert_eq!(
+            decode_line_or_hex(b"\xff\xfe".to_vec(), encoding_rs::UTF_8),
+            "<hex: ff fe>"
+        );
+        assert_eq!(decode_line_or_hex(b"\n".to_vec(), encoding_rs::UTF_8), "");
+    }
+
+    #[tokio::test]
+    async fn timestamps_lines_by_when_they_were_printed() {
+        let config = Config {
+            timestamps: true,
+            ..config()
+        };
+        let (_recorder, sender, _listener) = running_with(config, "echo a; sleep 30").await;
+
+        wait_until("nothing was printed", || async {
+            snapshot(&sender).await.len() > 1
+        })
+        .await;
+        let lines = snapshot(&sender).await;
+
+        assert_eq!(lines[0], "$ echo a; sleep 30");
+        assert!(lines[1].starts_with("[0."), "{}", lines[1]);
+        assert!(lines[1].ends_with("s] a"), "{}", lines[1]);
+        sender.send(Command::Cancel).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn runs_scheduled_jobs_until_unscheduled() {
+        let (runner, sender) = Runner::init(Recorder::default(), config());
+        tokio::spawn(runner.listen());
+
+        let job = MakeJob::new(|| *job("echo tick"));
+        let interval = Duration::from_millis(50);
+        sender
+            .send(Command::Schedule { interval, job })
+            .await
+            .unwrap();
+
+        wait_until("the job wasn't run again", || async {
+            history(&sender).await.len() >= 3
+        })
+        .await;
+
+        sender.send(Command::Unschedule).await.unwrap();
+        let runs = history(&sender).await.len();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(history(&sender).await.len() <= runs + 1);
+        assert!(history(&sender)
+            .await
+            .iter()
+            .all(|line| line == "echo tick"));
+    }
+
+    #[tokio::test]
+    async fn mirrors_lines_to_the_terminal_being_teed_into() {
+        let path = std::env::temp_dir().join(format!("termview-tee-{}", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+
+        let (runner, source) = Runner::init(Recorder::default(), config());
+        tokio::spawn(runner.listen());
+        let (runner, target) = Runner::init(Recorder::default(), config());
+        tokio::spawn(runner.listen());
+
+        source
+            .send(Command::Tee(Some(target.clone())))
+            .await
+            .unwrap();
+        // teeing back doesn't send the mirrored lines around in circles
+        target
+            .send(Command::Tee(Some(source.clone())))
+            .await
+            .unwrap();
+        source.send(Command::Follow(path.clone())).await.unwrap();
+        wait_until("the file was never followed", || async {
+            !snapshot(&source).await.is_empty()
+        })
+        .await;
+        std::fs::write(&path, "hi\n").unwrap();
+
+        wait_until("the line wasn't mirrored", || async {
+            !snapshot(&target).await.is_empty()
+        })
+        .await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let lines = snapshot(&source).await;
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(snapshot(&target).await, ["hi"]);
+        assert_eq!(lines, [&format!(" <following {}> ", path.display()), "hi"]);
+    }
+
+    #[tokio::test]
+    async fn drops_mirrored_lines_while_the_teed_terminal_is_backed_up() {
+        let (mut runner, _sender) = Runner::init(Recorder::default(), config());
+        let (target, mut backlog) = channel::channel(1);
+        target.send(Command::Redraw).await.unwrap();
+        runner.tee = Some(target);
+
+        // the target is full, which would block if it were waited on
+        runner.forward("dropped");
+        assert!(runner.tee.is_some());
+        assert!(matches!(backlog.recv().await, Some(Command::Redraw)));
+
+        runner.forward("kept");
+        assert!(matches!(backlog.recv().await, Some(Command::Feed(line)) if line == "kept"));
+
+        drop(backlog);
+        runner.forward("gone");
+        assert!(runner.tee.is_none());
+    }
 }